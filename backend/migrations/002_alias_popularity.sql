@@ -0,0 +1,6 @@
+-- number of times an alias has been looked up by name, used for sorting by popularity
+ALTER TABLE aliases ADD COLUMN uses INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX aliases_author ON aliases (author);
+CREATE INDEX aliases_created_at ON aliases (created_at);
+CREATE INDEX aliases_uses ON aliases (uses);
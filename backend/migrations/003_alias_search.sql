@@ -0,0 +1,19 @@
+-- full text index over alias names and content, the trigram tokenizer allows for substring and
+-- fuzzy matching
+CREATE VIRTUAL TABLE aliases_fts USING fts5(name, content, tokenize = 'trigram');
+
+INSERT INTO aliases_fts (name, content)
+SELECT name, content FROM aliases;
+
+CREATE TRIGGER aliases_fts_insert AFTER INSERT ON aliases BEGIN
+    INSERT INTO aliases_fts (name, content) VALUES (new.name, new.content);
+END;
+
+CREATE TRIGGER aliases_fts_delete AFTER DELETE ON aliases BEGIN
+    DELETE FROM aliases_fts WHERE name = old.name;
+END;
+
+CREATE TRIGGER aliases_fts_update AFTER UPDATE OF name, content ON aliases BEGIN
+    DELETE FROM aliases_fts WHERE name = old.name;
+    INSERT INTO aliases_fts (name, content) VALUES (new.name, new.content);
+END;
@@ -0,0 +1,29 @@
+-- every change to the content or type of an alias, the first revision of an alias has no previous
+-- values
+CREATE TABLE alias_revisions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    alias TEXT NOT NULL,
+    content TEXT NOT NULL,
+    "type" INTEGER NOT NULL,
+    previous_content TEXT,
+    previous_type INTEGER,
+    editor TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_editor_assoc
+        FOREIGN KEY (editor)
+        REFERENCES users (username),
+
+    CONSTRAINT fk_type_assoc
+        FOREIGN KEY ("type")
+        REFERENCES alias_types (id),
+
+    CONSTRAINT fk_previous_type_assoc
+        FOREIGN KEY (previous_type)
+        REFERENCES alias_types (id)
+) STRICT;
+
+CREATE INDEX alias_revisions_alias ON alias_revisions (alias);
+
+INSERT INTO alias_revisions (alias, content, "type", editor, created_at)
+SELECT name, content, "type", author, created_at FROM aliases;
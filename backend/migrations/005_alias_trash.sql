@@ -0,0 +1,5 @@
+-- deleted aliases are kept in the trash until they are purged after the retention period
+ALTER TABLE aliases ADD COLUMN deleted_by TEXT REFERENCES users (username);
+ALTER TABLE aliases ADD COLUMN deleted_at INTEGER; -- unix ts
+
+CREATE INDEX aliases_deleted_at ON aliases (deleted_at);
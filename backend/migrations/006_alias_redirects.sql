@@ -0,0 +1,14 @@
+-- old names of renamed aliases which keep resolving to the new name until they expire
+CREATE TABLE alias_redirects (
+    name TEXT PRIMARY KEY NOT NULL,
+    target TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+    expires_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_created_by_assoc
+        FOREIGN KEY (created_by)
+        REFERENCES users (username)
+) STRICT;
+
+CREATE INDEX alias_redirects_target ON alias_redirects (target);
@@ -0,0 +1,19 @@
+-- additional names which resolve to the same alias as its own name
+CREATE TABLE alias_synonyms (
+    name TEXT PRIMARY KEY NOT NULL,
+    alias TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+
+    CONSTRAINT fk_created_by_assoc
+        FOREIGN KEY (created_by)
+        REFERENCES users (username)
+) STRICT;
+
+CREATE INDEX alias_synonyms_alias ON alias_synonyms (alias);
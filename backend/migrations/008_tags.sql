@@ -0,0 +1,31 @@
+-- free-form tags for browsing aliases, tag names are stored lowercase
+CREATE TABLE tags (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_created_by_assoc
+        FOREIGN KEY (created_by)
+        REFERENCES users (username)
+) STRICT;
+
+CREATE TABLE alias_tags (
+    alias TEXT NOT NULL,
+    tag INTEGER NOT NULL,
+
+    PRIMARY KEY (alias, tag),
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+
+    CONSTRAINT fk_tag_assoc
+        FOREIGN KEY (tag)
+        REFERENCES tags (id)
+        ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX alias_tags_tag ON alias_tags (tag);
@@ -0,0 +1,13 @@
+-- alias types can be managed through the API, they have a label and hints for rendering them
+ALTER TABLE alias_types ADD COLUMN label TEXT NOT NULL DEFAULT '';
+ALTER TABLE alias_types ADD COLUMN display TEXT NOT NULL DEFAULT 'block'; -- 'inline' or 'block'
+ALTER TABLE alias_types ADD COLUMN max_size INTEGER; -- pixels
+ALTER TABLE alias_types ADD COLUMN retired_at INTEGER; -- unix ts
+
+UPDATE alias_types SET label = 'Text', display = 'inline' WHERE name = 'text';
+UPDATE alias_types SET label = 'Image', display = 'block', max_size = 512 WHERE name = 'image';
+UPDATE alias_types SET label = 'Gif', display = 'block', max_size = 512 WHERE name = 'gif';
+UPDATE alias_types SET label = 'Emote', display = 'inline', max_size = 64 WHERE name = 'emote';
+UPDATE alias_types
+SET label = 'Animated Emote', display = 'inline', max_size = 64
+WHERE name = 'animatedEmote';
@@ -0,0 +1,15 @@
+-- media uploaded to be hosted by the server, stored on disk under the hash of the content
+CREATE TABLE media (
+    hash TEXT PRIMARY KEY NOT NULL, -- hex encoded sha256
+    mime TEXT NOT NULL,
+    size INTEGER NOT NULL, -- bytes
+    uploaded_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_uploaded_by_assoc
+        FOREIGN KEY (uploaded_by)
+        REFERENCES users (username)
+) STRICT;
+
+-- the hosted media the content of an alias points at
+ALTER TABLE aliases ADD COLUMN media TEXT REFERENCES media (hash);
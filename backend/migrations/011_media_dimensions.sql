@@ -0,0 +1,4 @@
+-- what was read from the header of the media, missing if it could not be read
+ALTER TABLE media ADD COLUMN width INTEGER;
+ALTER TABLE media ADD COLUMN height INTEGER;
+ALTER TABLE media ADD COLUMN frames INTEGER;
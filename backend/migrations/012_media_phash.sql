@@ -0,0 +1,2 @@
+-- perceptual hash of the first frame used to find similar media, missing if it could not be decoded
+ALTER TABLE media ADD COLUMN phash INTEGER;
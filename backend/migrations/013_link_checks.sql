@@ -0,0 +1,18 @@
+-- the latest check of the url in the content of an alias
+CREATE TABLE link_checks (
+    alias TEXT PRIMARY KEY NOT NULL,
+    url TEXT NOT NULL, -- the content of the alias when it was checked
+    status INTEGER, -- http status, missing if the request failed
+    content_type TEXT,
+    error TEXT, -- why the request failed
+    failures INTEGER NOT NULL, -- failed checks of the url in a row
+    checked_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX link_checks_failures ON link_checks (failures);
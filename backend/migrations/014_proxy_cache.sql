@@ -0,0 +1,12 @@
+-- external content fetched by the proxy, stored on disk in a file named after the hash of the url
+CREATE TABLE proxy_cache (
+    url TEXT PRIMARY KEY NOT NULL,
+    file TEXT NOT NULL, -- hex encoded sha256 of the url
+    mime TEXT NOT NULL,
+    etag TEXT NOT NULL, -- hex encoded sha256 of the content
+    size INTEGER NOT NULL, -- bytes
+    fetched_at INTEGER NOT NULL, -- unix ts
+    used_at INTEGER NOT NULL -- unix ts
+) STRICT;
+
+CREATE INDEX proxy_cache_used_at ON proxy_cache (used_at);
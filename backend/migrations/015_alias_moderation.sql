@@ -0,0 +1,22 @@
+-- submissions of aliases which need to be approved by a moderator before everyone can see them,
+-- aliases without a row here were published without moderation
+CREATE TABLE alias_moderation (
+    alias TEXT PRIMARY KEY NOT NULL,
+    status TEXT NOT NULL CHECK (status IN ('pending', 'approved', 'rejected')),
+    submitted_at INTEGER NOT NULL, -- unix ts
+    moderator TEXT,
+    reason TEXT,
+    moderated_at INTEGER, -- unix ts
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+
+    CONSTRAINT fk_moderator_assoc
+        FOREIGN KEY (moderator)
+        REFERENCES users (username)
+) STRICT;
+
+CREATE INDEX alias_moderation_status ON alias_moderation (status);
@@ -0,0 +1,32 @@
+-- aliases which were reported by enough users are hidden until a moderator handles the reports
+ALTER TABLE aliases ADD COLUMN hidden_at INTEGER; -- unix ts
+
+-- reports of offensive or broken aliases for moderators
+CREATE TABLE alias_reports (
+    id INTEGER PRIMARY KEY NOT NULL,
+    alias TEXT NOT NULL,
+    reporter TEXT NOT NULL,
+    category TEXT NOT NULL,
+    text TEXT,
+    created_at INTEGER NOT NULL, -- unix ts
+    status TEXT NOT NULL CHECK (status IN ('open', 'resolved', 'dismissed')),
+    handled_by TEXT,
+    handled_at INTEGER, -- unix ts
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+
+    CONSTRAINT fk_reporter_assoc
+        FOREIGN KEY (reporter)
+        REFERENCES users (username),
+
+    CONSTRAINT fk_handled_by_assoc
+        FOREIGN KEY (handled_by)
+        REFERENCES users (username)
+) STRICT;
+
+-- every user can only have one open report per alias
+CREATE UNIQUE INDEX alias_reports_open ON alias_reports (alias, reporter) WHERE status = 'open';
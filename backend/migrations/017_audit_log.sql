@@ -0,0 +1,15 @@
+-- every change made through the api, along with what the changed thing looked like before and after
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY NOT NULL,
+    actor TEXT NOT NULL, -- username of who made the change
+    action TEXT NOT NULL,
+    target TEXT NOT NULL, -- name of what was changed
+    before TEXT, -- json, missing for things that were created
+    after TEXT, -- json, missing for things that were deleted
+    request_id TEXT,
+    created_at INTEGER NOT NULL -- unix ts
+) STRICT;
+
+CREATE INDEX audit_log_actor ON audit_log (actor);
+CREATE INDEX audit_log_target ON audit_log (target);
+CREATE INDEX audit_log_created_at ON audit_log (created_at);
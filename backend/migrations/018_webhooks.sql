@@ -0,0 +1,50 @@
+-- external services which are sent signed requests when aliases change
+CREATE TABLE webhooks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL, -- key of the HMAC-SHA256 signature of each request
+    events TEXT NOT NULL, -- json array of the events the webhook is subscribed to
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_created_by_assoc
+        FOREIGN KEY (created_by)
+        REFERENCES users (username)
+) STRICT;
+
+-- events which are waiting to be sent to a webhook, were sent or were given up on
+CREATE TABLE webhook_deliveries (
+    id INTEGER PRIMARY KEY NOT NULL,
+    webhook INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL, -- json body of the request
+    status TEXT NOT NULL CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER, -- unix ts, only set while pending
+    created_at INTEGER NOT NULL, -- unix ts
+    delivered_at INTEGER, -- unix ts
+
+    CONSTRAINT fk_webhook_assoc
+        FOREIGN KEY (webhook)
+        REFERENCES webhooks (id)
+        ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX webhook_deliveries_due ON webhook_deliveries (status, next_attempt_at);
+CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries (webhook);
+
+-- every request which was made for a delivery
+CREATE TABLE webhook_attempts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    delivery INTEGER NOT NULL,
+    status INTEGER, -- http status of the response, missing if none was received
+    error TEXT,
+    attempted_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_delivery_assoc
+        FOREIGN KEY (delivery)
+        REFERENCES webhook_deliveries (id)
+        ON DELETE CASCADE
+) STRICT;
+
+CREATE INDEX webhook_attempts_delivery ON webhook_attempts (delivery);
@@ -0,0 +1,158 @@
+-- the latest number given to a change of an alias, every change of an alias takes the next one so
+-- clients can ask for everything that changed after the last number they saw
+CREATE TABLE alias_change_seq (
+    seq INTEGER NOT NULL
+) STRICT;
+
+ALTER TABLE aliases ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0; -- unix ts
+ALTER TABLE aliases ADD COLUMN change_seq INTEGER NOT NULL DEFAULT 0;
+
+UPDATE aliases SET
+    updated_at = MAX(created_at, COALESCE(deleted_at, 0), COALESCE(hidden_at, 0), COALESCE((
+        SELECT MAX(r.created_at) FROM alias_revisions r WHERE r.alias = aliases.name
+    ), 0)),
+    change_seq = rowid;
+
+INSERT INTO alias_change_seq (seq) SELECT COALESCE(MAX(change_seq), 0) FROM aliases;
+
+CREATE INDEX aliases_change_seq ON aliases (change_seq);
+
+-- names which no longer resolve to an alias because it was deleted or renamed
+CREATE TABLE alias_tombstones (
+    name TEXT PRIMARY KEY NOT NULL,
+    change_seq INTEGER NOT NULL,
+    deleted_at INTEGER NOT NULL -- unix ts
+) STRICT;
+
+INSERT INTO alias_tombstones (name, change_seq, deleted_at)
+SELECT name, change_seq, deleted_at FROM aliases WHERE deleted_at IS NOT NULL;
+
+CREATE INDEX alias_tombstones_change_seq ON alias_tombstones (change_seq);
+
+-- changes to the alias itself
+CREATE TRIGGER aliases_change_insert AFTER INSERT ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.name;
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_update
+AFTER UPDATE OF name, content, "type", media, deleted_at, hidden_at ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_rename AFTER UPDATE OF name ON aliases
+WHEN old.name != new.name BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at)
+    SELECT old.name, seq, unixepoch() FROM alias_change_seq;
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_trash AFTER UPDATE OF deleted_at ON aliases
+WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at)
+    SELECT new.name, seq, unixepoch() FROM alias_change_seq;
+END;
+
+CREATE TRIGGER aliases_change_restore AFTER UPDATE OF deleted_at ON aliases
+WHEN old.deleted_at IS NOT NULL AND new.deleted_at IS NULL BEGIN
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+-- purged aliases already left a tombstone when they were moved to the trash
+CREATE TRIGGER aliases_change_delete AFTER DELETE ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR IGNORE INTO alias_tombstones (name, change_seq, deleted_at)
+    SELECT old.name, seq, unixepoch() FROM alias_change_seq;
+END;
+
+-- changes to what is returned along with the alias
+CREATE TRIGGER alias_synonyms_change_insert AFTER INSERT ON alias_synonyms BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.alias;
+END;
+
+CREATE TRIGGER alias_synonyms_change_delete AFTER DELETE ON alias_synonyms BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = old.alias;
+END;
+
+CREATE TRIGGER alias_tags_change_insert AFTER INSERT ON alias_tags BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.alias;
+END;
+
+CREATE TRIGGER alias_tags_change_delete AFTER DELETE ON alias_tags BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = old.alias;
+END;
+
+CREATE TRIGGER alias_moderation_change_insert AFTER INSERT ON alias_moderation BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.alias;
+END;
+
+CREATE TRIGGER alias_moderation_change_update AFTER UPDATE OF status ON alias_moderation BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.alias;
+END;
+
+CREATE TRIGGER alias_moderation_change_delete AFTER DELETE ON alias_moderation BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = old.alias;
+END;
+
+-- changes shared by many aliases, each of them takes its own number in the order of their names
+CREATE TRIGGER tags_change_rename AFTER UPDATE OF name ON tags BEGIN
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq) + (SELECT COUNT(*)
+            FROM alias_tags x
+            WHERE x.tag = new.id AND x.alias <= aliases.name),
+        updated_at = unixepoch()
+    WHERE name IN (SELECT alias FROM alias_tags WHERE tag = new.id);
+    UPDATE alias_change_seq
+    SET seq = seq + (SELECT COUNT(*) FROM alias_tags WHERE tag = new.id);
+END;
+
+CREATE TRIGGER alias_types_change_rename AFTER UPDATE OF name ON alias_types BEGIN
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq) + (SELECT COUNT(*)
+            FROM aliases a
+            WHERE a."type" = new.id AND a.name <= aliases.name),
+        updated_at = unixepoch()
+    WHERE "type" = new.id;
+    UPDATE alias_change_seq
+    SET seq = seq + (SELECT COUNT(*) FROM aliases WHERE "type" = new.id);
+END;
+
+CREATE TRIGGER media_change_update AFTER UPDATE OF mime, size, width, height, frames ON media BEGIN
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq) + (SELECT COUNT(*)
+            FROM aliases a
+            WHERE a.media = new.hash AND a.name <= aliases.name),
+        updated_at = unixepoch()
+    WHERE media = new.hash;
+    UPDATE alias_change_seq
+    SET seq = seq + (SELECT COUNT(*) FROM aliases WHERE media = new.hash);
+END;
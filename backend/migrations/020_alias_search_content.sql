@@ -0,0 +1,58 @@
+-- the full text index reads the names and content from the aliases instead of keeping its own
+-- copy, so rows are removed from it by rowid instead of scanning the whole index for a name
+DROP TRIGGER aliases_fts_insert;
+DROP TRIGGER aliases_fts_delete;
+DROP TRIGGER aliases_fts_update;
+DROP TABLE aliases_fts;
+
+-- VACUUM can renumber the rowids of the aliases, the index has to be rebuilt after it with
+-- INSERT INTO aliases_fts (aliases_fts) VALUES ('rebuild')
+CREATE VIRTUAL TABLE aliases_fts USING fts5(
+    name,
+    content,
+    content = 'aliases',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+
+INSERT INTO aliases_fts (aliases_fts) VALUES ('rebuild');
+
+CREATE TRIGGER aliases_fts_insert AFTER INSERT ON aliases BEGIN
+    INSERT INTO aliases_fts (rowid, name, content) VALUES (new.rowid, new.name, new.content);
+END;
+
+CREATE TRIGGER aliases_fts_delete AFTER DELETE ON aliases BEGIN
+    INSERT INTO aliases_fts (aliases_fts, rowid, name, content)
+    VALUES ('delete', old.rowid, old.name, old.content);
+END;
+
+CREATE TRIGGER aliases_fts_update AFTER UPDATE OF name, content ON aliases BEGIN
+    INSERT INTO aliases_fts (aliases_fts, rowid, name, content)
+    VALUES ('delete', old.rowid, old.name, old.content);
+    INSERT INTO aliases_fts (rowid, name, content) VALUES (new.rowid, new.name, new.content);
+END;
+
+-- synonyms are searched by name the same way as the aliases they belong to
+CREATE VIRTUAL TABLE alias_synonyms_fts USING fts5(
+    name,
+    content = 'alias_synonyms',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+
+INSERT INTO alias_synonyms_fts (alias_synonyms_fts) VALUES ('rebuild');
+
+CREATE TRIGGER alias_synonyms_fts_insert AFTER INSERT ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (rowid, name) VALUES (new.rowid, new.name);
+END;
+
+CREATE TRIGGER alias_synonyms_fts_delete AFTER DELETE ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (alias_synonyms_fts, rowid, name)
+    VALUES ('delete', old.rowid, old.name);
+END;
+
+CREATE TRIGGER alias_synonyms_fts_update AFTER UPDATE OF name ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (alias_synonyms_fts, rowid, name)
+    VALUES ('delete', old.rowid, old.name);
+    INSERT INTO alias_synonyms_fts (rowid, name) VALUES (new.rowid, new.name);
+END;
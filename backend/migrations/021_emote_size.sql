@@ -0,0 +1,3 @@
+-- images which fit in the maximum size of emotes are detected as emotes, the size is lowered to
+-- the 40 pixels the frontend uses to tell emotes apart unless it was already changed
+UPDATE alias_types SET max_size = 40 WHERE name IN ('emote', 'animatedEmote') AND max_size = 64;
@@ -0,0 +1,5 @@
+-- unix ts of when the dimensions and perceptual hash of media were read, media which can not be
+-- decoded keeps its missing values and is not read again on every start
+ALTER TABLE media ADD COLUMN metadata_read_at INTEGER;
+
+UPDATE media SET metadata_read_at = created_at WHERE width IS NOT NULL AND phash IS NOT NULL;
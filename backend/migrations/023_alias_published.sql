@@ -0,0 +1,53 @@
+-- unix ts of when the alias was first published, aliases which were never approved are not reported
+-- as deleted to users who never saw them
+ALTER TABLE aliases ADD COLUMN published_at INTEGER;
+
+-- aliases waiting for their first moderation or rejected in it only have the revision they were
+-- created with, edits of published aliases which are resubmitted add more
+UPDATE aliases SET published_at = created_at
+WHERE NOT EXISTS (
+    SELECT 1
+    FROM alias_moderation mq
+    WHERE mq.alias = aliases.name
+        AND mq.status != 'approved'
+        AND (SELECT COUNT(*) FROM alias_revisions r WHERE r.alias = aliases.name) <= 1
+);
+
+-- who could see the name before it stopped resolving
+ALTER TABLE alias_tombstones ADD COLUMN author TEXT;
+ALTER TABLE alias_tombstones ADD COLUMN published_at INTEGER;
+
+-- the aliases behind the tombstones of renamed and purged aliases are gone, their names keep being
+-- reported to everyone
+UPDATE alias_tombstones SET
+    author = (SELECT a.author FROM aliases a WHERE a.name = alias_tombstones.name),
+    published_at = CASE
+        WHEN EXISTS (SELECT 1 FROM aliases a WHERE a.name = alias_tombstones.name)
+        THEN (SELECT a.published_at FROM aliases a WHERE a.name = alias_tombstones.name)
+        ELSE deleted_at
+    END;
+
+DROP TRIGGER aliases_change_rename;
+CREATE TRIGGER aliases_change_rename AFTER UPDATE OF name ON aliases
+WHEN old.name != new.name BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT old.name, seq, unixepoch(), new.author, new.published_at FROM alias_change_seq;
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+DROP TRIGGER aliases_change_trash;
+CREATE TRIGGER aliases_change_trash AFTER UPDATE OF deleted_at ON aliases
+WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT new.name, seq, unixepoch(), new.author, new.published_at FROM alias_change_seq;
+END;
+
+-- purged aliases already left a tombstone when they were moved to the trash
+DROP TRIGGER aliases_change_delete;
+CREATE TRIGGER aliases_change_delete AFTER DELETE ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR IGNORE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT old.name, seq, unixepoch(), old.author, old.published_at FROM alias_change_seq;
+END;
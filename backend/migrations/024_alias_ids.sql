@@ -0,0 +1,225 @@
+-- the full text indexes read from the aliases and synonyms by rowid, which VACUUM can renumber for
+-- tables without an INTEGER PRIMARY KEY, so both tables are recreated with one
+DROP TRIGGER aliases_fts_insert;
+DROP TRIGGER aliases_fts_delete;
+DROP TRIGGER aliases_fts_update;
+DROP TABLE aliases_fts;
+
+DROP TRIGGER alias_synonyms_fts_insert;
+DROP TRIGGER alias_synonyms_fts_delete;
+DROP TRIGGER alias_synonyms_fts_update;
+DROP TABLE alias_synonyms_fts;
+
+-- triggers of other tables refer to the tables while they are swapped, the legacy behaviour renames
+-- them without checking those triggers
+PRAGMA legacy_alter_table = ON;
+
+CREATE TABLE aliases_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT UNIQUE NOT NULL,
+    content TEXT NOT NULL,
+    "type" INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+    uses INTEGER NOT NULL DEFAULT 0,
+    deleted_by TEXT,
+    deleted_at INTEGER, -- unix ts
+    media TEXT,
+    hidden_at INTEGER, -- unix ts
+    updated_at INTEGER NOT NULL DEFAULT 0, -- unix ts
+    change_seq INTEGER NOT NULL DEFAULT 0,
+    published_at INTEGER, -- unix ts
+
+    CONSTRAINT fk_author_assoc
+        FOREIGN KEY (author)
+        REFERENCES users (username),
+
+    CONSTRAINT fk_type_assoc
+        FOREIGN KEY ("type")
+        REFERENCES alias_types (id),
+
+    CONSTRAINT fk_deleted_by_assoc
+        FOREIGN KEY (deleted_by)
+        REFERENCES users (username),
+
+    CONSTRAINT fk_media_assoc
+        FOREIGN KEY (media)
+        REFERENCES media (hash)
+) STRICT;
+
+INSERT INTO aliases_new (
+    id,
+    name,
+    content,
+    "type",
+    author,
+    created_at,
+    uses,
+    deleted_by,
+    deleted_at,
+    media,
+    hidden_at,
+    updated_at,
+    change_seq,
+    published_at
+)
+SELECT
+    rowid,
+    name,
+    content,
+    "type",
+    author,
+    created_at,
+    uses,
+    deleted_by,
+    deleted_at,
+    media,
+    hidden_at,
+    updated_at,
+    change_seq,
+    published_at
+FROM aliases;
+
+DROP TABLE aliases;
+ALTER TABLE aliases_new RENAME TO aliases;
+
+CREATE INDEX aliases_author ON aliases (author);
+CREATE INDEX aliases_created_at ON aliases (created_at);
+CREATE INDEX aliases_uses ON aliases (uses);
+CREATE INDEX aliases_deleted_at ON aliases (deleted_at);
+CREATE INDEX aliases_change_seq ON aliases (change_seq);
+
+CREATE TABLE alias_synonyms_new (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT UNIQUE NOT NULL,
+    alias TEXT NOT NULL,
+    created_by TEXT NOT NULL,
+    created_at INTEGER NOT NULL, -- unix ts
+
+    CONSTRAINT fk_alias_assoc
+        FOREIGN KEY (alias)
+        REFERENCES aliases (name)
+        ON UPDATE CASCADE
+        ON DELETE CASCADE,
+
+    CONSTRAINT fk_created_by_assoc
+        FOREIGN KEY (created_by)
+        REFERENCES users (username)
+) STRICT;
+
+INSERT INTO alias_synonyms_new (id, name, alias, created_by, created_at)
+SELECT rowid, name, alias, created_by, created_at FROM alias_synonyms;
+
+DROP TABLE alias_synonyms;
+ALTER TABLE alias_synonyms_new RENAME TO alias_synonyms;
+
+CREATE INDEX alias_synonyms_alias ON alias_synonyms (alias);
+
+PRAGMA legacy_alter_table = OFF;
+
+-- the triggers of both tables were dropped with them, they are the same as before
+CREATE TRIGGER aliases_change_insert AFTER INSERT ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.name;
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_update
+AFTER UPDATE OF name, content, "type", media, deleted_at, hidden_at ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_rename AFTER UPDATE OF name ON aliases
+WHEN old.name != new.name BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT old.name, seq, unixepoch(), new.author, new.published_at FROM alias_change_seq;
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+CREATE TRIGGER aliases_change_trash AFTER UPDATE OF deleted_at ON aliases
+WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR REPLACE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT new.name, seq, unixepoch(), new.author, new.published_at FROM alias_change_seq;
+END;
+
+CREATE TRIGGER aliases_change_restore AFTER UPDATE OF deleted_at ON aliases
+WHEN old.deleted_at IS NOT NULL AND new.deleted_at IS NULL BEGIN
+    DELETE FROM alias_tombstones WHERE name = new.name;
+END;
+
+-- purged aliases already left a tombstone when they were moved to the trash
+CREATE TRIGGER aliases_change_delete AFTER DELETE ON aliases BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    INSERT OR IGNORE INTO alias_tombstones (name, change_seq, deleted_at, author, published_at)
+    SELECT old.name, seq, unixepoch(), old.author, old.published_at FROM alias_change_seq;
+END;
+
+CREATE TRIGGER alias_synonyms_change_insert AFTER INSERT ON alias_synonyms BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = new.alias;
+END;
+
+CREATE TRIGGER alias_synonyms_change_delete AFTER DELETE ON alias_synonyms BEGIN
+    UPDATE alias_change_seq SET seq = seq + 1;
+    UPDATE aliases
+    SET change_seq = (SELECT seq FROM alias_change_seq), updated_at = unixepoch()
+    WHERE name = old.alias;
+END;
+
+CREATE VIRTUAL TABLE aliases_fts USING fts5(
+    name,
+    content,
+    content = 'aliases',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+
+INSERT INTO aliases_fts (aliases_fts) VALUES ('rebuild');
+
+CREATE TRIGGER aliases_fts_insert AFTER INSERT ON aliases BEGIN
+    INSERT INTO aliases_fts (rowid, name, content) VALUES (new.id, new.name, new.content);
+END;
+
+CREATE TRIGGER aliases_fts_delete AFTER DELETE ON aliases BEGIN
+    INSERT INTO aliases_fts (aliases_fts, rowid, name, content)
+    VALUES ('delete', old.id, old.name, old.content);
+END;
+
+CREATE TRIGGER aliases_fts_update AFTER UPDATE OF name, content ON aliases BEGIN
+    INSERT INTO aliases_fts (aliases_fts, rowid, name, content)
+    VALUES ('delete', old.id, old.name, old.content);
+    INSERT INTO aliases_fts (rowid, name, content) VALUES (new.id, new.name, new.content);
+END;
+
+CREATE VIRTUAL TABLE alias_synonyms_fts USING fts5(
+    name,
+    content = 'alias_synonyms',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+
+INSERT INTO alias_synonyms_fts (alias_synonyms_fts) VALUES ('rebuild');
+
+CREATE TRIGGER alias_synonyms_fts_insert AFTER INSERT ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (rowid, name) VALUES (new.id, new.name);
+END;
+
+CREATE TRIGGER alias_synonyms_fts_delete AFTER DELETE ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (alias_synonyms_fts, rowid, name)
+    VALUES ('delete', old.id, old.name);
+END;
+
+CREATE TRIGGER alias_synonyms_fts_update AFTER UPDATE OF name ON alias_synonyms BEGIN
+    INSERT INTO alias_synonyms_fts (alias_synonyms_fts, rowid, name)
+    VALUES ('delete', old.id, old.name);
+    INSERT INTO alias_synonyms_fts (rowid, name) VALUES (new.id, new.name);
+END;
@@ -2,7 +2,7 @@ use anyhow::Context;
 use axum::response::IntoResponse;
 use axum::{extract::rejection::JsonRejection, Extension, Json};
 use idlib::AuthorizeCookie;
-use rusqlite::{params, OptionalExtension, ToSql};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
 use serde::{Deserialize, Serialize};
 use serde_rusqlite::from_row;
 use ts_rs::TS;
@@ -10,6 +10,7 @@ use utoipa::ToSchema;
 
 use std::sync::Arc;
 
+use crate::audit::{self, AuditAction, RequestId};
 use crate::error::Error;
 use crate::util::non_empty_trimmed_str;
 use crate::AppState;
@@ -69,30 +70,30 @@ pub async fn get_settings(
     maybe_token
         .wrap_future(async move {
             let username = payload.name;
-            let result = state
+            state
                 .db
-                .call(move |conn| {
-                    conn.query_row(
-                        "SELECT
-                            color_theme \
-                        FROM users WHERE username = ?1",
-                        params![username],
-                        |row| Ok(from_row::<DbSettings>(row).unwrap()),
-                    )
-                    .optional()
-                })
+                .call(move |conn| get(conn, &username).map(Json))
                 .await
-                .context("Failed to query settings")?;
-
-            if let Some(db_settings) = result {
-                Ok(Json(Settings::from(db_settings)))
-            } else {
-                Err(Error::NotFound)
-            }
         })
         .await
 }
 
+pub fn get(conn: &Connection, username: &str) -> Result<Settings, Error> {
+    let settings = conn
+        .query_row(
+            "SELECT
+                color_theme \
+            FROM users WHERE username = ?1",
+            params![username],
+            |row| Ok(Settings::from(from_row::<DbSettings>(row).unwrap())),
+        )
+        .optional()
+        .context("Failed to query settings")?
+        .ok_or(Error::NotFound)?;
+
+    Ok(settings)
+}
+
 #[derive(Debug, Deserialize, TS, ToSchema)]
 #[ts(export, export_to = "../frontend/src/types/")]
 #[serde(rename_all = "camelCase")]
@@ -120,6 +121,7 @@ pub struct PutSettings {
 pub async fn put_settings(
     AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
     Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
     request: Result<Json<PutSettings>, JsonRejection>,
 ) -> impl IntoResponse {
     maybe_token
@@ -132,17 +134,33 @@ pub async fn put_settings(
                 state
                     .db
                     .call(move |conn| {
+                        let tx = conn.transaction().context("Failed to create transaction")?;
+                        let before = get(&tx, &username)?;
+
                         let mut params = request.update_params();
-                        params.push(Box::new(username));
-                        conn.query_row(
+                        params.push(Box::new(username.clone()));
+                        tx.execute(
                             &format!("UPDATE users SET {update_str} WHERE username = ?"),
                             rusqlite::params_from_iter(params.iter()),
-                            |_| Ok(()),
                         )
-                        .optional()
+                        .context("Failed to update settings")?;
+
+                        let after = get(&tx, &username)?;
+                        audit::record(
+                            &tx,
+                            Some(&request_id),
+                            &username,
+                            AuditAction::SettingsUpdate,
+                            &username,
+                            audit::snapshot(&before),
+                            audit::snapshot(&after),
+                        )?;
+
+                        tx.commit().context("Failed to commit transaction")?;
+
+                        Ok::<_, Error>(())
                     })
-                    .await
-                    .context("Failed to update settings")?;
+                    .await?;
             }
 
             Ok::<_, Error>(())
@@ -1,20 +1,41 @@
-use axum::extract::rejection::JsonRejection;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::{header, HeaderMap, HeaderValue};
 use axum::response::IntoResponse;
 use idlib::{AuthorizeCookie, Has};
 
 use anyhow::Context;
-use axum::{extract::Path, Extension, Json};
+use axum::{
+    extract::{Path, Query, RawQuery},
+    Extension, Json,
+};
+use itertools::Itertools;
 use rusqlite::{params, Connection, OptionalExtension, ToSql, Transaction};
 use serde::{Deserialize, Serialize};
-use serde_rusqlite::{from_row, to_params};
+use serde_rusqlite::from_row;
 use ts_rs::TS;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::SystemTime;
 
+use crate::alias_type::{self, AliasType};
+use crate::audit::{self, AuditAction, RequestId};
+use crate::duplicate::{self, SimilarAlias};
 use crate::error::Error;
-use crate::util::non_empty_trimmed_str;
+use crate::events::AliasEventKind;
+use crate::expand;
+use crate::link_health;
+use crate::media::{self, MediaInfo};
+use crate::moderation::{self, ModerationStatus, Viewer};
+use crate::policy::{self, AliasAction};
+use crate::rename;
+use crate::rendition::Rendition;
+use crate::revision;
+use crate::suggest;
+use crate::template::Template;
+use crate::util::{comma_string, non_empty_trimmed_str, page_offset};
+use crate::webhook::{self, WebhookEvent};
 use crate::AppState;
 
 /// Aliases are key value text replacements for links to images or other things that are difficult
@@ -28,7 +49,7 @@ use crate::AppState;
 /// Alias: "fb", "foobar"  
 /// Comment: "I like !fb" -> "I like foobar"
 /// ```
-#[derive(Debug, Serialize, TS, ToSchema)]
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
 #[ts(export, export_to = "../frontend/src/types/")]
 #[serde(rename_all = "camelCase")]
 pub struct Alias {
@@ -36,7 +57,10 @@ pub struct Alias {
     #[schema(example = "funny.png")]
     pub name: String,
 
-    /// The content of the alias which is to be used as the replacement.
+    /// The content of the alias which is to be used as the replacement. The content of text
+    /// aliases can contain placeholders such as `{0}` or `{name:default}` which are filled in with
+    /// arguments when the alias is expanded, literal braces are written as `{{` and `}}`. Text
+    /// aliases stored before placeholders existed are expanded as they are.
     #[schema(example = "https://example.com/funny.png")]
     pub content: String,
 
@@ -51,80 +75,490 @@ pub struct Alias {
     /// A unix timestamp of when this alias was created.
     #[schema(example = 1670802822)]
     pub created_at: u64,
+
+    /// Additional names which can be used instead of the name of the alias.
+    #[schema(example = json!(["lol.png"]))]
+    pub synonyms: Vec<String>,
+
+    /// Free-form tags used to browse aliases.
+    #[schema(example = json!(["reaction"]))]
+    pub tags: Vec<String>,
+
+    /// The hosted media the content points at, along with what was read from its header.
+    pub media: Option<MediaInfo>,
+
+    /// URLs of the media in different sizes, only present for hosted media.
+    #[schema(example = json!({
+        "thumbnail": "/media/9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08?size=thumbnail",
+        "1x": "/media/9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08?size=1x",
+    }))]
+    pub renditions: Option<BTreeMap<Rendition, String>>,
+
+    /// Whether a moderator approved the alias, aliases which aren't approved are only visible to
+    /// their author and moderators.
+    pub status: ModerationStatus,
+
+    /// A unix timestamp of when the alias was hidden after being reported by too many users,
+    /// hidden aliases are only visible to their author and moderators.
+    pub hidden_at: Option<u64>,
+
+    /// A unix timestamp of when the alias or anything returned along with it last changed.
+    #[schema(example = 1670802822)]
+    pub updated_at: u64,
+
+    /// The sequence number of the latest change of the alias, which increases with every change
+    /// of any alias.
+    #[schema(example = 42)]
+    pub change_seq: u64,
 }
 
 #[derive(Deserialize, Debug)]
-struct DbAlias {
+pub(crate) struct DbAlias {
     name: String,
     content: String,
     #[serde(rename = "type")]
     typ: AliasType,
     author: String,
     created_at: u64,
+    /// A JSON array of the synonyms.
+    synonyms: String,
+    /// A JSON array of the tag names.
+    tags: String,
+    /// A JSON object of the hosted media.
+    media: Option<String>,
+    status: ModerationStatus,
+    hidden_at: Option<u64>,
+    updated_at: u64,
+    change_seq: u64,
 }
 
+/// The columns of [`DbAlias`] selected from the aliases `a`.
+pub(crate) const ALIAS_COLUMNS: &str = "a.name,
+    a.content,
+    (SELECT at.name FROM alias_types at WHERE at.id = a.type) as type,
+    a.author,
+    a.created_at,
+    (SELECT json_group_array(s.name)
+        FROM alias_synonyms s
+        WHERE s.alias = a.name) as synonyms,
+    (SELECT json_group_array(t.name)
+        FROM alias_tags x
+        JOIN tags t ON t.id = x.tag
+        WHERE x.alias = a.name) as tags,
+    (SELECT json_object(
+            'hash', m.hash,
+            'mime', m.mime,
+            'size', m.size,
+            'width', m.width,
+            'height', m.height,
+            'frames', m.frames)
+        FROM media m
+        WHERE m.hash = a.media) as media,
+    COALESCE((SELECT mq.status
+        FROM alias_moderation mq
+        WHERE mq.alias = a.name), 'approved') as status,
+    a.hidden_at,
+    a.updated_at,
+    a.change_seq";
+
 impl From<DbAlias> for Alias {
     fn from(alias: DbAlias) -> Self {
+        let media = alias
+            .media
+            .and_then(|media| serde_json::from_str::<MediaInfo>(&media).ok());
+        // The content of aliases with hosted media is the URL of the media.
+        let renditions = media.as_ref().map(|_| Rendition::urls(&alias.content));
+
         Self {
             name: alias.name,
             content: alias.content,
             typ: alias.typ,
             author: alias.author,
             created_at: alias.created_at,
+            synonyms: serde_json::from_str::<Vec<String>>(&alias.synonyms)
+                .unwrap_or_default()
+                .into_iter()
+                .sorted()
+                .collect(),
+            tags: serde_json::from_str::<Vec<String>>(&alias.tags)
+                .unwrap_or_default()
+                .into_iter()
+                .sorted()
+                .collect(),
+            media,
+            renditions,
+            status: alias.status,
+            hidden_at: alias.hidden_at,
+            updated_at: alias.updated_at,
+            change_seq: alias.change_seq,
+        }
+    }
+}
+
+/// The maximum number of aliases that can be requested for a single page.
+pub const MAX_PER_PAGE: u64 = 1000;
+
+/// Filters, sorting and pagination for listing aliases.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct AliasQuery {
+    /// The page to return, starting at 1. Only used together with `perPage`.
+    #[param(example = 1)]
+    pub page: Option<u64>,
+
+    /// How many aliases to return per page. If this is missing all matching aliases are returned.
+    #[param(example = 200, maximum = 1000)]
+    pub per_page: Option<u64>,
+
+    /// Comma separated list of alias names to include, synonyms match the alias they belong to.
+    #[param(value_type = Option<String>, example = "funny.png,lol.png")]
+    #[serde(default, deserialize_with = "comma_string")]
+    pub name: Option<Vec<String>>,
+
+    /// Comma separated list of alias types to include.
+    #[param(value_type = Option<String>, example = "image,gif")]
+    #[serde(rename = "type", default, deserialize_with = "comma_string")]
+    pub typ: Option<Vec<String>>,
+
+    /// Comma separated list of tags, which of them aliases need to have depends on `tagMatch`.
+    #[param(value_type = Option<String>, example = "reaction,cat")]
+    #[serde(default, deserialize_with = "comma_string")]
+    pub tags: Option<Vec<String>>,
+
+    /// Whether aliases need to have all or any of `tags`, defaults to `all`.
+    pub tag_match: Option<TagMatch>,
+
+    /// Only include aliases created by this user.
+    #[param(example = "Alice")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub author: Option<String>,
+
+    /// Only include aliases created before this unix timestamp.
+    #[param(example = 1670802822)]
+    pub created_before: Option<u64>,
+
+    /// Only include aliases created after this unix timestamp.
+    #[param(example = 1670802822)]
+    pub created_after: Option<u64>,
+
+    /// Only include aliases whose URL is broken, or with `false` only those which aren't known to
+    /// be broken.
+    pub broken: Option<bool>,
+
+    /// The field to sort the aliases by, defaults to `createdAt`.
+    pub sort: Option<AliasSort>,
+
+    /// The direction to sort the aliases in, defaults to `asc`.
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum AliasSort {
+    Name,
+    CreatedAt,
+    /// How often the alias has been looked up by name.
+    Popularity,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum TagMatch {
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl AliasQuery {
+    fn where_str(&self, viewer: &Viewer) -> String {
+        let mut result = vec!["a.deleted_at IS NULL".to_string()];
+
+        if let Some(names) = &self.name {
+            let placeholders = names.iter().map(|_| "?").join(", ");
+            result.push(format!(
+                "(a.name IN ({placeholders}) OR a.name IN (
+                    SELECT s.alias FROM alias_synonyms s WHERE s.name IN ({placeholders})
+                ))"
+            ));
+        }
+
+        if let Some(types) = &self.typ {
+            let placeholders = types.iter().map(|_| "?").join(", ");
+            result.push(format!("at.name IN ({placeholders})"));
+        }
+
+        if let Some(tags) = self.tags() {
+            let placeholders = tags.iter().map(|_| "?").join(", ");
+            let required = match self.tag_match.unwrap_or(TagMatch::All) {
+                TagMatch::All => tags.len(),
+                TagMatch::Any => 1,
+            };
+            result.push(format!(
+                "(SELECT COUNT(*)
+                    FROM alias_tags x
+                    JOIN tags t ON t.id = x.tag
+                    WHERE x.alias = a.name AND t.name IN ({placeholders})
+                ) >= {required}"
+            ));
+        }
+
+        if self.author.is_some() {
+            result.push("a.author = ?".into());
+        }
+
+        if self.created_before.is_some() {
+            result.push("a.created_at < ?".into());
+        }
+
+        if self.created_after.is_some() {
+            result.push("a.created_at > ?".into());
+        }
+
+        if let Some(broken) = self.broken {
+            let exists = if broken { "EXISTS" } else { "NOT EXISTS" };
+            result.push(format!(
+                "{exists} (SELECT 1
+                    FROM link_checks lc
+                    WHERE lc.alias = a.name AND lc.url = a.content AND lc.failures >= {}
+                )",
+                link_health::BROKEN_AFTER_FAILURES
+            ));
+        }
+
+        if let Some(visible) = viewer.where_str() {
+            result.push(visible);
+        }
+
+        format!("WHERE {}", result.join(" AND "))
+    }
+
+    fn where_params(&self, viewer: &Viewer) -> Vec<Box<dyn ToSql>> {
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(names) = &self.name {
+            // Once for the names and once for the synonyms.
+            for _ in 0..2 {
+                for name in names {
+                    params.push(Box::new(name.trim().to_owned()));
+                }
+            }
+        }
+
+        if let Some(types) = &self.typ {
+            for typ in types {
+                params.push(Box::new(typ.trim().to_owned()));
+            }
+        }
+
+        if let Some(tags) = self.tags() {
+            for tag in tags {
+                params.push(Box::new(tag));
+            }
+        }
+
+        if let Some(author) = &self.author {
+            params.push(Box::new(author.clone()));
+        }
+
+        if let Some(created_before) = self.created_before {
+            params.push(Box::new(created_before));
+        }
+
+        if let Some(created_after) = self.created_after {
+            params.push(Box::new(created_after));
+        }
+
+        params.extend(viewer.where_params());
+
+        params
+    }
+
+    fn order_str(&self) -> String {
+        let column = match self.sort.unwrap_or(AliasSort::CreatedAt) {
+            AliasSort::Name => "a.name",
+            AliasSort::CreatedAt => "a.created_at",
+            AliasSort::Popularity => "a.uses",
+        };
+        let direction = match self.order.unwrap_or(SortOrder::Asc) {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+
+        format!("ORDER BY {column} {direction}, a.name {direction}")
+    }
+
+    fn limit_str(&self) -> Result<String, Error> {
+        match self.per_page {
+            Some(per_page) => {
+                let offset = page_offset(self.page(), per_page)?;
+                Ok(format!("LIMIT {per_page} OFFSET {offset}"))
+            }
+            None => Ok(String::new()),
         }
     }
+
+    /// The distinct tags to filter by, in the same form they are stored in.
+    fn tags(&self) -> Option<Vec<String>> {
+        self.tags.as_ref().map(|tags| {
+            tags.iter()
+                .map(|tag| tag.trim().to_lowercase())
+                .unique()
+                .collect()
+        })
+    }
+
+    fn page(&self) -> u64 {
+        self.page.unwrap_or(1)
+    }
 }
 
-/// Get a list of all aliases.
+/// Get a list of aliases.
+/// # Note
+/// Without `perPage` all aliases matching the filters are returned. The total amount of matching
+/// aliases is sent in the `X-Total-Count` header and when there are more pages a `Link` header
+/// pointing to the next page is included. Aliases waiting for moderation are only included for
+/// their author and moderators.
 #[utoipa::path(
     get,
     path = "/api/alias",
     responses(
-        (status = 200, description = "All aliases are returned.", body = [Alias]),
+        (status = 200, description = "The matching aliases are returned.", body = [Alias],
+            headers(
+                ("X-Total-Count" = u64, description = "Total amount of aliases matching the filters."),
+                ("Link" = String, description = "Link to the next page with `rel=\"next\"`, only present if there is one."),
+            ),
+        ),
+        (status = 400, description = "One of the query parameters is invalid."),
         (status = 302, description = "Redirects to hiveID if not authenticated."),
-    )
+    ),
+    params(AliasQuery),
 )]
 pub async fn get_aliases(
-    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
     Extension(state): Extension<Arc<AppState>>,
+    RawQuery(raw_query): RawQuery,
+    query: Result<Query<AliasQuery>, QueryRejection>,
 ) -> impl IntoResponse {
     maybe_token
         .wrap_future(async move {
-            state
+            let Query(query) = query?;
+
+            if query.page == Some(0) {
+                return Err(Error::InvalidQuery("page must be at least 1".into()));
+            }
+            if let Some(per_page) = query.per_page {
+                if per_page == 0 || per_page > MAX_PER_PAGE {
+                    return Err(Error::InvalidQuery(format!(
+                        "perPage must be between 1 and {MAX_PER_PAGE}"
+                    )));
+                }
+                page_offset(query.page(), per_page)?;
+            }
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let (aliases, total, next_page) = state
                 .db
-                .call(move |conn| get_all(conn).map(|u| Json(u)))
-                .await
+                .call(move |conn| {
+                    let (aliases, total) = get_all(conn, &query, &viewer)?;
+                    let next_page = match query.per_page {
+                        Some(per_page) => {
+                            (page_offset(query.page(), per_page)?.saturating_add(per_page) < total)
+                                .then(|| query.page() + 1)
+                        }
+                        None => None,
+                    };
+
+                    Ok::<_, Error>((aliases, total, next_page))
+                })
+                .await?;
+
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Total-Count", HeaderValue::from(total));
+            if let Some(next_page) = next_page {
+                let link = format!(
+                    "</api/alias?{}>; rel=\"next\"",
+                    with_page(raw_query.as_deref().unwrap_or_default(), next_page)
+                );
+                headers.insert(
+                    header::LINK,
+                    HeaderValue::from_str(&link).context("Failed to create link header")?,
+                );
+            }
+
+            Ok((headers, Json(aliases)))
         })
         .await
 }
 
-pub fn get_all(conn: &Connection) -> Result<Vec<Alias>, Error> {
+/// Replaces the page parameter in a query string.
+pub(crate) fn with_page(query: &str, page: u64) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("page="))
+        .map(ToOwned::to_owned)
+        .chain(std::iter::once(format!("page={page}")))
+        .join("&")
+}
+
+/// Returns the aliases matching the query which `viewer` can see along with the total amount of
+/// them.
+pub(crate) fn get_all(
+    conn: &Connection,
+    query: &AliasQuery,
+    viewer: &Viewer,
+) -> Result<(Vec<Alias>, u64), Error> {
+    let where_str = query.where_str(viewer);
+
+    let total = conn
+        .query_row(
+            &format!(
+                "SELECT COUNT(*)
+                FROM aliases a
+                JOIN alias_types at ON at.id = a.type
+                {where_str}"
+            ),
+            rusqlite::params_from_iter(query.where_params(viewer).iter()),
+            |row| row.get(0),
+        )
+        .context("Failed to count aliases")?;
+
     let mut stmt = conn
-        .prepare(
-            "SELECT
-                a.name,
-                a.content,
-                at.name as type,
-                a.author,
-                a.created_at
+        .prepare(&format!(
+            "SELECT {ALIAS_COLUMNS}
             FROM aliases a
             JOIN alias_types at ON at.id = a.type
-            ",
-        )
+            {where_str}
+            {}
+            {}",
+            query.order_str(),
+            query.limit_str()?,
+        ))
         .context("Failed to prepare statement for alias query")?;
 
     let aliases = stmt
-        .query_map(params![], |row| {
-            Ok(Alias::from(from_row::<DbAlias>(row).unwrap()))
-        })
+        .query_map(
+            rusqlite::params_from_iter(query.where_params(viewer).iter()),
+            |row| Ok(Alias::from(from_row::<DbAlias>(row).unwrap())),
+        )
         .context("Failed to query aliases")?
         .collect::<Result<Vec<_>, _>>()
         .context("Failed to collect aliases")?;
 
-    Ok(aliases)
+    Ok((aliases, total))
 }
 
 /// Get a alias by its name.
+/// # Note
+/// Every lookup counts as a use of the alias when sorting by popularity. The old name of a renamed
+/// alias returns the renamed alias until its redirect expires. Aliases waiting for moderation are
+/// only found for their author and moderators.
 #[utoipa::path(
     get,
     path = "/api/alias/{name}",
@@ -139,39 +573,59 @@ pub fn get_all(conn: &Connection) -> Result<Vec<Alias>, Error> {
 )]
 pub async fn get_alias_by_name(
     Path(name): Path<String>,
-    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
     Extension(state): Extension<Arc<AppState>>,
 ) -> impl IntoResponse {
     maybe_token
         .wrap_future(async move {
-            state
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let alias = state
                 .db
-                .call(move |conn| get_by_name(conn, name).map(|u| Json(u)))
-                .await
+                .call(move |conn| {
+                    let alias = get_by_name(conn, name)?;
+                    if !viewer.can_see(&alias) {
+                        return Err(Error::NotFound);
+                    }
+
+                    Ok::<_, Error>(alias)
+                })
+                .await?;
+            state.uses.count(&alias.name);
+
+            Ok::<_, Error>(Json(alias))
         })
         .await
 }
 
+/// Gets a live alias by its name or one of its synonyms, following the redirect left behind if the
+/// alias was renamed.
 pub fn get_by_name(conn: &Connection, name: String) -> Result<Alias, Error> {
-    let content = conn
+    if let Some(alias) = query_by_name(conn, &name)? {
+        return Ok(alias);
+    }
+
+    match rename::resolve(conn, &name)? {
+        Some(target) => query_by_name(conn, &target)?.ok_or(Error::NotFound),
+        None => Err(Error::NotFound),
+    }
+}
+
+pub(crate) fn query_by_name(conn: &Connection, name: &str) -> Result<Option<Alias>, Error> {
+    let alias = conn
         .query_row(
-            "SELECT
-                a.name,
-                a.content,
-                at.name as type,
-                a.author,
-                a.created_at
-            FROM aliases a
-            JOIN alias_types at ON at.id = a.type
-            WHERE name = $1",
+            &format!(
+                "SELECT {ALIAS_COLUMNS}
+                FROM aliases a
+                WHERE (a.name = $1 OR a.name = (SELECT s.alias FROM alias_synonyms s WHERE s.name = $1))
+                    AND a.deleted_at IS NULL"
+            ),
             params![name],
             |row| Ok(Alias::from(from_row::<DbAlias>(row).unwrap())),
         )
         .optional()
-        .context("Failed to query alias")?
-        .ok_or(Error::NotFound)?;
+        .context("Failed to query alias")?;
 
-    Ok(content)
+    Ok(alias)
 }
 
 #[derive(Debug, Deserialize, TS, ToSchema)]
@@ -191,29 +645,34 @@ pub struct PostAlias {
     pub typ: AliasType,
 }
 
-#[derive(Debug, Serialize, Deserialize, TS, ToSchema)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 #[ts(export, export_to = "../frontend/src/types/")]
 #[serde(rename_all = "camelCase")]
-pub enum AliasType {
-    Text,
-    Image,
-    Gif,
-    Emote,
-    AnimatedEmote,
+pub struct PostAliasResponse {
+    /// Existing aliases with hosted media similar to the media of the new alias, these might be
+    /// duplicates.
+    pub similar: Vec<SimilarAlias>,
+
+    /// Whether the alias is published or waiting for a moderator.
+    pub status: ModerationStatus,
 }
 
 type HasCreateAliases = Has<"create-aliases">;
 
 /// Create alias from the body.
 /// # Note
-/// Requires `create-aliases` permission.
+/// Requires `create-aliases` permission. Text aliases with malformed placeholders or which would
+/// expand into themselves through other aliases are rejected. Aliases hosting the same media as an
+/// existing alias are rejected, aliases with media similar to that of existing aliases are created
+/// and the similar aliases are returned as a warning. When moderation is enabled aliases from users
+/// without `moderate-aliases` permission are pending until a moderator approves them.
 #[utoipa::path(
     post,
     path = "/api/alias",
     request_body = PostAlias,
     responses(
-        (status = 200, description = "The alias was successfully created."),
-        (status = 400, description = "One of the values sent in is invalid."),
+        (status = 200, description = "The alias was successfully created.", body = PostAliasResponse),
+        (status = 400, description = "One of the values sent in is invalid, the name is already taken or another alias hosts the same media."),
         (status = 403, description = "User does not have the required permissions."),
         (status = 302, description = "Redirects to hiveID if not authenticated."),
     )
@@ -221,6 +680,7 @@ type HasCreateAliases = Has<"create-aliases">;
 pub async fn post_alias(
     AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasCreateAliases>,
     Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
     request: Result<Json<PostAlias>, JsonRejection>,
 ) -> impl IntoResponse {
     maybe_token
@@ -233,45 +693,110 @@ pub async fn post_alias(
             if request.content.is_empty() {
                 return Err(Error::EmptyField("content"));
             }
+            if request.typ.is_text() {
+                Template::parse(&request.content)?;
+            }
 
             let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            let media = media::hash_of(&state, &request.content);
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let status = if state.moderate_new_aliases && !viewer.is_moderator() {
+                ModerationStatus::Pending
+            } else {
+                ModerationStatus::Approved
+            };
 
-            state
+            let events = state.events.clone();
+            let (similar, created) = state
                 .db
                 .call(move |conn| {
                     let tx = conn.transaction().context("Failed to create transaction")?;
-                    let type_id = tx
-                        .query_row(
-                            "SELECT id
-                            FROM alias_types
-                            WHERE name = $1",
-                            to_params(&request.typ).unwrap(),
-                            |row| Ok(from_row::<i64>(row).unwrap()),
-                        )
-                        .context("Failed to get alias type")?;
+                    let typ = media::enforced_type(&tx, media.as_deref(), request.typ)?;
+                    let type_id = alias_type::usable_id(&tx, &typ)?;
+
+                    check_name_available(&tx, &request.name)?;
+                    duplicate::check_identical(&tx, &request.name, media.as_deref(), &viewer)?;
+
+                    if typ.is_text() {
+                        expand::check_cycles(&tx, &request.name, &request.content)?;
+                    }
 
                     tx.execute(
                         &format!(
-                            "INSERT INTO aliases (name, content, type, author, created_at)
-                            VALUES ($1, $2, $3, $4)"
+                            "INSERT INTO aliases (name, content, type, author, created_at, media, published_at)
+                            VALUES ($1, $2, $3, $4, $5, (SELECT hash FROM media WHERE hash = $6), $7)"
                         ),
-                        params![&request.name, &request.content, type_id, &payload.name, now],
+                        params![
+                            &request.name,
+                            &request.content,
+                            type_id,
+                            &payload.name,
+                            now,
+                            media,
+                            (status == ModerationStatus::Approved).then_some(now)
+                        ],
                     )
                     .context("Failed to insert alias")?;
 
+                    revision::record(&tx, &request.name, None, &payload.name)?;
+                    rename::remove(&tx, &request.name)?;
+                    if status == ModerationStatus::Pending {
+                        moderation::submit(&tx, &request.name, now)?;
+                    }
+
+                    let created = get_by_name(&tx, request.name.clone())?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasCreate,
+                        &request.name,
+                        None,
+                        audit::snapshot(&created),
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasCreated, &created, None)?;
+
+                    let similar = match &media {
+                        Some(hash) => duplicate::find_similar(&tx, &request.name, hash, &viewer)?,
+                        None => Vec::new(),
+                    };
+
                     tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Created, created.clone(), None);
 
-                    Ok::<_, Error>(())
+                    Ok::<_, Error>((similar, created))
                 })
-                .await
-                .context("Failed to insert alias")?;
+                .await?;
 
-            Ok::<_, Error>(())
+            let names = vec![created.name];
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(Json(PostAliasResponse { similar, status }))
         })
         .await
 }
 
-type HasEditAliases = Has<"edit-aliases">;
+/// Checks that `name` isn't used as the name or a synonym of another alias, including aliases in
+/// the trash.
+pub(crate) fn check_name_available(conn: &Connection, name: &str) -> Result<(), Error> {
+    let existing = conn
+        .query_row(
+            "SELECT a.deleted_at
+            FROM aliases a
+            WHERE a.name = $1 OR a.name = (SELECT s.alias FROM alias_synonyms s WHERE s.name = $1)",
+            params![name],
+            |row| row.get::<_, Option<u64>>(0),
+        )
+        .optional()
+        .context("Failed to check if alias exists")?;
+
+    match existing {
+        Some(None) => Err(Error::AliasExists),
+        Some(Some(_)) => Err(Error::AliasInTrash),
+        None => Ok(()),
+    }
+}
 
 /// A list of fields that can be updated for an alias. To leave
 /// fields as they are they can be skipped, set to null or set to a whitespace only string.
@@ -293,9 +818,12 @@ pub struct PutAlias {
     pub typ: Option<AliasType>,
 }
 
-/// Update alias for the specified alias name.
+/// Update alias for the specified alias name, changes are recorded in the history of the alias.
 /// # Note
-/// Requires either `edit-aliases` permission.
+/// Requires `edit-aliases` permission unless the alias was created by the user. Text aliases with
+/// malformed placeholders or which would expand into themselves through other aliases are
+/// rejected, as is content hosting the same media as another alias. When aliases are moderated,
+/// content changed by users who aren't moderators is pending until it is approved again.
 #[utoipa::path(
     put,
     path = "/api/alias/{name}",
@@ -303,7 +831,8 @@ pub struct PutAlias {
     responses(
         (status = 200, description = "The alias was successfully updated."),
         (status = 400, description = "One of the values sent in is invalid."),
-        (status = 403, description = "User does not have the required permissions."),
+        (status = 404, description = "Alias with the specified name does not exist."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
         (status = 302, description = "Redirects to hiveID if not authenticated."),
     ),
     params(
@@ -312,54 +841,135 @@ pub struct PutAlias {
 )]
 pub async fn put_alias_by_name(
     Path(name): Path<String>,
-    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasEditAliases>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
     Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
     request: Result<Json<PutAlias>, JsonRejection>,
 ) -> impl IntoResponse {
     maybe_token
         .wrap_future(async move {
-            let Json(request) = request?;
+            let Json(mut request) = request?;
+
+            let media = request
+                .content
+                .as_ref()
+                .and_then(|content| media::hash_of(&state, content));
 
-            let check_name = name.clone();
-            let alias_exists = state
+            let content_changed = request.content.is_some();
+            let moderated = state.moderate_new_aliases
+                && !Viewer::new(&payload.name, &payload.groups).is_moderator();
+            let requested_type = request.typ.clone();
+            let hash = media.clone();
+            let (user, groups) = (payload.name.clone(), payload.groups.clone());
+            let (current, enforced_type) = state
                 .db
                 .call(move |conn| {
-                    conn.query_row(
-                        "SELECT 1 FROM aliases
-                        WHERE name = ?",
-                        params![&check_name],
-                        |row| Ok(from_row::<i64>(row).unwrap()),
-                    )
+                    let current = get_by_name(conn, name)?;
+                    // Checked here so users who can't edit the alias get a 403 rather than a
+                    // validation error, and again below in case it was replaced in the meantime.
+                    policy::authorize(&user, &groups, &current.author, AliasAction::Edit)?;
+
+                    // Hosted media keeps the type detected from its header, also when only the
+                    // type is changed.
+                    let hash = if content_changed {
+                        hash
+                    } else {
+                        current.media.as_ref().map(|media| media.hash.clone())
+                    };
+                    let requested = requested_type.unwrap_or_else(|| current.typ.clone());
+                    let enforced_type = media::enforced_type(conn, hash.as_deref(), requested)?;
+
+                    Ok::<_, Error>((current, enforced_type))
                 })
-                .await
-                .optional()
-                .context("Failed to check if alias exists")?;
+                .await?;
+            // The alias might have been found through the redirect of an old name.
+            let name = current.name;
 
-            if alias_exists.is_none() {
-                return Err(Error::NotFound);
+            request.typ = Some(enforced_type);
+            // Aliases can keep a type which has been retired since they were created.
+            if request.typ.as_ref() == Some(&current.typ) {
+                request.typ = None;
+            }
+
+            let typ = request.typ.clone().unwrap_or(current.typ);
+            let content = request.content.clone().unwrap_or(current.content);
+            // Content stored before placeholders existed can contain single braces, it is only
+            // checked when it is replaced so other changes can still be made to those aliases.
+            if typ.is_text() && (request.content.is_some() || request.typ.is_some()) {
+                Template::parse(&content)?;
             }
 
             let update_str = request.update_str();
             if !update_str.is_empty() {
-                state
+                let events = state.events.clone();
+                let after = state
                     .db
                     .call(move |conn| {
                         let tx = conn.transaction().context("Failed to create transaction")?;
-                        let mut params = request.update_params(&tx)?;
-                        params.push(Box::new(name));
-                        tx.query_row(
+                        if typ.is_text() {
+                            expand::check_cycles(&tx, &name, &content)?;
+                        }
+
+                        let previous = revision::stored_values(&tx, &name)?;
+                        let before = get_by_name(&tx, name.clone())?;
+                        policy::authorize(
+                            &payload.name,
+                            &payload.groups,
+                            &before.author,
+                            AliasAction::Edit,
+                        )?;
+                        if request.content.is_some() {
+                            let viewer = Viewer::new(&payload.name, &payload.groups);
+                            duplicate::check_identical(&tx, &name, media.as_deref(), &viewer)?;
+                        }
+                        let resubmit = moderated
+                            && request
+                                .content
+                                .as_ref()
+                                .filter(|content| **content != before.content)
+                                .is_some();
+
+                        let mut params = request.update_params(&tx, media)?;
+                        params.push(Box::new(name.clone()));
+                        tx.execute(
                             &format!("UPDATE aliases SET {update_str} WHERE name = ?"),
                             rusqlite::params_from_iter(params.iter()),
-                            |_| Ok(()),
                         )
                         .context("Failed to update alias")?;
 
+                        revision::record(&tx, &name, Some(previous), &payload.name)?;
+                        if resubmit {
+                            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+                            moderation::submit(&tx, &name, now)?;
+                            // Subscribers only know about published aliases, it is created again
+                            // for them once it is approved.
+                            webhook::enqueue(&tx, WebhookEvent::AliasDeleted, &before, None)?;
+                        }
+                        let after = get_by_name(&tx, name.clone())?;
+                        audit::record(
+                            &tx,
+                            Some(&request_id),
+                            &payload.name,
+                            AuditAction::AliasUpdate,
+                            &name,
+                            audit::snapshot(&before),
+                            audit::snapshot(&after),
+                        )?;
+                        webhook::enqueue(&tx, WebhookEvent::AliasUpdated, &after, None)?;
+
                         tx.commit().context("Failed to commit transaction")?;
+                        if resubmit {
+                            events.publish(AliasEventKind::Deleted, before, None);
+                        }
+                        events.publish(AliasEventKind::Updated, after.clone(), None);
 
-                        Ok::<_, Error>(())
+                        Ok::<_, Error>(after)
                     })
-                    .await
-                    .context("Failed to update alias fields")?;
+                    .await?;
+
+                let names = vec![after.name];
+                state.webhooks_queued.notify_one();
+                suggest::update(&state, names).await?;
             }
 
             Ok::<_, Error>(())
@@ -372,7 +982,9 @@ impl PutAlias {
         let mut result = Vec::new();
 
         if self.content.is_some() {
-            result.push("content = ?")
+            result.push("content = ?");
+            // The hosted media the new content points at, if any.
+            result.push("media = (SELECT hash FROM media WHERE hash = ?)");
         }
 
         if self.typ.is_some() {
@@ -382,43 +994,39 @@ impl PutAlias {
         result.join(", ")
     }
 
-    fn update_params(mut self, tx: &Transaction) -> Result<Vec<Box<dyn ToSql>>, Error> {
+    fn update_params(
+        mut self,
+        tx: &Transaction,
+        media: Option<String>,
+    ) -> Result<Vec<Box<dyn ToSql>>, Error> {
         let mut params: Vec<Box<dyn ToSql>> = Vec::new();
 
         if let Some(content) = self.content.take() {
-            params.push(Box::new(content))
+            params.push(Box::new(content));
+            params.push(Box::new(media));
         }
 
         if let Some(typ) = self.typ.take() {
-            let type_id = tx
-                .query_row(
-                    "SELECT id
-                    FROM alias_types
-                    WHERE name = $1",
-                    to_params(&typ).unwrap(),
-                    |row| Ok(from_row::<i64>(row).unwrap()),
-                )
-                .context("Failed to get alias type")?;
-
-            params.push(Box::new(type_id))
+            params.push(Box::new(alias_type::usable_id(tx, &typ)?))
         }
 
         Ok(params)
     }
 }
 
-type HasDeleteAliases = Has<"delete-aliases">;
+pub(crate) type HasDeleteAliases = Has<"delete-aliases">;
 
-/// Delete alias by its name.
+/// Move an alias to the trash by its name.
 /// # Note
-/// Requires `delete-aliases`permission.
+/// Requires `delete-aliases` permission unless the alias was created by the user. Trashed aliases
+/// can be restored until they are purged after the retention period.
 #[utoipa::path(
     delete,
     path = "/api/alias/{name}",
     responses(
-        (status = 200, description = "The alias was successfully deleted."),
+        (status = 200, description = "The alias was successfully moved to the trash."),
         (status = 404, description = "Alias with the specified name does not exist."),
-        (status = 403, description = "User does not have the required permissions."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
         (status = 302, description = "Redirects to hiveID if not authenticated."),
     ),
     params(
@@ -427,23 +1035,60 @@ type HasDeleteAliases = Has<"delete-aliases">;
 )]
 pub async fn delete_alias_by_name(
     Path(name): Path<String>,
-    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasDeleteAliases>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
     Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
 ) -> impl IntoResponse {
     maybe_token
         .wrap_future(async move {
-            state
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            let events = state.events.clone();
+            let deleted = state
                 .db
                 .call(move |conn| {
-                    conn.query_row(
-                        &format!("DELETE FROM aliases WHERE name = ?"),
-                        params![name],
-                        |_| Ok(()),
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    // Synonyms can't be used to delete an alias.
+                    let alias = query_by_name(&tx, &name)?
+                        .filter(|alias| alias.name == name)
+                        .ok_or(Error::NotFound)?;
+                    policy::authorize(
+                        &payload.name,
+                        &payload.groups,
+                        &alias.author,
+                        AliasAction::Delete,
+                    )?;
+
+                    tx.execute(
+                        "UPDATE aliases
+                        SET deleted_by = ?, deleted_at = ?
+                        WHERE name = ? AND deleted_at IS NULL",
+                        params![payload.name, now, name],
                     )
-                    .optional()
+                    .context("Failed to delete alias")?;
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasDelete,
+                        &name,
+                        audit::snapshot(&alias),
+                        None,
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasDeleted, &alias, None)?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Deleted, alias.clone(), None);
+
+                    Ok::<_, Error>(alias)
                 })
-                .await
-                .context("Failed to delete alias")?;
+                .await?;
+
+            let names = vec![deleted.name];
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, names).await?;
 
             Ok::<_, Error>(())
         })
@@ -0,0 +1,621 @@
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::{AuthorizeCookie, Has};
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::probe::DEFAULT_EMOTE_SIZE;
+use crate::suggest;
+use crate::util::{non_empty_trimmed_str, nullable};
+use crate::AppState;
+
+/// The types which have always existed, these can't be renamed since clients rely on them.
+pub const BUILTIN_TYPES: [&str; 5] = ["text", "image", "gif", "emote", "animatedEmote"];
+
+/// The largest maximum size in pixels a type can have.
+pub const MAX_SIZE: u32 = 4096;
+
+/// A category describing the type of content in an alias. Text aliases contain text which can
+/// reference other aliases, the content of every other type is a link to media.
+///
+/// Besides the built-in `text`, `image`, `gif`, `emote` and `animatedEmote` types more can be
+/// created through the API.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(transparent)]
+#[schema(example = "image")]
+pub struct AliasType(
+    // `string & {}` keeps the built-in names from being swallowed by `string` in the union.
+    #[ts(type = "\"text\" | \"image\" | \"gif\" | \"emote\" | \"animatedEmote\" | (string & {})")]
+    String,
+);
+
+impl AliasType {
+    pub fn text() -> Self {
+        Self("text".into())
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.0 == "text"
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for AliasType {
+    fn from(name: &str) -> Self {
+        Self(name.to_owned())
+    }
+}
+
+impl From<String> for AliasType {
+    fn from(name: String) -> Self {
+        Self(name)
+    }
+}
+
+impl fmt::Display for AliasType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ToSql for AliasType {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+/// How clients should lay out the content of aliases of a type.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum TypeDisplay {
+    /// Shown within the surrounding text, like an emote.
+    Inline,
+    /// Shown on its own line, like an image.
+    Block,
+}
+
+impl TypeDisplay {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TypeDisplay::Inline => "inline",
+            TypeDisplay::Block => "block",
+        }
+    }
+}
+
+/// A type which aliases can have together with hints for rendering them.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct AliasTypeInfo {
+    /// The name used as the type of aliases.
+    pub name: AliasType,
+
+    /// A human readable name for the type.
+    #[schema(example = "Image")]
+    pub label: String,
+
+    /// How aliases of this type should be laid out.
+    pub display: TypeDisplay,
+
+    /// The maximum width and height in pixels media of this type should be shown at. Uploaded
+    /// images which fit in the maximum size of `emote` are detected as emotes.
+    #[schema(example = 512)]
+    pub max_size: Option<u32>,
+
+    /// Whether this is one of the built-in types, these can't be renamed.
+    pub builtin: bool,
+
+    /// A unix timestamp of when the type was retired, new aliases can't use retired types.
+    #[schema(example = json!(null))]
+    pub retired_at: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbAliasTypeInfo {
+    name: AliasType,
+    label: String,
+    display: TypeDisplay,
+    max_size: Option<u32>,
+    retired_at: Option<u64>,
+}
+
+impl From<DbAliasTypeInfo> for AliasTypeInfo {
+    fn from(info: DbAliasTypeInfo) -> Self {
+        Self {
+            builtin: BUILTIN_TYPES.contains(&info.name.as_str()),
+            name: info.name,
+            label: info.label,
+            display: info.display,
+            max_size: info.max_size,
+            retired_at: info.retired_at,
+        }
+    }
+}
+
+/// Returns the id of `typ` so it can be stored in a new or changed alias, failing if the type
+/// doesn't exist or has been retired.
+pub(crate) fn usable_id(conn: &Connection, typ: &AliasType) -> Result<i64, Error> {
+    let (id, retired_at) = conn
+        .query_row(
+            "SELECT id, retired_at FROM alias_types WHERE name = ?",
+            params![typ],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<u64>>(1)?)),
+        )
+        .optional()
+        .context("Failed to get alias type")?
+        .ok_or_else(|| Error::UnknownAliasType(typ.to_string()))?;
+
+    if retired_at.is_some() {
+        return Err(Error::AliasTypeRetired(typ.to_string()));
+    }
+
+    Ok(id)
+}
+
+/// Returns the largest width and height of images which are detected as emotes, this is the
+/// maximum size of the `emote` type.
+pub(crate) fn emote_size(conn: &Connection) -> Result<u32, Error> {
+    let max_size = conn
+        .query_row(
+            "SELECT max_size FROM alias_types WHERE name = 'emote'",
+            [],
+            |row| row.get::<_, Option<u32>>(0),
+        )
+        .context("Failed to get emote size")?;
+
+    Ok(max_size.unwrap_or(DEFAULT_EMOTE_SIZE))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric())
+}
+
+fn check_max_size(max_size: Option<u32>) -> Result<(), Error> {
+    if let Some(max_size) = max_size {
+        if max_size == 0 || max_size > MAX_SIZE {
+            return Err(Error::OutOfRange {
+                field: "maxSize",
+                minimum: 1,
+                maximum: MAX_SIZE as u64,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a list of all alias types including retired ones.
+#[utoipa::path(
+    get,
+    path = "/api/types",
+    responses(
+        (status = 200, description = "All alias types are returned.", body = [AliasTypeInfo]),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_types(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move { state.db.call(move |conn| get_all(conn).map(Json)).await })
+        .await
+}
+
+pub fn get_all(conn: &Connection) -> Result<Vec<AliasTypeInfo>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                name,
+                label,
+                display,
+                max_size,
+                retired_at
+            FROM alias_types
+            ORDER BY id",
+        )
+        .context("Failed to prepare statement for alias type query")?;
+
+    let types = stmt
+        .query_map(params![], |row| {
+            Ok(AliasTypeInfo::from(
+                from_row::<DbAliasTypeInfo>(row).unwrap(),
+            ))
+        })
+        .context("Failed to query alias types")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect alias types")?;
+
+    Ok(types)
+}
+
+fn get_by_name(conn: &Connection, name: &str) -> Result<Option<AliasTypeInfo>, Error> {
+    let info = conn
+        .query_row(
+            "SELECT
+                name,
+                label,
+                display,
+                max_size,
+                retired_at
+            FROM alias_types
+            WHERE name = ?",
+            params![name],
+            |row| {
+                Ok(AliasTypeInfo::from(
+                    from_row::<DbAliasTypeInfo>(row).unwrap(),
+                ))
+            },
+        )
+        .optional()
+        .context("Failed to query alias type")?;
+
+    Ok(info)
+}
+
+type HasManageAliasTypes = Has<"manage-alias-types">;
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostAliasType {
+    /// The name used as the type of aliases, it can only contain letters and numbers.
+    #[schema(example = "sticker")]
+    pub name: String,
+
+    /// A human readable name for the type.
+    #[schema(example = "Sticker")]
+    pub label: String,
+
+    /// How aliases of this type should be laid out.
+    pub display: TypeDisplay,
+
+    /// The maximum width and height in pixels media of this type should be shown at.
+    #[schema(example = 256, maximum = 4096)]
+    pub max_size: Option<u32>,
+}
+
+/// Create an alias type.
+/// # Note
+/// Requires `manage-alias-types` permission.
+#[utoipa::path(
+    post,
+    path = "/api/types",
+    request_body = PostAliasType,
+    responses(
+        (status = 200, description = "The alias type was successfully created."),
+        (status = 400, description = "One of the values sent in is invalid or the name is already taken."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn post_type(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageAliasTypes>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PostAliasType>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            if !is_valid_name(&request.name) {
+                return Err(Error::InvalidAliasTypeName(request.name));
+            }
+            let label = request.label.trim().to_owned();
+            if label.is_empty() {
+                return Err(Error::EmptyField("label"));
+            }
+            check_max_size(request.max_size)?;
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let exists = tx
+                        .query_row(
+                            "SELECT 1 FROM alias_types WHERE name = ?",
+                            params![&request.name],
+                            |_| Ok(()),
+                        )
+                        .optional()
+                        .context("Failed to check if alias type exists")?
+                        .is_some();
+                    if exists {
+                        return Err(Error::AliasTypeExists);
+                    }
+
+                    tx.execute(
+                        "INSERT INTO alias_types (name, label, display, max_size)
+                        VALUES (?, ?, ?, ?)",
+                        params![
+                            &request.name,
+                            &label,
+                            request.display.as_str(),
+                            request.max_size
+                        ],
+                    )
+                    .context("Failed to insert alias type")?;
+
+                    let created = get_by_name(&tx, &request.name)?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TypeCreate,
+                        &request.name,
+                        None,
+                        audit::snapshot(&created),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
+
+/// A list of fields that can be updated for an alias type. To leave fields as they are they can
+/// be skipped.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PutAliasType {
+    /// The new name of the type, aliases of this type keep it under the new name.
+    /// # Note
+    /// The input is trimmed and empty inputs are not updated.
+    #[schema(example = "sticker")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub name: Option<String>,
+
+    /// A human readable name for the type.
+    /// # Note
+    /// The input is trimmed and empty inputs are not updated.
+    #[schema(example = "Sticker")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub label: Option<String>,
+
+    /// How aliases of this type should be laid out.
+    pub display: Option<TypeDisplay>,
+
+    /// The maximum width and height in pixels media of this type should be shown at, null removes
+    /// the limit.
+    #[schema(example = 256, maximum = 4096)]
+    #[serde(default, deserialize_with = "nullable")]
+    #[ts(type = "number | null")]
+    pub max_size: Option<Option<u32>>,
+
+    /// Whether the type is retired, false makes a retired type usable again.
+    pub retired: Option<bool>,
+}
+
+/// Update an alias type, this includes renaming it and retiring it.
+/// # Note
+/// Requires `manage-alias-types` permission. Built-in types can't be renamed and `text` can't be
+/// retired.
+#[utoipa::path(
+    put,
+    path = "/api/types/{name}",
+    request_body = PutAliasType,
+    responses(
+        (status = 200, description = "The alias type was successfully updated."),
+        (status = 400, description = "One of the values sent in is invalid."),
+        (status = 404, description = "Alias type with the specified name does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias type to update."),
+    )
+)]
+pub async fn put_type(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageAliasTypes>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PutAliasType>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            let builtin = BUILTIN_TYPES.contains(&name.as_str());
+            if let Some(new_name) = &request.name {
+                if !is_valid_name(new_name) {
+                    return Err(Error::InvalidAliasTypeName(new_name.clone()));
+                }
+                if builtin && *new_name != name {
+                    return Err(Error::BuiltinAliasType {
+                        name,
+                        action: "renamed",
+                    });
+                }
+            }
+            if request.retired == Some(true) && name == "text" {
+                return Err(Error::BuiltinAliasType {
+                    name,
+                    action: "retired",
+                });
+            }
+            if let Some(max_size) = request.max_size {
+                check_max_size(max_size)?;
+            }
+
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            let renamed = request.name.is_some();
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let (id, retired_at) = tx
+                        .query_row(
+                            "SELECT id, retired_at FROM alias_types WHERE name = ?",
+                            params![&name],
+                            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<u64>>(1)?)),
+                        )
+                        .optional()
+                        .context("Failed to get alias type")?
+                        .ok_or(Error::NotFound)?;
+
+                    if let Some(new_name) = &request.name {
+                        let taken = tx
+                            .query_row(
+                                "SELECT 1 FROM alias_types WHERE name = ? AND id != ?",
+                                params![new_name, id],
+                                |_| Ok(()),
+                            )
+                            .optional()
+                            .context("Failed to check if alias type exists")?
+                            .is_some();
+                        if taken {
+                            return Err(Error::AliasTypeExists);
+                        }
+                    }
+
+                    let before = get_by_name(&tx, &name)?;
+                    let retired_at = match request.retired {
+                        Some(true) => retired_at.or(Some(now)),
+                        Some(false) => None,
+                        None => retired_at,
+                    };
+
+                    tx.execute(
+                        "UPDATE alias_types
+                        SET name = COALESCE(?, name),
+                            label = COALESCE(?, label),
+                            display = COALESCE(?, display),
+                            max_size = CASE WHEN ? THEN ? ELSE max_size END,
+                            retired_at = ?
+                        WHERE id = ?",
+                        params![
+                            request.name,
+                            request.label,
+                            request.display.map(|display| display.as_str()),
+                            request.max_size.is_some(),
+                            request.max_size.flatten(),
+                            retired_at,
+                            id
+                        ],
+                    )
+                    .context("Failed to update alias type")?;
+
+                    let after = get_by_name(&tx, request.name.as_deref().unwrap_or(&name))?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TypeUpdate,
+                        &name,
+                        audit::snapshot(&before),
+                        audit::snapshot(&after),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await?;
+
+            // Suggestions contain the name of the type of each alias.
+            if renamed {
+                suggest::rebuild(&state).await?;
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Retire an alias type so it can't be used for new aliases, existing aliases keep it.
+/// # Note
+/// Requires `manage-alias-types` permission. The `text` type can't be retired.
+#[utoipa::path(
+    delete,
+    path = "/api/types/{name}",
+    responses(
+        (status = 200, description = "The alias type was successfully retired."),
+        (status = 400, description = "The alias type can't be retired."),
+        (status = 404, description = "Alias type with the specified name does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias type to retire."),
+    )
+)]
+pub async fn delete_type(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageAliasTypes>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            if name == "text" {
+                return Err(Error::BuiltinAliasType {
+                    name,
+                    action: "retired",
+                });
+            }
+
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let before = get_by_name(&tx, &name)?.ok_or(Error::NotFound)?;
+
+                    tx.execute(
+                        "UPDATE alias_types
+                        SET retired_at = COALESCE(retired_at, ?)
+                        WHERE name = ?",
+                        params![now, &name],
+                    )
+                    .context("Failed to retire alias type")?;
+
+                    let after = get_by_name(&tx, &name)?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TypeDelete,
+                        &name,
+                        audit::snapshot(&before),
+                        audit::snapshot(&after),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
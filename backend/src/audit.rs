@@ -0,0 +1,569 @@
+//! A persistent log of every change made through the api, so moderators can find out who changed
+//! what and when.
+
+use axum::extract::rejection::QueryRejection;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, RawQuery},
+    Extension, Json,
+};
+use itertools::Itertools;
+use rusqlite::{params, Connection, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias::{self, MAX_PER_PAGE};
+use crate::error::Error;
+use crate::moderation::HasModerateAliases;
+use crate::util::{comma_string, non_empty_trimmed_str, page_offset};
+use crate::AppState;
+
+/// The header the id of a request is read from and returned in.
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Request ids sent by clients which are longer than this are replaced with a generated one.
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The id of the request which is being handled, recorded along with the changes it made.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Middleware which takes the request id from the `X-Request-Id` header or generates one, makes it
+/// available to handlers and returns it in the response.
+pub async fn request_id<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| {
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+            let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+            format!("{now:x}-{count:x}")
+        });
+
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
+
+/// What kind of change was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+pub enum AuditAction {
+    #[serde(rename = "alias.create")]
+    AliasCreate,
+    #[serde(rename = "alias.update")]
+    AliasUpdate,
+    #[serde(rename = "alias.delete")]
+    AliasDelete,
+    #[serde(rename = "alias.rename")]
+    AliasRename,
+    #[serde(rename = "alias.synonyms")]
+    AliasSynonyms,
+    #[serde(rename = "alias.tags")]
+    AliasTags,
+    #[serde(rename = "alias.media")]
+    AliasMedia,
+    /// The alias was restored to an earlier revision.
+    #[serde(rename = "alias.revert")]
+    AliasRevert,
+    /// The alias was restored from the trash.
+    #[serde(rename = "alias.restore")]
+    AliasRestore,
+    #[serde(rename = "alias.approve")]
+    AliasApprove,
+    #[serde(rename = "alias.reject")]
+    AliasReject,
+    #[serde(rename = "alias.report")]
+    AliasReport,
+    #[serde(rename = "reports.resolve")]
+    ReportsResolve,
+    #[serde(rename = "reports.dismiss")]
+    ReportsDismiss,
+    #[serde(rename = "tag.create")]
+    TagCreate,
+    #[serde(rename = "tag.update")]
+    TagUpdate,
+    #[serde(rename = "tag.delete")]
+    TagDelete,
+    #[serde(rename = "type.create")]
+    TypeCreate,
+    #[serde(rename = "type.update")]
+    TypeUpdate,
+    #[serde(rename = "type.delete")]
+    TypeDelete,
+    #[serde(rename = "settings.update")]
+    SettingsUpdate,
+    #[serde(rename = "user.create")]
+    UserCreate,
+    #[serde(rename = "webhook.create")]
+    WebhookCreate,
+    #[serde(rename = "webhook.delete")]
+    WebhookDelete,
+    /// A delivery of the webhook was sent again.
+    #[serde(rename = "webhook.retry")]
+    WebhookRetry,
+}
+
+/// Turns what a changed thing looked like into the form it is recorded in.
+pub(crate) fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+    serde_json::to_value(value).ok()
+}
+
+/// Records a change made by `actor` to `target`, `before` is missing for things that were created
+/// and `after` for things that were deleted.
+pub(crate) fn record(
+    conn: &Connection,
+    request_id: Option<&RequestId>,
+    actor: &str,
+    action: AuditAction,
+    target: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<(), Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let action = serde_json::to_value(action).context("Failed to serialize audit action")?;
+
+    conn.execute(
+        "INSERT INTO audit_log (actor, action, target, before, after, request_id, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            actor,
+            action.as_str(),
+            target,
+            before.map(|before| before.to_string()),
+            after.map(|after| after.to_string()),
+            request_id.map(|id| &id.0),
+            now
+        ],
+    )
+    .context("Failed to insert audit log entry")?;
+
+    Ok(())
+}
+
+/// A change made through the api.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    #[schema(example = 12)]
+    pub id: i64,
+
+    /// The username of the account who made the change.
+    #[schema(example = "Alice")]
+    pub actor: String,
+
+    pub action: AuditAction,
+
+    /// The name of what was changed, such as an alias, tag, type or user, or the id of a webhook.
+    #[schema(example = "funny.png")]
+    pub target: String,
+
+    /// What the changed thing looked like before the change, missing if it was created.
+    #[ts(type = "unknown")]
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Value>,
+
+    /// What the changed thing looked like after the change, missing if it was deleted.
+    #[ts(type = "unknown")]
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Value>,
+
+    /// The id of the request which made the change, also returned in the `X-Request-Id` header.
+    #[schema(example = "1734f8a6b2c4d1e0-2a")]
+    pub request_id: Option<String>,
+
+    /// A unix timestamp of when the change was made.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbAuditEntry {
+    id: i64,
+    actor: String,
+    action: AuditAction,
+    target: String,
+    before: Option<String>,
+    after: Option<String>,
+    request_id: Option<String>,
+    created_at: u64,
+}
+
+impl From<DbAuditEntry> for AuditEntry {
+    fn from(entry: DbAuditEntry) -> Self {
+        Self {
+            id: entry.id,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            before: entry
+                .before
+                .and_then(|before| serde_json::from_str(&before).ok()),
+            after: entry
+                .after
+                .and_then(|after| serde_json::from_str(&after).ok()),
+            request_id: entry.request_id,
+            created_at: entry.created_at,
+        }
+    }
+}
+
+/// Filters and pagination for the audit log.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    /// The page to return, starting at 1.
+    #[param(example = 1)]
+    pub page: Option<u64>,
+
+    /// How many entries to return per page, defaults to 100.
+    #[param(example = 100, maximum = 1000)]
+    pub per_page: Option<u64>,
+
+    /// Only include changes made by this user.
+    #[param(example = "Alice")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub actor: Option<String>,
+
+    /// Comma separated list of actions to include.
+    #[param(value_type = Option<String>, example = "alias.update,alias.delete")]
+    #[serde(default, deserialize_with = "comma_string")]
+    pub action: Option<Vec<String>>,
+
+    /// Only include changes to the alias, tag, type or user with this name.
+    #[param(example = "funny.png")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub target: Option<String>,
+
+    /// Only include changes made before this unix timestamp.
+    #[param(example = 1670802822)]
+    pub created_before: Option<u64>,
+
+    /// Only include changes made after this unix timestamp.
+    #[param(example = 1670802822)]
+    pub created_after: Option<u64>,
+}
+
+/// How many entries are returned per page if `perPage` is missing.
+const DEFAULT_PER_PAGE: u64 = 100;
+
+impl AuditQuery {
+    fn where_str(&self) -> String {
+        let mut result = Vec::new();
+
+        if self.actor.is_some() {
+            result.push("actor = ?".to_string());
+        }
+
+        if let Some(actions) = &self.action {
+            let placeholders = actions.iter().map(|_| "?").join(", ");
+            result.push(format!("action IN ({placeholders})"));
+        }
+
+        if self.target.is_some() {
+            result.push("target = ?".to_string());
+        }
+
+        if self.created_before.is_some() {
+            result.push("created_at < ?".to_string());
+        }
+
+        if self.created_after.is_some() {
+            result.push("created_at > ?".to_string());
+        }
+
+        if result.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", result.join(" AND "))
+        }
+    }
+
+    fn where_params(&self) -> Vec<Box<dyn ToSql>> {
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(actor) = &self.actor {
+            params.push(Box::new(actor.clone()));
+        }
+
+        if let Some(actions) = &self.action {
+            for action in actions {
+                params.push(Box::new(action.trim().to_owned()));
+            }
+        }
+
+        if let Some(target) = &self.target {
+            params.push(Box::new(target.clone()));
+        }
+
+        if let Some(created_before) = self.created_before {
+            params.push(Box::new(created_before));
+        }
+
+        if let Some(created_after) = self.created_after {
+            params.push(Box::new(created_after));
+        }
+
+        params
+    }
+
+    fn page(&self) -> u64 {
+        self.page.unwrap_or(1)
+    }
+
+    fn per_page(&self) -> u64 {
+        self.per_page.unwrap_or(DEFAULT_PER_PAGE)
+    }
+}
+
+/// Get the audit log, newest changes first.
+/// # Note
+/// Requires `moderate-aliases` permission. The total amount of matching entries is sent in the
+/// `X-Total-Count` header and when there are more pages a `Link` header pointing to the next page
+/// is included.
+#[utoipa::path(
+    get,
+    path = "/api/audit",
+    responses(
+        (status = 200, description = "The matching entries are returned.", body = [AuditEntry],
+            headers(
+                ("X-Total-Count" = u64, description = "Total amount of entries matching the filters."),
+                ("Link" = String, description = "Link to the next page with `rel=\"next\"`, only present if there is one."),
+            ),
+        ),
+        (status = 400, description = "One of the query parameters is invalid."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(AuditQuery),
+)]
+pub async fn get_audit_log(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    RawQuery(raw_query): RawQuery,
+    query: Result<Query<AuditQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            if query.page == Some(0) {
+                return Err(Error::InvalidQuery("page must be at least 1".into()));
+            }
+            if query.per_page == Some(0) || query.per_page() > MAX_PER_PAGE {
+                return Err(Error::InvalidQuery(format!(
+                    "perPage must be between 1 and {MAX_PER_PAGE}"
+                )));
+            }
+            page_offset(query.page(), query.per_page())?;
+
+            let (entries, total, next_page) = state
+                .db
+                .call(move |conn| {
+                    let (entries, total) = get_entries(conn, &query)?;
+                    let next_page = (page_offset(query.page(), query.per_page())?
+                        .saturating_add(query.per_page())
+                        < total)
+                        .then(|| query.page() + 1);
+
+                    Ok::<_, Error>((entries, total, next_page))
+                })
+                .await?;
+
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Total-Count", HeaderValue::from(total));
+            if let Some(next_page) = next_page {
+                let link = format!(
+                    "</api/audit?{}>; rel=\"next\"",
+                    alias::with_page(raw_query.as_deref().unwrap_or_default(), next_page)
+                );
+                headers.insert(
+                    header::LINK,
+                    HeaderValue::from_str(&link).context("Failed to create link header")?,
+                );
+            }
+
+            Ok((headers, Json(entries)))
+        })
+        .await
+}
+
+/// Returns a page of the entries matching the query along with the total amount of them.
+pub fn get_entries(conn: &Connection, query: &AuditQuery) -> Result<(Vec<AuditEntry>, u64), Error> {
+    let where_str = query.where_str();
+
+    let total = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM audit_log {where_str}"),
+            rusqlite::params_from_iter(query.where_params().iter()),
+            |row| row.get(0),
+        )
+        .context("Failed to count audit log entries")?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT
+                id,
+                actor,
+                action,
+                target,
+                before,
+                after,
+                request_id,
+                created_at
+            FROM audit_log
+            {where_str}
+            ORDER BY id DESC
+            LIMIT {} OFFSET {}",
+            query.per_page(),
+            page_offset(query.page(), query.per_page())?,
+        ))
+        .context("Failed to prepare statement for audit log query")?;
+
+    let entries = stmt
+        .query_map(
+            rusqlite::params_from_iter(query.where_params().iter()),
+            |row| Ok(AuditEntry::from(from_row::<DbAuditEntry>(row).unwrap())),
+        )
+        .context("Failed to query audit log")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect audit log")?;
+
+    Ok((entries, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    /// A database with an alias created by alice, updated by bob and deleted by alice.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            let request_id = RequestId("request".to_owned());
+            let changes = [
+                ("alice", AuditAction::AliasCreate, None, Some(1)),
+                ("bob", AuditAction::AliasUpdate, Some(1), Some(2)),
+                ("alice", AuditAction::AliasDelete, Some(2), None),
+            ];
+            for (actor, action, before, after) in changes {
+                let before = before.map(|content| json!({ "content": content }));
+                let after = after.map(|content| json!({ "content": content }));
+                record(conn, Some(&request_id), actor, action, "fb", before, after).unwrap();
+            }
+            record(
+                conn,
+                None,
+                "alice",
+                AuditAction::TagCreate,
+                "cat",
+                None,
+                None,
+            )
+            .unwrap();
+        })
+        .await;
+        db
+    }
+
+    fn actions(entries: &[AuditEntry]) -> Vec<AuditAction> {
+        entries.iter().map(|entry| entry.action).collect()
+    }
+
+    #[tokio::test]
+    async fn returns_the_newest_entries_first() {
+        let db = setup().await;
+        let (entries, total) = db
+            .call(|conn| get_entries(conn, &AuditQuery::default()).unwrap())
+            .await;
+
+        assert_eq!(total, 4);
+        assert_eq!(
+            actions(&entries),
+            [
+                AuditAction::TagCreate,
+                AuditAction::AliasDelete,
+                AuditAction::AliasUpdate,
+                AuditAction::AliasCreate,
+            ]
+        );
+
+        let update = &entries[2];
+        assert_eq!(update.actor, "bob");
+        assert_eq!(update.before, Some(json!({ "content": 1 })));
+        assert_eq!(update.after, Some(json!({ "content": 2 })));
+        assert_eq!(update.request_id.as_deref(), Some("request"));
+        assert_eq!(entries[0].request_id, None);
+    }
+
+    #[tokio::test]
+    async fn filters_entries() {
+        let db = setup().await;
+        db.call(|conn| {
+            let query = AuditQuery {
+                actor: Some("alice".to_owned()),
+                target: Some("fb".to_owned()),
+                ..AuditQuery::default()
+            };
+            let (entries, _) = get_entries(conn, &query).unwrap();
+            assert_eq!(
+                actions(&entries),
+                [AuditAction::AliasDelete, AuditAction::AliasCreate]
+            );
+
+            let query = AuditQuery {
+                action: Some(vec!["alias.update".to_owned(), " tag.create".to_owned()]),
+                ..AuditQuery::default()
+            };
+            let (entries, _) = get_entries(conn, &query).unwrap();
+            assert_eq!(
+                actions(&entries),
+                [AuditAction::TagCreate, AuditAction::AliasUpdate]
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn pages_through_entries() {
+        let db = setup().await;
+        let (entries, total) = db
+            .call(|conn| {
+                let query = AuditQuery {
+                    page: Some(2),
+                    per_page: Some(3),
+                    ..AuditQuery::default()
+                };
+                get_entries(conn, &query).unwrap()
+            })
+            .await;
+
+        assert_eq!(total, 4);
+        assert_eq!(actions(&entries), [AuditAction::AliasCreate]);
+    }
+}
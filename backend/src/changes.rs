@@ -0,0 +1,332 @@
+//! Lets clients keep a local copy of the aliases by only fetching what changed since they last
+//! looked. Every change of an alias takes the next number of a sequence which is kept up to date by
+//! triggers in the database, deleted and renamed aliases leave a tombstone behind.
+
+use axum::extract::rejection::QueryRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Query, Extension, Json};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::alias::{Alias, DbAlias, ALIAS_COLUMNS};
+use crate::error::Error;
+use crate::moderation::Viewer;
+use crate::AppState;
+
+/// The maximum number of changes that can be requested at once.
+pub const MAX_CHANGES: u64 = 1000;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangesQuery {
+    /// The `seq` of the last response, changes after it are returned. Without it every alias is
+    /// returned.
+    #[param(example = 42)]
+    pub since: Option<u64>,
+
+    /// How many changes to return at most, defaults to 1000.
+    #[param(example = 200, maximum = 1000)]
+    pub limit: Option<u64>,
+}
+
+/// The aliases which changed since a point in the sequence of changes.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct AliasChanges {
+    /// Aliases which were created or changed, in the order of their changes.
+    pub aliases: Vec<Alias>,
+
+    /// Names which no longer resolve to an alias the user can see, because it was deleted, renamed
+    /// or hidden. Only names the user could see before are included.
+    #[schema(example = json!(["funny.png"]))]
+    pub deleted: Vec<String>,
+
+    /// The sequence number of the latest change included, pass it as `since` to get the changes
+    /// after these.
+    #[schema(example = 42)]
+    pub seq: u64,
+
+    /// Whether there are more changes after `seq` which didn't fit in the limit.
+    pub more: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbChange {
+    name: String,
+    change_seq: u64,
+    /// Only set for tombstones.
+    deleted_at: Option<u64>,
+    /// Not known for the tombstones of aliases which were renamed or purged before it was stored.
+    author: Option<String>,
+    published_at: Option<u64>,
+}
+
+impl DbChange {
+    /// Whether the name resolved to an alias `viewer` could see before it changed.
+    fn was_visible(&self, viewer: &Viewer) -> bool {
+        self.published_at.is_some()
+            || viewer.is_moderator()
+            || matches!(&self.author, Some(author) if viewer.is_author(author))
+    }
+}
+
+/// Get the aliases which changed since the last time they were fetched.
+/// # Note
+/// Each alias and deletion is only returned once, for its latest change. Aliases waiting for
+/// moderation are only included for their author and moderators. Changes of other aliases which
+/// can't be seen are returned as deleted if the aliases were published before, names which were
+/// never published are left out.
+#[utoipa::path(
+    get,
+    path = "/api/alias/changes",
+    responses(
+        (status = 200, description = "The changes after `since` are returned.", body = AliasChanges),
+        (status = 400, description = "One of the query parameters is invalid."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(ChangesQuery),
+)]
+pub async fn get_alias_changes(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<ChangesQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            let limit = query.limit.unwrap_or(MAX_CHANGES);
+            if limit == 0 || limit > MAX_CHANGES {
+                return Err(Error::InvalidQuery(format!(
+                    "limit must be between 1 and {MAX_CHANGES}"
+                )));
+            }
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            state
+                .db
+                .call(move |conn| {
+                    get_since(conn, query.since.unwrap_or(0), limit, &viewer).map(Json)
+                })
+                .await
+        })
+        .await
+}
+
+pub fn get_since(
+    conn: &Connection,
+    since: u64,
+    limit: u64,
+    viewer: &Viewer,
+) -> Result<AliasChanges, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT name, change_seq, deleted_at, author, published_at FROM (
+                SELECT a.name, a.change_seq, NULL as deleted_at, a.author, a.published_at
+                FROM aliases a
+                WHERE a.change_seq > $1 AND a.deleted_at IS NULL
+                UNION ALL
+                SELECT t.name, t.change_seq, t.deleted_at, t.author, t.published_at
+                FROM alias_tombstones t
+                WHERE t.change_seq > $1
+            )
+            ORDER BY change_seq
+            LIMIT $2",
+        )
+        .context("Failed to prepare statement for change query")?;
+
+    // One more than the limit to know whether there are more.
+    let mut changes = stmt
+        .query_map(params![since, limit + 1], |row| {
+            Ok(from_row::<DbChange>(row).unwrap())
+        })
+        .context("Failed to query changes")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect changes")?;
+
+    let more = changes.len() as u64 > limit;
+    changes.truncate(limit as usize);
+
+    let changed: Vec<&str> = changes
+        .iter()
+        .filter(|change| change.deleted_at.is_none())
+        .map(|change| change.name.as_str())
+        .collect();
+    let mut changed = get_changed(conn, &changed)?;
+
+    let seq = changes.last().map_or(since, |change| change.change_seq);
+    let mut aliases = Vec::new();
+    let mut deleted = Vec::new();
+    for change in changes {
+        let alias = match change.deleted_at {
+            None => changed.remove(&change.name),
+            Some(_) => None,
+        };
+
+        match alias {
+            Some(alias) if viewer.can_see(&alias) => aliases.push(alias),
+            // Names the viewer never saw are left out so they don't learn about them.
+            _ if change.was_visible(viewer) => deleted.push(change.name),
+            _ => {}
+        }
+    }
+
+    Ok(AliasChanges {
+        aliases,
+        deleted,
+        seq,
+        more,
+    })
+}
+
+/// Returns the live aliases with exactly the given `names`, by name.
+fn get_changed(conn: &Connection, names: &[&str]) -> Result<HashMap<String, Alias>, Error> {
+    if names.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {ALIAS_COLUMNS}
+            FROM aliases a
+            WHERE a.name IN (SELECT value FROM json_each(?)) AND a.deleted_at IS NULL"
+        ))
+        .context("Failed to prepare statement for changed alias query")?;
+
+    // Passed as a single JSON array so the number of names isn't limited by the maximum number
+    // of parameters.
+    let names = serde_json::to_string(names).context("Failed to serialize alias names")?;
+    let aliases = stmt
+        .query_map(params![names], |row| {
+            Ok(Alias::from(from_row::<DbAlias>(row).unwrap()))
+        })
+        .context("Failed to query changed aliases")?
+        .map(|alias| alias.map(|alias| (alias.name.clone(), alias)))
+        .collect::<Result<HashMap<_, _>, _>>()
+        .context("Failed to collect changed aliases")?;
+
+    Ok(aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with a published alias `fb` by alice and a pending alias `new` by bob which was
+    /// never published.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0), ('bob', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, published_at)
+                VALUES ('fb', 'foobar', 1, 'alice', 0, 0);
+                INSERT INTO aliases (name, content, type, author, created_at)
+                VALUES ('new', 'secret', 1, 'bob', 0);
+                INSERT INTO alias_moderation (alias, status, submitted_at)
+                VALUES ('new', 'pending', 0);",
+            )
+            .unwrap();
+        })
+        .await;
+
+        db
+    }
+
+    fn changes(conn: &Connection, since: u64, user: &str, groups: &[String]) -> AliasChanges {
+        get_since(conn, since, MAX_CHANGES, &Viewer::new(user, groups)).unwrap()
+    }
+
+    fn names(aliases: &[Alias]) -> Vec<&str> {
+        aliases.iter().map(|alias| alias.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn returns_visible_aliases_in_order() {
+        let db = setup().await;
+
+        db.call(|conn| {
+            let all = changes(conn, 0, "alice", &[]);
+            assert_eq!(names(&all.aliases), ["fb"]);
+            assert!(all.deleted.is_empty());
+            assert!(!all.more);
+
+            let author = changes(conn, 0, "bob", &[]);
+            assert_eq!(names(&author.aliases), ["fb", "new"]);
+
+            conn.execute("UPDATE aliases SET content = 'foo' WHERE name = 'fb'", [])
+                .unwrap();
+            let later = changes(conn, author.seq, "bob", &[]);
+            assert_eq!(names(&later.aliases), ["fb"]);
+            assert_eq!(later.aliases[0].content, "foo");
+
+            let page = get_since(conn, 0, 1, &Viewer::new("bob", &[])).unwrap();
+            assert_eq!(names(&page.aliases), ["new"]);
+            assert!(page.more);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn only_reports_names_which_were_visible_as_deleted() {
+        let db = setup().await;
+
+        db.call(|conn| {
+            let seq = changes(conn, 0, "alice", &[]).seq;
+            conn.execute_batch(
+                "UPDATE aliases SET deleted_at = 1 WHERE name = 'new';
+                UPDATE aliases SET hidden_at = 1 WHERE name = 'fb';",
+            )
+            .unwrap();
+
+            // The pending alias was never published, so only its author and moderators knew it.
+            let other = changes(conn, seq, "carol", &[]);
+            assert!(other.aliases.is_empty());
+            assert_eq!(other.deleted, ["fb"]);
+
+            let author = changes(conn, seq, "bob", &[]);
+            assert_eq!(author.deleted, ["new", "fb"]);
+
+            let moderator = changes(conn, seq, "carol", &["moderate-aliases".to_owned()]);
+            assert_eq!(names(&moderator.aliases), ["fb"]);
+            assert_eq!(moderator.deleted, ["new"]);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reports_the_old_name_of_renamed_aliases() {
+        let db = setup().await;
+
+        db.call(|conn| {
+            let seq = changes(conn, 0, "alice", &[]).seq;
+            conn.execute_batch(
+                "INSERT INTO alias_synonyms (name, alias, created_by, created_at)
+                VALUES ('fbb', 'fb', 'alice', 0);
+                UPDATE aliases SET name = 'foobar' WHERE name = 'fb';
+                UPDATE aliases SET name = 'newer' WHERE name = 'new';",
+            )
+            .unwrap();
+
+            let other = changes(conn, seq, "carol", &[]);
+            assert_eq!(names(&other.aliases), ["foobar"]);
+            assert_eq!(other.deleted, ["fb"]);
+
+            let author = changes(conn, seq, "bob", &[]);
+            assert_eq!(names(&author.aliases), ["foobar", "newer"]);
+            assert_eq!(author.deleted, ["fb", "new"]);
+        })
+        .await;
+    }
+}
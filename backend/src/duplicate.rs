@@ -0,0 +1,473 @@
+//! Finding aliases with the same or nearly the same content so the same emote isn't added under
+//! several names.
+
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{Extension, Json};
+use image::error::{LimitError, LimitErrorKind};
+use image::imageops::FilterType;
+use image::{ImageError, ImageResult};
+use itertools::Itertools;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::alias::HasDeleteAliases;
+use crate::error::Error;
+use crate::moderation::Viewer;
+use crate::probe::ImageInfo;
+use crate::rendition;
+use crate::AppState;
+
+/// Media whose perceptual hashes differ in at most this many of the 64 bits is considered similar.
+pub const SIMILARITY_THRESHOLD: u32 = 6;
+
+/// Computes a difference hash of the first frame of an image. Scaled, recompressed or slightly
+/// edited copies of an image have hashes which only differ in a few bits. Images with more than
+/// [`rendition::MAX_RENDER_PIXELS`] pixels are not decoded.
+pub fn perceptual_hash(data: &[u8], info: &ImageInfo) -> ImageResult<u64> {
+    if info.width as u64 * info.height as u64 > rendition::MAX_RENDER_PIXELS {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    }
+
+    let image = image::load_from_memory_with_format(data, rendition::image_format(info.format))?;
+    let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y).0[0] < small.get_pixel(x + 1, y).0[0];
+            hash = hash << 1 | u64::from(brighter);
+        }
+    }
+
+    Ok(hash)
+}
+
+fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// An alias with content similar to the content of another alias.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct SimilarAlias {
+    /// The name of the alias with similar content.
+    #[schema(example = "funny.png")]
+    pub name: String,
+
+    /// How many bits of the perceptual hashes of the media differ, lower is more similar.
+    #[schema(example = 2)]
+    pub distance: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbHashedAlias {
+    name: String,
+    media: String,
+    /// The perceptual hash of the hosted media, stored as a signed integer.
+    phash: Option<i64>,
+}
+
+impl DbHashedAlias {
+    fn phash(&self) -> Option<u64> {
+        self.phash.map(|phash| phash as u64)
+    }
+}
+
+/// Fails if another live alias than `name` which `viewer` can see hosts the same media. Text is
+/// never compared, several aliases can have the same short text or link on purpose.
+pub(crate) fn check_identical(
+    conn: &Connection,
+    name: &str,
+    media: Option<&str>,
+    viewer: &Viewer,
+) -> Result<(), Error> {
+    let media = match media {
+        Some(media) => media,
+        None => return Ok(()),
+    };
+
+    let visible = viewer
+        .where_str()
+        .map(|visible| format!("AND {visible}"))
+        .unwrap_or_default();
+    let mut params: Vec<Box<dyn ToSql>> =
+        vec![Box::new(media.to_owned()), Box::new(name.to_owned())];
+    params.extend(viewer.where_params());
+
+    let existing = conn
+        .query_row(
+            &format!(
+                "SELECT a.name
+                FROM aliases a
+                WHERE a.media = ? AND a.name != ? AND a.deleted_at IS NULL {visible}
+                ORDER BY a.created_at
+                LIMIT 1"
+            ),
+            params_from_iter(params.iter()),
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .context("Failed to query aliases with identical media")?;
+
+    match existing {
+        Some(existing) => Err(Error::DuplicateContent(existing)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the live aliases other than `name` which `viewer` can see with hosted media similar to
+/// the media with `hash`, most similar first.
+pub(crate) fn find_similar(
+    conn: &Connection,
+    name: &str,
+    hash: &str,
+    viewer: &Viewer,
+) -> Result<Vec<SimilarAlias>, Error> {
+    let phash = conn
+        .query_row(
+            "SELECT phash FROM media WHERE hash = ?",
+            params![hash],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+        .optional()
+        .context("Failed to query perceptual hash")?
+        .flatten();
+    let phash = match phash {
+        Some(phash) => phash as u64,
+        None => return Ok(Vec::new()),
+    };
+
+    let similar = media_aliases(conn, "m.phash IS NOT NULL", viewer)?
+        .into_iter()
+        .filter(|alias| alias.name != name)
+        .filter_map(|alias| {
+            let distance = distance(phash, alias.phash()?);
+            (distance <= SIMILARITY_THRESHOLD).then_some(SimilarAlias {
+                name: alias.name,
+                distance,
+            })
+        })
+        .sorted_by(|a, b| {
+            a.distance
+                .cmp(&b.distance)
+                .then_with(|| a.name.cmp(&b.name))
+        })
+        .collect();
+
+    Ok(similar)
+}
+
+/// Returns the live aliases with hosted media which `viewer` can see and which match `condition`
+/// on the media `m`.
+fn media_aliases(
+    conn: &Connection,
+    condition: &str,
+    viewer: &Viewer,
+) -> Result<Vec<DbHashedAlias>, Error> {
+    let visible = viewer
+        .where_str()
+        .map(|visible| format!("AND {visible}"))
+        .unwrap_or_default();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT a.name, a.media, m.phash
+            FROM aliases a
+            JOIN media m ON m.hash = a.media
+            WHERE a.deleted_at IS NULL AND {condition} {visible}
+            ORDER BY a.name"
+        ))
+        .context("Failed to prepare statement for hosted aliases query")?;
+
+    let aliases = stmt
+        .query_map(params_from_iter(viewer.where_params().iter()), |row| {
+            Ok(from_row::<DbHashedAlias>(row).unwrap())
+        })
+        .context("Failed to query hosted aliases")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect hosted aliases")?;
+
+    Ok(aliases)
+}
+
+/// A group of aliases which are likely duplicates of each other.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCluster {
+    /// The names of the aliases in the cluster.
+    #[schema(example = json!(["funny.png", "lol.png"]))]
+    pub aliases: Vec<String>,
+
+    /// Whether all aliases host exactly the same media, otherwise their media is similar.
+    pub identical: bool,
+}
+
+/// Get clusters of aliases with the same or similar hosted media, largest first.
+/// # Note
+/// Requires `delete-aliases` permission. Aliases are in the same cluster if they are connected by
+/// a chain of duplicates, so the first and last alias in a cluster can be less similar than
+/// [`SIMILARITY_THRESHOLD`] allows.
+#[utoipa::path(
+    get,
+    path = "/api/duplicates",
+    responses(
+        (status = 200, description = "The clusters of likely duplicates are returned.", body = [DuplicateCluster]),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_duplicates(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasDeleteAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            state
+                .db
+                .call(move |conn| get_clusters(conn, &viewer).map(Json))
+                .await
+        })
+        .await
+}
+
+pub(crate) fn get_clusters(
+    conn: &Connection,
+    viewer: &Viewer,
+) -> Result<Vec<DuplicateCluster>, Error> {
+    let aliases = media_aliases(conn, "TRUE", viewer)?;
+    let mut clusters = Clusters::new(aliases.len());
+
+    let mut by_media = HashMap::new();
+    for (i, alias) in aliases.iter().enumerate() {
+        if let Some(&first) = by_media.get(alias.media.as_str()) {
+            clusters.join(first, i);
+        } else {
+            by_media.insert(alias.media.as_str(), i);
+        }
+    }
+
+    let hashed = aliases
+        .iter()
+        .enumerate()
+        .filter_map(|(i, alias)| Some((i, alias.phash()?)))
+        .collect::<Vec<_>>();
+    for (a, (i, phash_a)) in hashed.iter().enumerate() {
+        for (j, phash_b) in &hashed[a + 1..] {
+            if distance(*phash_a, *phash_b) <= SIMILARITY_THRESHOLD {
+                clusters.join(*i, *j);
+            }
+        }
+    }
+
+    let clusters = (0..aliases.len())
+        .map(|i| (clusters.root(i), i))
+        .into_group_map()
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|members| DuplicateCluster {
+            identical: members.iter().map(|&i| &aliases[i].media).all_equal(),
+            aliases: members.iter().map(|&i| aliases[i].name.clone()).collect(),
+        })
+        .sorted_by(|a, b| {
+            b.aliases
+                .len()
+                .cmp(&a.aliases.len())
+                .then_with(|| a.aliases.cmp(&b.aliases))
+        })
+        .collect();
+
+    Ok(clusters)
+}
+
+/// Disjoint sets of alias indices.
+struct Clusters {
+    parents: Vec<usize>,
+}
+
+impl Clusters {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+        }
+    }
+
+    fn root(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        self.parents[a.max(b)] = a.min(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::{DynamicImage, ImageOutputFormat, RgbImage};
+
+    use std::io::Cursor;
+
+    use crate::probe;
+
+    /// A gradient with a few stripes, `seed` changes the stripes.
+    fn png(width: u32, height: u32, seed: u32) -> Vec<u8> {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            let x = x * 255 / width;
+            let y = y * 255 / height;
+            let stripe = if (x / 32 + seed) % 3 == 0 { 80 } else { 0 };
+            image::Rgb([(x + stripe).min(255) as u8, y as u8, 128])
+        });
+
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Png)
+            .unwrap();
+        data
+    }
+
+    fn hash(data: &[u8]) -> u64 {
+        perceptual_hash(data, &probe::probe(data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn scaled_copies_are_similar() {
+        let original = hash(&png(200, 100, 0));
+        let scaled = hash(&png(100, 50, 0));
+        let other = hash(&png(200, 100, 1));
+
+        assert!(distance(original, scaled) <= SIMILARITY_THRESHOLD);
+        assert!(distance(original, other) > SIMILARITY_THRESHOLD);
+    }
+
+    #[test]
+    fn does_not_decode_huge_images() {
+        let data = png(10, 10, 0);
+        let info = ImageInfo {
+            width: 100_000,
+            height: 100_000,
+            ..probe::probe(&data).unwrap()
+        };
+
+        assert!(matches!(
+            perceptual_hash(&data, &info),
+            Err(ImageError::Limits(_))
+        ));
+    }
+
+    #[test]
+    fn counts_differing_bits() {
+        assert_eq!(distance(0, 0), 0);
+        assert_eq!(distance(0b1011, 0b0010), 2);
+        assert_eq!(distance(u64::MAX, 0), 64);
+    }
+
+    /// A database with text aliases `a` and `b` with the same content, an alias `pic` by alice and
+    /// a pending alias `copy` by bob with similar media, and an alias `video` with unhashed media.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0), ('bob', 0);
+                INSERT INTO media (hash, mime, size, uploaded_by, created_at, phash)
+                VALUES ('pic', 'image/png', 1, 'alice', 0, 0),
+                    ('copy', 'image/png', 1, 'bob', 0, 3),
+                    ('video', 'video/mp4', 1, 'alice', 0, NULL);
+                INSERT INTO aliases (name, content, type, author, created_at, media)
+                VALUES ('a', 'same', 1, 'alice', 0, NULL),
+                    ('b', 'same', 1, 'alice', 1, NULL),
+                    ('pic', 'pic.png', 1, 'alice', 2, 'pic'),
+                    ('copy', 'copy.png', 1, 'bob', 3, 'copy'),
+                    ('video', 'video.mp4', 1, 'alice', 4, 'video');
+                INSERT INTO alias_moderation (alias, status, submitted_at)
+                VALUES ('copy', 'pending', 0);",
+            )
+            .unwrap();
+        })
+        .await;
+        db
+    }
+
+    #[tokio::test]
+    async fn only_rejects_identical_media() {
+        let db = setup().await;
+        db.call(|conn| {
+            let viewer = Viewer::new("alice", &[]);
+            assert!(check_identical(conn, "c", None, &viewer).is_ok());
+            assert!(check_identical(conn, "pic", Some("pic"), &viewer).is_ok());
+            assert!(matches!(
+                check_identical(conn, "c", Some("pic"), &viewer),
+                Err(Error::DuplicateContent(name)) if name == "pic"
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn ignores_aliases_the_viewer_cant_see() {
+        let db = setup().await;
+        db.call(|conn| {
+            let alice = Viewer::new("alice", &[]);
+            let bob = Viewer::new("bob", &[]);
+
+            assert!(check_identical(conn, "c", Some("copy"), &alice).is_ok());
+            assert!(check_identical(conn, "c", Some("copy"), &bob).is_err());
+
+            let names = |viewer| {
+                find_similar(conn, "c", "pic", viewer)
+                    .unwrap()
+                    .into_iter()
+                    .map(|alias| (alias.name, alias.distance))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(names(&alice), [("pic".to_owned(), 0)]);
+            assert_eq!(names(&bob), [("pic".to_owned(), 0), ("copy".to_owned(), 2)]);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn clusters_aliases_by_media() {
+        let db = setup().await;
+        let clusters = db
+            .call(|conn| get_clusters(conn, &Viewer::new("bob", &[])).unwrap())
+            .await;
+
+        assert_eq!(clusters.len(), 1);
+        assert_eq!(clusters[0].aliases, ["copy", "pic"]);
+        assert!(!clusters[0].identical);
+    }
+
+    #[test]
+    fn joins_clusters_transitively() {
+        let mut clusters = Clusters::new(5);
+        clusters.join(0, 3);
+        clusters.join(3, 4);
+        clusters.join(1, 2);
+
+        assert_eq!(clusters.root(4), clusters.root(0));
+        assert_eq!(clusters.root(2), clusters.root(1));
+        assert_ne!(clusters.root(0), clusters.root(1));
+
+        clusters.join(2, 4);
+        assert!((0..5).all(|i| clusters.root(i) == 0));
+    }
+}
@@ -1,5 +1,8 @@
 use axum::{
-    extract::rejection::JsonRejection,
+    extract::{
+        multipart::MultipartRejection,
+        rejection::{JsonRejection, QueryRejection},
+    },
     http::StatusCode,
     response::{IntoResponse, Response},
     Json,
@@ -16,12 +19,31 @@ pub enum Error {
     #[error("Unathorized")]
     Unathorized,
 
+    #[error("Only the author of the alias or users in the {group} group can {action} it")]
+    Forbidden {
+        action: &'static str,
+        group: &'static str,
+    },
+
+    #[error("Only users in the {group} group can {action} the aliases of other users")]
+    ForbiddenForOthers {
+        action: &'static str,
+        group: &'static str,
+    },
+
     #[error("{field} should not be longer than {maximum_length} characters")]
     TooManyCharacters {
         field: &'static str,
         maximum_length: u64,
     },
 
+    #[error("{field} should be between {minimum} and {maximum}")]
+    OutOfRange {
+        field: &'static str,
+        minimum: u64,
+        maximum: u64,
+    },
+
     #[error("The supplied quote id does not exist")]
     InvalidQuoteId,
 
@@ -31,6 +53,63 @@ pub enum Error {
     #[error("A tag with that name already exists")]
     TagExists,
 
+    #[error("'{0}' is not a valid tag name, tags can not contain whitespace or commas")]
+    InvalidTagName(String),
+
+    #[error("An alias with that name already exists")]
+    AliasExists,
+
+    #[error("An alias with that name is in the trash, restore it instead")]
+    AliasInTrash,
+
+    #[error("The alias {0} already hosts the same media, add a synonym to it instead")]
+    DuplicateContent(String),
+
+    #[error("You already reported the alias {0}")]
+    AlreadyReported(String),
+
+    #[error("The alias {0} is not waiting for moderation")]
+    NotPending(String),
+
+    #[error("There is no alias type named {0}")]
+    UnknownAliasType(String),
+
+    #[error("The alias type {0} is retired and can not be used for new aliases")]
+    AliasTypeRetired(String),
+
+    #[error("An alias type with that name already exists")]
+    AliasTypeExists,
+
+    #[error("'{0}' is not a valid alias type name, names can only contain letters and numbers and must start with a letter")]
+    InvalidAliasTypeName(String),
+
+    #[error("The built-in alias type {name} can not be {action}")]
+    BuiltinAliasType { name: String, action: &'static str },
+
+    #[error("The uploaded file is not a PNG, JPEG, GIF or WebP image")]
+    UnsupportedMedia,
+
+    #[error("The uploaded file is larger than {maximum} bytes")]
+    MediaTooLarge { maximum: usize },
+
+    #[error("Media is not hosted by this server")]
+    MediaNotHosted,
+
+    #[error("Invalid upload: {0}")]
+    InvalidUpload(String),
+
+    #[error("The content of the alias is not a URL")]
+    NotAUrl,
+
+    #[error("Fetching content from {0} is not allowed")]
+    ProxyBlocked(String),
+
+    #[error("{0}")]
+    ProxyFailed(String),
+
+    #[error("'{0}' is not a valid webhook URL, only http and https URLs are supported")]
+    InvalidWebhookUrl(String),
+
     #[error("The field {0} is empty")]
     EmptyField(&'static str),
 
@@ -43,11 +122,38 @@ pub enum Error {
         field: &'static str,
     },
 
+    #[error("{0}")]
+    InvalidQuery(String),
+
+    #[error("Invalid template: {0}")]
+    InvalidTemplate(String),
+
+    #[error("Alias expansion loops back on itself: {}", .0.join(" -> "))]
+    AliasCycle(Vec<String>),
+
+    #[error("Alias expansion goes deeper than {max_depth} aliases: {}", .chain.join(" -> "))]
+    ExpansionTooDeep {
+        max_depth: usize,
+        chain: Vec<String>,
+    },
+
+    #[error("The expanded text is longer than {maximum} bytes")]
+    ExpansionTooLong { maximum: usize },
+
+    #[error("The text needs more than {maximum} alias lookups to expand")]
+    TooManyLookups { maximum: usize },
+
     #[error("Internal Server Error")]
     InternalError(#[from] anyhow::Error),
 
     #[error("{0}")]
     JsonRejection(#[from] JsonRejection),
+
+    #[error("{0}")]
+    QueryRejection(#[from] QueryRejection),
+
+    #[error("{0}")]
+    MultipartRejection(#[from] MultipartRejection),
 }
 
 impl IntoResponse for Error {
@@ -55,6 +161,12 @@ impl IntoResponse for Error {
         let status = match &self {
             Error::Unathorized => StatusCode::UNAUTHORIZED,
             Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Forbidden { .. } | Error::ForbiddenForOthers { .. } => StatusCode::FORBIDDEN,
+            Error::UnsupportedMedia => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Error::MediaTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::ProxyBlocked(_) => StatusCode::FORBIDDEN,
+            Error::ProxyFailed(_) => StatusCode::BAD_GATEWAY,
+            Error::MediaNotHosted => StatusCode::NOT_IMPLEMENTED,
             Error::InternalError(e) => {
                 let err = e
                     .chain()
@@ -65,9 +177,32 @@ impl IntoResponse for Error {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
             Error::TooManyCharacters { .. }
+            | Error::OutOfRange { .. }
             | Error::JsonRejection(_)
+            | Error::QueryRejection(_)
+            | Error::MultipartRejection(_)
+            | Error::InvalidUpload(_)
+            | Error::NotAUrl
+            | Error::InvalidWebhookUrl(_)
+            | Error::InvalidQuery(_)
+            | Error::InvalidTemplate(_)
+            | Error::AliasCycle(_)
+            | Error::ExpansionTooDeep { .. }
+            | Error::ExpansionTooLong { .. }
+            | Error::TooManyLookups { .. }
             | Error::InvalidQuoteId
             | Error::TagExists
+            | Error::InvalidTagName(_)
+            | Error::AliasExists
+            | Error::AliasInTrash
+            | Error::DuplicateContent(_)
+            | Error::NotPending(_)
+            | Error::AlreadyReported(_)
+            | Error::UnknownAliasType(_)
+            | Error::AliasTypeRetired(_)
+            | Error::AliasTypeExists
+            | Error::InvalidAliasTypeName(_)
+            | Error::BuiltinAliasType { .. }
             | Error::EmptyField(_)
             | Error::EmptyArrayElement(_)
             | Error::EmptyArrayField { .. }
@@ -81,6 +216,10 @@ impl IntoResponse for Error {
                 JsonRejection::JsonSyntaxError(e) => e.source().unwrap().to_string(),
                 _ => rej.to_string(),
             }
+        } else if let Error::QueryRejection(rej) = self {
+            rej.body_text()
+        } else if let Error::MultipartRejection(rej) = self {
+            rej.body_text()
         } else {
             self.to_string()
         };
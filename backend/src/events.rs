@@ -0,0 +1,312 @@
+//! Pushes alias changes to connected clients over Server-Sent Events or a WebSocket, so they don't
+//! have to refetch the alias list to learn about new aliases.
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Query, Extension};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::alias::Alias;
+use crate::error::Error;
+use crate::moderation::Viewer;
+use crate::AppState;
+
+/// How many of the latest events are kept so clients can catch up after reconnecting.
+const LOG_SIZE: usize = 1000;
+
+/// How many events a slow client can fall behind before it is told to reset.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// The message sent instead of an event when a client missed events which can't be replayed, it
+/// should refetch the aliases it cares about.
+const RESET_MESSAGE: &str = r#"{"type":"reset"}"#;
+
+/// What happened to an alias. Clients should treat `created` and `updated` alike and insert or
+/// replace the alias, since an alias which becomes visible to some users, like one approved by a
+/// moderator, is only new to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum AliasEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl AliasEventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            AliasEventKind::Created => "created",
+            AliasEventKind::Updated => "updated",
+            AliasEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// A change to an alias pushed to clients.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct AliasEvent {
+    /// Increases with every event, pass the last one received as `Last-Event-ID` to catch up
+    /// after reconnecting.
+    #[schema(example = 1670802822000001u64)]
+    pub id: u64,
+
+    #[serde(rename = "type")]
+    pub kind: AliasEventKind,
+
+    /// The alias after the change, or before it for deleted aliases.
+    pub alias: Alias,
+
+    /// The name the alias had before it was renamed.
+    #[schema(example = "funny.png")]
+    pub previous_name: Option<String>,
+
+    /// A unix timestamp of when the change was made.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+}
+
+enum StreamItem {
+    Event(Arc<AliasEvent>),
+    /// The client missed events which are no longer in the log.
+    Reset,
+}
+
+/// Hands out alias events to every connected client and keeps the latest ones around.
+pub struct EventHub {
+    sender: broadcast::Sender<Arc<AliasEvent>>,
+    log: Mutex<EventLog>,
+}
+
+struct EventLog {
+    events: VecDeque<Arc<AliasEvent>>,
+    next_id: u64,
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        // Ids start at the time the server started, so ids from before a restart are older than
+        // any event in the log and clients reconnecting with one are told to reset.
+        let started = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_millis() as u64;
+
+        Self {
+            sender,
+            log: Mutex::new(EventLog {
+                events: VecDeque::with_capacity(LOG_SIZE),
+                next_id: started * 1000,
+            }),
+        }
+    }
+
+    /// Sends an event to every connected client. This should be called on the database thread right
+    /// after the change is committed, so the ids of the events are in the order of the changes.
+    pub(crate) fn publish(
+        &self,
+        kind: AliasEventKind,
+        alias: Alias,
+        previous_name: Option<String>,
+    ) {
+        let mut log = self.log.lock().unwrap();
+
+        let event = Arc::new(AliasEvent {
+            id: log.next_id,
+            kind,
+            alias,
+            previous_name,
+            created_at: SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs(),
+        });
+        log.next_id += 1;
+
+        if log.events.len() == LOG_SIZE {
+            log.events.pop_front();
+        }
+        log.events.push_back(event.clone());
+
+        // Nobody might be listening, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// The events after `last_event_id` followed by every new event.
+    fn subscribe(&self, last_event_id: Option<u64>) -> impl Stream<Item = StreamItem> {
+        // Subscribing while holding the log means no event is missed or sent twice.
+        let log = self.log.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let mut missed = Vec::new();
+        if let Some(last_event_id) = last_event_id {
+            let oldest = log.events.front().map_or(log.next_id, |event| event.id);
+            if last_event_id.saturating_add(1) < oldest {
+                missed.push(StreamItem::Reset);
+            } else {
+                missed.extend(
+                    log.events
+                        .iter()
+                        .filter(|event| event.id > last_event_id)
+                        .cloned()
+                        .map(StreamItem::Event),
+                );
+            }
+        }
+        drop(log);
+
+        let live = futures::stream::unfold(receiver, |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(event) => Some((StreamItem::Event(event), receiver)),
+                Err(RecvError::Lagged(_)) => Some((StreamItem::Reset, receiver)),
+                Err(RecvError::Closed) => None,
+            }
+        });
+
+        futures::stream::iter(missed).chain(live)
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct EventsQuery {
+    /// The id of the last event received, for WebSocket clients which can't set the
+    /// `Last-Event-ID` header.
+    pub last_event_id: Option<u64>,
+}
+
+/// Get a live stream of alias creations, updates and deletions.
+/// # Note
+/// Responds with Server-Sent Events, or upgrades to a WebSocket which is sent each event as a
+/// JSON text message. Each event is an [`AliasEvent`], SSE events are named after its type. Events
+/// after the `Last-Event-ID` header or `lastEventId` parameter are replayed from the latest 1000
+/// events. When events were missed which can't be replayed a `reset` event with
+/// `{"type":"reset"}` is sent instead, after which the client should refetch the aliases. Aliases
+/// waiting for moderation or hidden by reports are only sent to their author and moderators, the
+/// others are sent a deletion when an alias stops being visible to them.
+#[utoipa::path(
+    get,
+    path = "/api/alias/events",
+    responses(
+        (status = 200, description = "The event stream is returned.", content_type = "text/event-stream", body = AliasEvent),
+        (status = 101, description = "The connection was upgraded to a WebSocket."),
+        (status = 400, description = "The last event id is not a number."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(EventsQuery),
+)]
+pub async fn get_alias_events(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    websocket: Option<WebSocketUpgrade>,
+    headers: HeaderMap,
+    query: Result<Query<EventsQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            let last_event_id = match headers.get("last-event-id") {
+                Some(value) => Some(
+                    value
+                        .to_str()
+                        .ok()
+                        .and_then(|value| value.trim().parse().ok())
+                        .ok_or_else(|| {
+                            Error::InvalidQuery("Last-Event-ID must be a number".into())
+                        })?,
+                ),
+                None => query.last_event_id,
+            };
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let messages = state
+                .events
+                .subscribe(last_event_id)
+                .filter(move |item| {
+                    let visible = match item {
+                        StreamItem::Event(event) => viewer.can_see(&event.alias),
+                        StreamItem::Reset => true,
+                    };
+                    futures::future::ready(visible)
+                })
+                .map(|item| match item {
+                    StreamItem::Event(event) => {
+                        let data = serde_json::to_string(event.as_ref())
+                            .context("Failed to serialize alias event")?;
+                        Ok::<_, Error>((Some(event.id), event.kind.as_str(), data))
+                    }
+                    StreamItem::Reset => Ok((None, "reset", RESET_MESSAGE.to_owned())),
+                });
+
+            let response: Response = match websocket {
+                Some(websocket) => websocket
+                    .on_upgrade(|socket| forward(socket, messages))
+                    .into_response(),
+                None => {
+                    let events = messages.filter_map(|message| {
+                        let event = message.ok().map(|(id, name, data)| {
+                            let event = Event::default().event(name).data(data);
+                            let event = match id {
+                                Some(id) => event.id(id.to_string()),
+                                None => event,
+                            };
+                            Ok::<_, Infallible>(event)
+                        });
+                        futures::future::ready(event)
+                    });
+
+                    Sse::new(events)
+                        .keep_alive(KeepAlive::default())
+                        .into_response()
+                }
+            };
+
+            Ok::<_, Error>(response)
+        })
+        .await
+}
+
+/// Sends the messages to the socket until either side goes away.
+async fn forward(
+    mut socket: WebSocket,
+    messages: impl Stream<Item = Result<(Option<u64>, &'static str, String), Error>>,
+) {
+    futures::pin_mut!(messages);
+
+    loop {
+        tokio::select! {
+            message = messages.next() => {
+                let data = match message {
+                    Some(Ok((_, _, data))) => data,
+                    Some(Err(_)) => continue,
+                    None => break,
+                };
+                if socket.send(Message::Text(data)).await.is_err() {
+                    break;
+                }
+            }
+            received = socket.recv() => {
+                // Messages from the client are ignored, only closing the socket matters.
+                match received {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
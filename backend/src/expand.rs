@@ -0,0 +1,1130 @@
+//! Replacement of alias references such as `!fb` in text with the content of the alias.
+
+use anyhow::Context;
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use axum::{Extension, Json};
+use itertools::Itertools;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::alias::{self, Alias};
+use crate::alias_type::AliasType;
+use crate::error::Error;
+use crate::moderation::{ModerationStatus, Viewer};
+use crate::template::{parse_arguments, Arguments, Template};
+use crate::util::check_length;
+use crate::AppState;
+
+/// The maximum length of text that can be expanded in a single request.
+pub const MAX_TEXT_LENGTH: u64 = 20_000;
+
+/// The maximum depth that can be requested for recursive expansion.
+pub const MAX_DEPTH: usize = 10;
+
+/// The maximum length in bytes of the text produced by a single request, nested aliases can
+/// otherwise make the output grow exponentially with the depth.
+pub const MAX_OUTPUT_LENGTH: usize = 200_000;
+
+/// The maximum number of names looked up for a single request. Every prefix in the text can lead
+/// to a lookup for each trailing punctuation character, so without a limit a long run of
+/// punctuation keeps the database busy.
+pub const MAX_LOOKUPS: usize = 10_000;
+
+/// Decides which characters may come right before the prefix of an alias reference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum WordBoundary {
+    /// References need to be at the start of the text or after whitespace.
+    Whitespace,
+    /// References need to be at the start of the text or after a character which is not a letter
+    /// or a number, so `(!fb)` is expanded but `abc!fb` is not.
+    #[default]
+    NonAlphanumeric,
+    /// References are expanded wherever they appear.
+    Anywhere,
+}
+
+impl WordBoundary {
+    fn allows(self, previous: Option<char>) -> bool {
+        match (self, previous) {
+            (_, None) | (WordBoundary::Anywhere, _) => true,
+            (WordBoundary::Whitespace, Some(c)) => c.is_whitespace(),
+            (WordBoundary::NonAlphanumeric, Some(c)) => !c.is_alphanumeric(),
+        }
+    }
+}
+
+/// The format that alias content is written in when it replaces a reference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum OutputFormat {
+    /// The content is inserted as is.
+    #[default]
+    Raw,
+    /// Aliases containing images are inserted as markdown images.
+    Markdown,
+    /// The whole text is escaped as HTML and aliases containing images are inserted as `<img>`
+    /// elements.
+    Html,
+}
+
+/// Options for how alias references are found and replaced.
+#[derive(Debug, Clone)]
+pub struct ExpandOptions {
+    /// The character which starts an alias reference.
+    pub prefix: char,
+    pub boundary: WordBoundary,
+    /// Whether a prefix preceded by a backslash is written out as a literal prefix instead of
+    /// being expanded, `\!fb` becomes `!fb`.
+    pub escape: bool,
+    pub format: OutputFormat,
+    /// Whether references inside the content of text aliases are expanded as well.
+    pub recursive: bool,
+    /// How many text aliases can be nested inside each other when expanding recursively.
+    pub max_depth: usize,
+    /// The maximum length of the expanded text in bytes.
+    pub max_length: usize,
+    /// The length in bytes of the longest name an alias can be referenced by, longer names aren't
+    /// looked up.
+    pub max_name_length: usize,
+    /// How often names can be looked up before expansion fails with [`Error::TooManyLookups`].
+    pub max_lookups: usize,
+}
+
+impl Default for ExpandOptions {
+    fn default() -> Self {
+        Self {
+            prefix: '!',
+            boundary: WordBoundary::default(),
+            escape: true,
+            format: OutputFormat::default(),
+            recursive: false,
+            max_depth: 5,
+            max_length: MAX_OUTPUT_LENGTH,
+            max_name_length: usize::MAX,
+            max_lookups: MAX_LOOKUPS,
+        }
+    }
+}
+
+/// The result of expanding the aliases in a text.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct Expansion {
+    /// The text with all alias references replaced.
+    #[schema(example = "I like foobar")]
+    pub text: String,
+
+    /// The alias references which were found in the text.
+    pub matches: Vec<ExpandMatch>,
+}
+
+/// An alias reference found in a text.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct ExpandMatch {
+    /// The byte offset of the prefix in the input text.
+    #[schema(example = 7)]
+    pub start: usize,
+
+    /// The byte offset of the end of the reference in the input text.
+    #[schema(example = 10)]
+    pub end: usize,
+
+    /// The name of the alias which was referenced.
+    #[schema(example = "fb")]
+    pub name: String,
+
+    /// A category describing the type of content in the alias.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The content of the alias.
+    #[schema(example = "foobar")]
+    pub content: String,
+}
+
+/// Replaces all alias references in `text` with the content of the referenced aliases.
+///
+/// References are found by looking for the prefix followed by a name which ends at the next
+/// whitespace. If no alias with that name is found, trailing punctuation is removed from the name
+/// one character at a time so that references at the end of a sentence, like `!fb.`, still match.
+/// `lookup` is called with each possible name and should return the alias with that name if it
+/// exists.
+///
+/// Text aliases can be passed arguments for their placeholders as a list right after the name,
+/// like `!slap(alice, by=bob)`, see [`crate::template`].
+///
+/// When expanding recursively the content of text aliases is expanded before it is inserted. This
+/// fails with [`Error::AliasCycle`] if an alias ends up referencing itself and with
+/// [`Error::ExpansionTooDeep`] if more than `max_depth` aliases are nested. Expansion stops with
+/// [`Error::ExpansionTooLong`] as soon as the text grows longer than `max_length`.
+///
+/// Names longer than `max_name_length` are never passed to `lookup`, every other name only once.
+/// Expansion fails with [`Error::TooManyLookups`] once more than `max_lookups` names were needed.
+///
+/// # Example
+/// ```markdown
+/// Alias: "fb", "foobar"
+/// Text: "I like !fb" -> "I like foobar"
+/// ```
+pub fn expand<F>(text: &str, options: &ExpandOptions, lookup: F) -> Result<Expansion, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    let mut lookup = limit_lookups(lookup, options.max_lookups);
+    expand_nested(text, options, &mut lookup, &mut Vec::new())
+}
+
+/// Wraps `lookup` so that every name is only looked up once and the calls fail with
+/// [`Error::TooManyLookups`] after `maximum` of them, no matter if the name was looked up before.
+fn limit_lookups<F>(
+    mut lookup: F,
+    maximum: usize,
+) -> impl FnMut(&str) -> Result<Option<Alias>, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    let mut found = HashMap::new();
+    let mut count = 0;
+
+    move |name| {
+        count += 1;
+        if count > maximum {
+            return Err(Error::TooManyLookups { maximum });
+        }
+
+        if let Some(alias) = found.get(name) {
+            return Ok(Option::clone(alias));
+        }
+        let alias = lookup(name)?;
+        found.insert(name.to_owned(), alias.clone());
+        Ok(alias)
+    }
+}
+
+/// Expands `text` which is the content of the last alias in `chain`.
+fn expand_nested<F>(
+    text: &str,
+    options: &ExpandOptions,
+    lookup: &mut F,
+    chain: &mut Vec<String>,
+) -> Result<Expansion, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    let mut output = String::with_capacity(text.len());
+    let mut matches = Vec::new();
+
+    let mut chars = text.char_indices().peekable();
+    let mut previous = None;
+    // The word the last reference was looked for in, several prefixes can share it.
+    let mut word = Word::default();
+
+    while let Some((i, c)) = chars.next() {
+        if options.escape && c == '\\' && chars.peek().map(|(_, c)| *c) == Some(options.prefix) {
+            chars.next();
+            push_text(&mut output, options.prefix, options.format);
+            previous = Some(options.prefix);
+            continue;
+        }
+
+        if c == options.prefix && options.boundary.allows(previous) {
+            let start = i + c.len_utf8();
+            if start > word.end {
+                word = Word::at(text, start);
+            }
+
+            if let Some((alias, arguments, end)) =
+                find_reference(text, start, &word, options.max_name_length, lookup)?
+            {
+                let content = if alias.typ.is_text() {
+                    // Content which isn't a valid template was stored before templates existed
+                    // and is used as is.
+                    Template::parse(&alias.content)
+                        .map(|template| template.render(&arguments))
+                        .unwrap_or_else(|_| alias.content.clone())
+                } else {
+                    alias.content.clone()
+                };
+
+                if options.recursive && alias.typ.is_text() {
+                    if chain.contains(&alias.name) {
+                        chain.push(alias.name);
+                        return Err(Error::AliasCycle(chain.clone()));
+                    }
+                    if chain.len() >= options.max_depth {
+                        chain.push(alias.name);
+                        return Err(Error::ExpansionTooDeep {
+                            max_depth: options.max_depth,
+                            chain: chain.clone(),
+                        });
+                    }
+
+                    chain.push(alias.name.clone());
+                    let nested = expand_nested(&content, options, lookup, chain)?;
+                    chain.pop();
+
+                    output.push_str(&nested.text);
+                } else {
+                    render(
+                        &mut output,
+                        &alias.name,
+                        &content,
+                        &alias.typ,
+                        options.format,
+                    );
+                }
+
+                while matches!(chars.peek(), Some((j, _)) if *j < end) {
+                    chars.next();
+                }
+                previous = text[..end].chars().last();
+
+                matches.push(ExpandMatch {
+                    start: i,
+                    end,
+                    name: alias.name,
+                    typ: alias.typ,
+                    content: alias.content,
+                });
+
+                if output.len() > options.max_length {
+                    return Err(Error::ExpansionTooLong {
+                        maximum: options.max_length,
+                    });
+                }
+                continue;
+            }
+        }
+
+        push_text(&mut output, c, options.format);
+        previous = Some(c);
+
+        if output.len() > options.max_length {
+            return Err(Error::ExpansionTooLong {
+                maximum: options.max_length,
+            });
+        }
+    }
+
+    Ok(Expansion {
+        text: output,
+        matches,
+    })
+}
+
+/// The bounds of the word a reference is looked for in, which ends at the next whitespace.
+#[derive(Debug, Default)]
+struct Word {
+    end: usize,
+    /// The end of the last character of the word which isn't ASCII punctuation, names need to
+    /// reach at least this far. Can be before the start of the reference.
+    name_end: usize,
+}
+
+impl Word {
+    fn at(text: &str, start: usize) -> Self {
+        let end = text[start..]
+            .find(char::is_whitespace)
+            .map_or(text.len(), |end| start + end);
+        let name_end = text[..end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !c.is_ascii_punctuation())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+
+        Self { end, name_end }
+    }
+}
+
+/// Finds the alias referenced by the text starting at `start` inside `word`, returning it together
+/// with the arguments passed to it and the end of the reference.
+fn find_reference<F>(
+    text: &str,
+    start: usize,
+    word: &Word,
+    max_name_length: usize,
+    lookup: &mut F,
+) -> Result<Option<(Alias, Arguments, usize)>, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    let token = &text[start..word.end];
+
+    // Arguments can contain whitespace so they are parsed from the text instead of the token.
+    let open = token
+        .bytes()
+        .take(max_name_length.saturating_add(1))
+        .position(|b| b == b'(');
+    if let Some(open) = open.filter(|open| *open > 0) {
+        if let Some(alias) = lookup(&token[..open])? {
+            if alias.typ.is_text() {
+                if let Some((arguments, length)) = parse_arguments(&text[start + open..]) {
+                    return Ok(Some((alias, arguments, start + open + length)));
+                }
+            }
+        }
+    }
+
+    let shortest = word.name_end.saturating_sub(start);
+    Ok(find_alias(token, shortest, max_name_length, lookup)?
+        .map(|(alias, length)| (alias, Arguments::default(), start + length)))
+}
+
+/// Looks up the longest alias name at the start of `token`, only allowing trailing punctuation to
+/// be left over, so the name is at least `shortest` bytes long. Names longer than
+/// `max_name_length` are skipped. Returns the alias together with the length of the name in
+/// `token`, which differs from the name of the alias when it was found through a redirect.
+fn find_alias<F>(
+    token: &str,
+    shortest: usize,
+    max_name_length: usize,
+    lookup: &mut F,
+) -> Result<Option<(Alias, usize)>, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    if shortest > max_name_length {
+        return Ok(None);
+    }
+
+    // Everything after the first `shortest` bytes is ASCII punctuation, so the token can be cut
+    // at any byte after them.
+    let mut token = &token[..token.len().min(max_name_length)];
+    while !token.is_empty() && token.len() >= shortest {
+        if let Some(alias) = lookup(token)? {
+            return Ok(Some((alias, token.len())));
+        }
+
+        token = &token[..token.len() - 1];
+    }
+
+    Ok(None)
+}
+
+fn render(output: &mut String, name: &str, content: &str, typ: &AliasType, format: OutputFormat) {
+    let is_image = !typ.is_text();
+
+    match format {
+        OutputFormat::Markdown if is_image => output.push_str(&format!(
+            "![{}](<{}>)",
+            escape_markdown_label(name),
+            escape_markdown_url(content),
+        )),
+        OutputFormat::Html if is_image => output.push_str(&format!(
+            "<img src=\"{}\" alt=\"{}\" title=\"{}\">",
+            escape_html(content),
+            escape_html(name),
+            escape_html(name),
+        )),
+        OutputFormat::Html => output.push_str(&escape_html(content)),
+        OutputFormat::Raw | OutputFormat::Markdown => output.push_str(content),
+    }
+}
+
+fn push_text(output: &mut String, c: char, format: OutputFormat) {
+    if format == OutputFormat::Html {
+        output.push_str(&escape_html(c.encode_utf8(&mut [0; 4])));
+    } else {
+        output.push(c);
+    }
+}
+
+/// Escapes the characters which would end the label of a Markdown link.
+fn escape_markdown_label(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\\' | '[' | ']' => format!("\\{c}"),
+            c => c.to_string(),
+        })
+        .join("")
+}
+
+/// Percent-encodes the characters which can't be part of a Markdown link destination enclosed in
+/// angle brackets.
+fn escape_markdown_url(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '<' | '>' | '\\' => format!("%{:02X}", c as u32),
+            c if c.is_ascii_whitespace() || c.is_ascii_control() => format!("%{:02X}", c as u32),
+            c => c.to_string(),
+        })
+        .join("")
+}
+
+fn escape_html(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .join("")
+}
+
+/// Checks that storing `content` as the content of the text alias `name` doesn't make the alias
+/// expand into itself when expanded recursively.
+pub fn check_cycles(conn: &Connection, name: &str, content: &str) -> Result<(), Error> {
+    let max_name_length = longest_name(conn)?.max(name.len());
+    find_cycle(name, content, max_name_length, |n| lookup(conn, n))
+}
+
+/// Walks the references of the text aliases reachable from `content` depth first, failing with
+/// [`Error::AliasCycle`] if one of them leads back to an alias which is still being walked. Every
+/// alias is only walked once, no matter how often it is referenced. All of the walk shares a
+/// budget of [`MAX_LOOKUPS`] lookups.
+fn find_cycle<F>(name: &str, content: &str, max_name_length: usize, lookup: F) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    let mut lookup = limit_lookups(lookup, MAX_LOOKUPS);
+    let mut lookup = |n: &str| {
+        let alias = lookup(n)?;
+
+        // The alias can also be referenced through one of its synonyms.
+        if n == name || matches!(&alias, Some(alias) if alias.name == name) {
+            // The alias might not be stored yet or still have its old content.
+            Ok(Some(Alias {
+                name: name.to_owned(),
+                content: content.to_owned(),
+                typ: AliasType::text(),
+                author: String::new(),
+                created_at: 0,
+                synonyms: Vec::new(),
+                tags: Vec::new(),
+                media: None,
+                renditions: None,
+                status: ModerationStatus::Approved,
+                hidden_at: None,
+                updated_at: 0,
+                change_seq: 0,
+            }))
+        } else {
+            Ok(alias)
+        }
+    };
+
+    let options = ExpandOptions {
+        max_length: usize::MAX,
+        max_name_length,
+        ..ExpandOptions::default()
+    };
+    let mut visited = HashSet::from([name.to_owned()]);
+    walk(
+        content,
+        &options,
+        &mut lookup,
+        &mut visited,
+        &mut vec![name.to_owned()],
+    )
+}
+
+/// Walks the references in `content`, which is the content of the last alias in `chain`.
+fn walk<F>(
+    content: &str,
+    options: &ExpandOptions,
+    lookup: &mut F,
+    visited: &mut HashSet<String>,
+    chain: &mut Vec<String>,
+) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    for (name, content) in references(content, options, lookup)? {
+        if chain.contains(&name) {
+            chain.push(name);
+            return Err(Error::AliasCycle(chain.clone()));
+        }
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+
+        chain.push(name);
+        walk(&content, options, lookup, visited, chain)?;
+        chain.pop();
+    }
+
+    Ok(())
+}
+
+/// Finds the text aliases which are expanded inside the content of a text alias, returning their
+/// names and content. References passed as arguments end up in the content of the alias they are
+/// passed to, so they count as references of the alias passing them.
+fn references<F>(
+    content: &str,
+    options: &ExpandOptions,
+    lookup: &mut F,
+) -> Result<Vec<(String, String)>, Error>
+where
+    F: FnMut(&str) -> Result<Option<Alias>, Error>,
+{
+    // The content is checked the way it is expanded without any arguments.
+    let text = Template::parse(content)
+        .map(|template| template.render(&Arguments::default()))
+        .unwrap_or_else(|_| content.to_owned());
+
+    let expansion = expand(&text, options, &mut *lookup)?;
+
+    let mut found = Vec::new();
+    for m in expansion.matches {
+        if !m.typ.is_text() {
+            continue;
+        }
+
+        let reference = &text[m.start + options.prefix.len_utf8()..m.end];
+        if let Some(open) = reference.find('(') {
+            if let Some((arguments, _)) = parse_arguments(&reference[open..]) {
+                for argument in arguments.positional.iter().chain(arguments.named.values()) {
+                    found.extend(references(argument, options, lookup)?);
+                }
+            }
+        }
+
+        found.push((m.name, m.content));
+    }
+
+    Ok(found)
+}
+
+/// Returns the length in bytes of the longest name an alias can be referenced by, including
+/// synonyms and redirects.
+pub fn longest_name(conn: &Connection) -> Result<usize, Error> {
+    let length: i64 = conn
+        .query_row(
+            "SELECT COALESCE(MAX(length(CAST(name AS BLOB))), 0) FROM (
+                SELECT name FROM aliases
+                UNION ALL SELECT name FROM alias_synonyms
+                UNION ALL SELECT name FROM alias_redirects
+            )",
+            [],
+            |row| row.get(0),
+        )
+        .context("Failed to query the longest alias name")?;
+
+    Ok(length as usize)
+}
+
+/// Looks up an alias by name, returning `None` if it doesn't exist.
+pub fn lookup(conn: &Connection, name: &str) -> Result<Option<Alias>, Error> {
+    match alias::get_by_name(conn, name.to_owned()) {
+        Ok(alias) => Ok(Some(alias)),
+        Err(Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct ExpandRequest {
+    /// The text containing alias references.
+    #[schema(example = "I like !fb")]
+    pub text: String,
+
+    /// The character which starts an alias reference, defaults to `!`.
+    #[schema(example = "!")]
+    pub prefix: Option<char>,
+
+    /// Which characters may come before a reference, defaults to `nonAlphanumeric`.
+    pub boundary: Option<WordBoundary>,
+
+    /// Whether `\!fb` is written as `!fb` instead of being expanded, defaults to `true`.
+    pub escape: Option<bool>,
+
+    /// The format to insert the alias content in, defaults to `raw`.
+    pub format: Option<OutputFormat>,
+
+    /// Whether references inside text aliases are expanded as well, defaults to `false`.
+    pub recursive: Option<bool>,
+
+    /// How many text aliases can be nested when expanding recursively, defaults to 5.
+    #[schema(example = 5, maximum = 10)]
+    pub max_depth: Option<usize>,
+}
+
+impl From<&ExpandRequest> for ExpandOptions {
+    fn from(request: &ExpandRequest) -> Self {
+        let default = ExpandOptions::default();
+
+        Self {
+            prefix: request.prefix.unwrap_or(default.prefix),
+            boundary: request.boundary.unwrap_or(default.boundary),
+            escape: request.escape.unwrap_or(default.escape),
+            format: request.format.unwrap_or(default.format),
+            recursive: request.recursive.unwrap_or(default.recursive),
+            max_depth: request.max_depth.unwrap_or(default.max_depth),
+            max_length: default.max_length,
+            max_name_length: default.max_name_length,
+            max_lookups: default.max_lookups,
+        }
+    }
+}
+
+/// Replace alias references in a text with the alias content.
+/// # Note
+/// Every expanded alias counts as a use of the alias when sorting by popularity. Aliases waiting
+/// for moderation are only expanded for their author and moderators. The expanded text can be at
+/// most 200000 bytes long and expanding it can take at most 10000 lookups of alias names.
+#[utoipa::path(
+    post,
+    path = "/api/expand",
+    request_body = ExpandRequest,
+    responses(
+        (status = 200, description = "The expanded text and the references found in it.", body = Expansion),
+        (status = 400, description = "One of the values sent in is invalid, recursive expansion failed, the expanded text is too long or needs too many lookups."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn post_expand(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    request: Result<Json<ExpandRequest>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            check_length("text", Some(&request.text), MAX_TEXT_LENGTH)?;
+
+            if let Some(max_depth) = request.max_depth {
+                if max_depth == 0 || max_depth > MAX_DEPTH {
+                    return Err(Error::OutOfRange {
+                        field: "maxDepth",
+                        minimum: 1,
+                        maximum: MAX_DEPTH as u64,
+                    });
+                }
+            }
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let expansion = state
+                .db
+                .call(move |conn| {
+                    let options = ExpandOptions {
+                        max_name_length: longest_name(conn)?,
+                        ..ExpandOptions::from(&request)
+                    };
+                    expand(&request.text, &options, |name| {
+                        Ok(lookup(conn, name)?.filter(|alias| viewer.can_see(alias)))
+                    })
+                })
+                .await?;
+
+            for name in expansion.matches.iter().map(|m| &m.name).unique() {
+                state.uses.count(name);
+            }
+
+            Ok::<_, Error>(Json(expansion))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn alias(name: &str, typ: &str, content: &str) -> Alias {
+        Alias {
+            name: name.to_owned(),
+            content: content.to_owned(),
+            typ: AliasType::from(typ),
+            author: String::new(),
+            created_at: 0,
+            synonyms: Vec::new(),
+            tags: Vec::new(),
+            media: None,
+            renditions: None,
+            status: ModerationStatus::Approved,
+            hidden_at: None,
+            updated_at: 0,
+            change_seq: 0,
+        }
+    }
+
+    /// Looks up aliases from a list of `(name, type, content)`.
+    fn aliases(list: &[(&str, &str, &str)]) -> impl FnMut(&str) -> Result<Option<Alias>, Error> {
+        let aliases = list
+            .iter()
+            .map(|(name, typ, content)| (name.to_string(), alias(name, typ, content)))
+            .collect::<HashMap<_, _>>();
+
+        move |name| Ok(aliases.get(name).cloned())
+    }
+
+    fn expand_text(text: &str, options: &ExpandOptions) -> String {
+        let lookup = aliases(&[
+            ("fb", "text", "foobar"),
+            ("fb.png", "image", "https://example.com/fb.png"),
+            ("pic", "image", "https://example.com/a&b.png"),
+            ("quote", "text", "<b>"),
+        ]);
+
+        expand(text, options, lookup).unwrap().text
+    }
+
+    fn recursive() -> ExpandOptions {
+        ExpandOptions {
+            recursive: true,
+            ..ExpandOptions::default()
+        }
+    }
+
+    #[test]
+    fn replaces_references() {
+        let lookup = aliases(&[("fb", "text", "foobar")]);
+
+        let expansion = expand("I like !fb", &ExpandOptions::default(), lookup).unwrap();
+        assert_eq!(expansion.text, "I like foobar");
+        assert_eq!(expansion.matches.len(), 1);
+        assert_eq!(expansion.matches[0].start, 7);
+        assert_eq!(expansion.matches[0].end, 10);
+        assert_eq!(expansion.matches[0].name, "fb");
+    }
+
+    #[test]
+    fn leaves_unknown_references() {
+        let options = ExpandOptions::default();
+
+        assert_eq!(expand_text("!nothing here", &options), "!nothing here");
+        assert_eq!(expand_text("!", &options), "!");
+    }
+
+    #[test]
+    fn finds_the_longest_name_before_punctuation() {
+        let options = ExpandOptions::default();
+
+        assert_eq!(expand_text("!fb.", &options), "foobar.");
+        assert_eq!(expand_text("!fb?!", &options), "foobar?!");
+        assert_eq!(
+            expand_text("!fb.png", &options),
+            "https://example.com/fb.png"
+        );
+        assert_eq!(expand_text("!fbx", &options), "!fbx");
+    }
+
+    #[test]
+    fn find_alias_returns_the_matched_length() {
+        let mut lookup = aliases(&[("fb", "text", "foobar")]);
+
+        let (alias, length) = find_alias("fb...", 2, usize::MAX, &mut lookup)
+            .unwrap()
+            .unwrap();
+        assert_eq!(alias.name, "fb");
+        assert_eq!(length, 2);
+        assert!(find_alias("fbb", 3, usize::MAX, &mut lookup)
+            .unwrap()
+            .is_none());
+        assert!(find_alias("...", 0, usize::MAX, &mut lookup)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn skips_names_longer_than_the_longest_name() {
+        let mut lookup = aliases(&[("fb", "text", "foobar")]);
+        let options = ExpandOptions {
+            max_name_length: 2,
+            ..ExpandOptions::default()
+        };
+
+        let mut names = Vec::new();
+        let text = format!("!fb{} !{}", ".".repeat(10_000), "x".repeat(10_000));
+        let expansion = expand(&text, &options, |name| {
+            names.push(name.to_owned());
+            lookup(name)
+        })
+        .unwrap();
+
+        assert_eq!(expansion.matches.len(), 1);
+        assert!(expansion.text.starts_with("foobar..."));
+        assert_eq!(names, ["fb"]);
+    }
+
+    #[test]
+    fn limits_lookups_for_long_runs_of_punctuation() {
+        let mut lookup = aliases(&[("fb", "text", "foobar")]);
+        let options = ExpandOptions {
+            max_name_length: 2,
+            ..ExpandOptions::default()
+        };
+
+        // Every `!` starts a reference to `!!` or `!`, which are only looked up once.
+        let mut names = Vec::new();
+        let result = expand(&"!".repeat(20_000), &options, |name| {
+            names.push(name.to_owned());
+            lookup(name)
+        });
+
+        match result {
+            Err(Error::TooManyLookups { maximum }) => assert_eq!(maximum, MAX_LOOKUPS),
+            result => panic!("Expected too many lookups, got {result:?}"),
+        }
+        assert_eq!(names, ["!!", "!"]);
+
+        let options = ExpandOptions {
+            max_name_length: 2,
+            max_lookups: 100,
+            ..ExpandOptions::default()
+        };
+        assert!(matches!(
+            expand(&"! ".repeat(100), &options, aliases(&[])),
+            Ok(expansion) if expansion.text == "! ".repeat(100)
+        ));
+    }
+
+    #[test]
+    fn respects_word_boundaries() {
+        let with = |boundary| ExpandOptions {
+            boundary,
+            ..ExpandOptions::default()
+        };
+
+        let text = "a!fb (!fb) !fb";
+        assert_eq!(
+            expand_text(text, &with(WordBoundary::Whitespace)),
+            "a!fb (!fb) foobar"
+        );
+        assert_eq!(
+            expand_text(text, &with(WordBoundary::NonAlphanumeric)),
+            "a!fb (foobar) foobar"
+        );
+        assert_eq!(
+            expand_text(text, &with(WordBoundary::Anywhere)),
+            "afoobar (foobar) foobar"
+        );
+    }
+
+    #[test]
+    fn escapes_the_prefix() {
+        let options = ExpandOptions::default();
+        assert_eq!(expand_text("\\!fb !fb", &options), "!fb foobar");
+
+        let options = ExpandOptions {
+            escape: false,
+            ..ExpandOptions::default()
+        };
+        assert_eq!(expand_text("\\!fb", &options), "\\foobar");
+    }
+
+    #[test]
+    fn uses_a_custom_prefix() {
+        let options = ExpandOptions {
+            prefix: '$',
+            ..ExpandOptions::default()
+        };
+
+        assert_eq!(expand_text("$fb !fb", &options), "foobar !fb");
+    }
+
+    #[test]
+    fn formats_media() {
+        let with = |format| ExpandOptions {
+            format,
+            ..ExpandOptions::default()
+        };
+
+        assert_eq!(
+            expand_text("!pic !quote", &with(OutputFormat::Raw)),
+            "https://example.com/a&b.png <b>"
+        );
+        assert_eq!(
+            expand_text("!pic !quote", &with(OutputFormat::Markdown)),
+            "![pic](<https://example.com/a&b.png>) <b>"
+        );
+        assert_eq!(
+            expand_text("<!pic> !quote", &with(OutputFormat::Html)),
+            "&lt;<img src=\"https://example.com/a&amp;b.png\" alt=\"pic\" title=\"pic\">&gt; \
+            &lt;b&gt;"
+        );
+    }
+
+    #[test]
+    fn escapes_media_in_markdown() {
+        let mut output = String::new();
+        render(
+            &mut output,
+            "a]b[\\",
+            "https://example.com/a b.png?x=<y>)\n![z](https://evil.example)",
+            &AliasType::from("image"),
+            OutputFormat::Markdown,
+        );
+
+        assert_eq!(
+            output,
+            "![a\\]b\\[\\\\](<https://example.com/a%20b.png?x=%3Cy%3E)%0A![z](https://evil.example)>)"
+        );
+    }
+
+    #[test]
+    fn passes_arguments_to_templates() {
+        // Only text aliases take arguments, like before templates existed `!pic(x)` isn't a
+        // reference to `pic`.
+        let lookup = aliases(&[
+            ("slap", "text", "{0} was slapped by {by:nobody}"),
+            ("pic", "image", "https://example.com/pic.png"),
+        ]);
+
+        let expansion = expand(
+            "!slap(alice, by=bob) !slap(carol). !pic(x)",
+            &ExpandOptions::default(),
+            lookup,
+        )
+        .unwrap();
+        assert_eq!(
+            expansion.text,
+            "alice was slapped by bob carol was slapped by nobody. !pic(x)"
+        );
+        assert_eq!(expansion.matches[0].end, 20);
+    }
+
+    #[test]
+    fn expands_nested_text_aliases() {
+        let lookup = aliases(&[
+            ("greet", "text", "hello !who"),
+            ("who", "text", "!name"),
+            ("name", "text", "world"),
+            ("pic", "image", "https://example.com/pic.png"),
+        ]);
+
+        let expansion = expand("!greet !pic", &recursive(), lookup).unwrap();
+        assert_eq!(expansion.text, "hello world https://example.com/pic.png");
+        assert_eq!(
+            expansion
+                .matches
+                .iter()
+                .map(|m| &m.name)
+                .collect::<Vec<_>>(),
+            ["greet", "pic"]
+        );
+    }
+
+    #[test]
+    fn only_expands_the_top_level_by_default() {
+        let lookup = aliases(&[("greet", "text", "hello !who"), ("who", "text", "world")]);
+
+        let expansion = expand("!greet", &ExpandOptions::default(), lookup).unwrap();
+        assert_eq!(expansion.text, "hello !who");
+    }
+
+    #[test]
+    fn rejects_cycles_while_expanding() {
+        let lookup = aliases(&[("a", "text", "!b"), ("b", "text", "x !a")]);
+
+        match expand("!a", &recursive(), lookup) {
+            Err(Error::AliasCycle(chain)) => assert_eq!(chain, ["a", "b", "a"]),
+            result => panic!("expected a cycle, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_expansion_deeper_than_the_maximum() {
+        let lookup = aliases(&[
+            ("a", "text", "!b"),
+            ("b", "text", "!c"),
+            ("c", "text", "!d"),
+            ("d", "text", "done"),
+        ]);
+        let options = ExpandOptions {
+            max_depth: 2,
+            ..recursive()
+        };
+
+        match expand("!a", &options, lookup) {
+            Err(Error::ExpansionTooDeep { max_depth, chain }) => {
+                assert_eq!(max_depth, 2);
+                assert_eq!(chain, ["a", "b", "c"]);
+            }
+            result => panic!("expected the expansion to be too deep, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn stops_expansion_longer_than_the_maximum() {
+        // Every level doubles the length of the text.
+        let lookup = aliases(&[
+            ("a", "text", "!b !b"),
+            ("b", "text", "!c !c"),
+            ("c", "text", "!d !d"),
+            ("d", "text", "0123456789"),
+        ]);
+        let options = ExpandOptions {
+            max_length: 50,
+            ..recursive()
+        };
+
+        assert!(matches!(
+            expand("!a", &options, lookup),
+            Err(Error::ExpansionTooLong { maximum: 50 })
+        ));
+    }
+
+    #[test]
+    fn finds_cycles_through_the_new_content() {
+        let lookup = aliases(&[("a", "text", "!b"), ("b", "text", "!c"), ("c", "text", "x")]);
+
+        match find_cycle("c", "!a", usize::MAX, lookup) {
+            Err(Error::AliasCycle(chain)) => assert_eq!(chain, ["c", "a", "b", "c"]),
+            result => panic!("expected a cycle, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn finds_cycles_through_arguments() {
+        let lookup = aliases(&[("slap", "text", "{0} was slapped"), ("a", "text", "x")]);
+
+        assert!(matches!(
+            find_cycle("a", "!slap(!a)", usize::MAX, lookup),
+            Err(Error::AliasCycle(_))
+        ));
+    }
+
+    #[test]
+    fn allows_aliases_referenced_many_times() {
+        // Every alias references the one before it ten times, expanding all of them would take
+        // 10^19 lookups.
+        let list = (0..20)
+            .map(|i| match i {
+                0 => ("a0".to_owned(), "x".to_owned()),
+                i => (format!("a{i}"), format!("!a{} ", i - 1).repeat(10)),
+            })
+            .collect::<Vec<_>>();
+        let list = list
+            .iter()
+            .map(|(name, content)| (name.as_str(), "text", content.as_str()))
+            .collect::<Vec<_>>();
+
+        let mut lookups = 0;
+        let mut lookup = aliases(&list);
+        find_cycle("top", "!a19", usize::MAX, |name| {
+            lookups += 1;
+            lookup(name)
+        })
+        .unwrap();
+        assert!(lookups < 1000);
+    }
+
+    #[test]
+    fn ignores_references_to_media() {
+        let lookup = aliases(&[("a", "image", "https://example.com/!a")]);
+
+        find_cycle("b", "!a", usize::MAX, lookup).unwrap();
+    }
+}
@@ -1,11 +1,15 @@
+use anyhow::Context;
 use axum::{
+    extract::DefaultBodyLimit,
     http::StatusCode,
+    middleware,
     routing::{delete, get, post, put, Router},
     Extension,
 };
 use futures::FutureExt;
 use idlib::{AuthCallback, IdpClient, SecretKey, Variables};
 use rusqlite_migration::{Migrations, M};
+use tokio::sync::Notify;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use utoipa::{
     openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme},
@@ -14,18 +18,56 @@ use utoipa::{
 use utoipa_swagger_ui::SwaggerUi;
 
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+pub mod expand;
+pub mod link_health;
+pub mod proxy;
+pub mod template;
 pub mod util;
+pub mod webhook;
 
 mod account;
 mod alias;
+mod alias_type;
+mod audit;
 mod auth;
+mod changes;
+mod duplicate;
 mod error;
+mod events;
+mod media;
+mod moderation;
+mod policy;
+mod probe;
+mod rename;
+mod rendition;
+mod report;
+mod revision;
+mod search;
+mod suggest;
+mod synonym;
+mod tag;
+mod trash;
+mod usage;
 mod user;
 
+pub use alias::Alias;
+pub use alias_type::AliasType;
+pub use error::Error;
+
 pub struct AppState {
     db: tokio_rusqlite::Connection,
+    suggestions: Arc<RwLock<suggest::SuggestIndex>>,
+    uses: Arc<usage::UseCounts>,
+    trash_retention: Duration,
+    media: Option<media::MediaStore>,
+    proxy: proxy::ProxyCache,
+    moderate_new_aliases: bool,
+    report_threshold: u64,
+    webhooks_queued: Arc<Notify>,
+    events: Arc<events::EventHub>,
 }
 
 #[derive(OpenApi)]
@@ -39,9 +81,47 @@ pub struct AppState {
         user::get_user_by_username,
         alias::get_aliases,
         alias::post_alias,
+        search::search_aliases,
+        suggest::get_suggestions,
         alias::get_alias_by_name,
         alias::put_alias_by_name,
         alias::delete_alias_by_name,
+        events::get_alias_events,
+        changes::get_alias_changes,
+        revision::get_alias_history,
+        revision::post_restore_revision,
+        rename::post_rename_alias,
+        synonym::put_synonyms,
+        tag::get_tags,
+        tag::post_tag,
+        tag::put_tag,
+        tag::delete_tag,
+        tag::put_alias_tags,
+        alias_type::get_types,
+        alias_type::post_type,
+        alias_type::put_type,
+        alias_type::delete_type,
+        media::post_media,
+        media::get_media,
+        proxy::get_alias_content,
+        trash::get_trash,
+        trash::post_restore_trash,
+        duplicate::get_duplicates,
+        moderation::get_queue,
+        moderation::post_approve,
+        moderation::post_reject,
+        report::post_report,
+        report::get_reports,
+        report::post_resolve_reports,
+        report::post_dismiss_reports,
+        audit::get_audit_log,
+        webhook::get_webhooks,
+        webhook::post_webhook,
+        webhook::delete_webhook,
+        webhook::get_deliveries,
+        webhook::post_retry_delivery,
+        link_health::get_link_health,
+        expand::post_expand,
         auth::_authorize_dummy,
         auth::_revoke_dummy,
         auth::_logout_dummy
@@ -50,8 +130,56 @@ pub struct AppState {
         user::User,
         alias::Alias,
         alias::PostAlias,
+        alias::PostAliasResponse,
         alias::PutAlias,
-        alias::AliasType,
+        alias_type::AliasType,
+        alias::AliasSort,
+        alias::SortOrder,
+        alias::TagMatch,
+        events::AliasEvent,
+        events::AliasEventKind,
+        changes::AliasChanges,
+        suggest::Suggestion,
+        revision::AliasRevision,
+        rename::RenameAlias,
+        tag::Tag,
+        tag::PostTag,
+        tag::PutTag,
+        alias_type::AliasTypeInfo,
+        alias_type::TypeDisplay,
+        alias_type::PostAliasType,
+        alias_type::PutAliasType,
+        media::MediaUpload,
+        media::MediaInfo,
+        media::PostMediaResponse,
+        rendition::Rendition,
+        trash::TrashedAlias,
+        duplicate::DuplicateCluster,
+        duplicate::SimilarAlias,
+        moderation::ModerationStatus,
+        moderation::ModerationItem,
+        moderation::ModerationDecision,
+        report::ReportCategory,
+        report::PostReport,
+        report::Report,
+        report::ReportedAlias,
+        audit::AuditAction,
+        audit::AuditEntry,
+        webhook::WebhookEvent,
+        webhook::WebhookPayload,
+        webhook::Webhook,
+        webhook::PostWebhook,
+        webhook::PostWebhookResponse,
+        webhook::DeliveryStatus,
+        webhook::DeliveryAttempt,
+        webhook::WebhookDelivery,
+        link_health::LinkCheck,
+        link_health::LinkHealthReport,
+        expand::ExpandRequest,
+        expand::Expansion,
+        expand::ExpandMatch,
+        expand::WordBoundary,
+        expand::OutputFormat,
         account::Settings,
         account::PutSettings
     )),
@@ -80,12 +208,90 @@ impl Modify for SecurityAddon {
     }
 }
 
+/// What the server makes requests to other servers with, replaceable in tests.
+pub struct Clients {
+    /// Fetches content for the image proxy.
+    pub upstream: Arc<dyn proxy::Upstream>,
+    /// Sends webhook deliveries.
+    pub webhooks: Arc<dyn webhook::WebhookClient>,
+    /// Checks the links in the content of aliases.
+    pub links: Arc<dyn link_health::LinkClient>,
+}
+
+impl Clients {
+    /// Clients which make real HTTP requests.
+    pub fn http() -> anyhow::Result<Self> {
+        Ok(Self {
+            upstream: Arc::new(proxy::HttpUpstream::default()),
+            webhooks: Arc::new(webhook::HttpWebhookClient::new()?),
+            links: Arc::new(link_health::HttpLinkClient::default()),
+        })
+    }
+}
+
 pub async fn api_route(db: tokio_rusqlite::Connection) -> anyhow::Result<Router> {
+    api_route_with_clients(db, Clients::http()?).await
+}
+
+/// Creates the router with `clients` making the requests to other servers.
+pub async fn api_route_with_clients(
+    db: tokio_rusqlite::Connection,
+    clients: Clients,
+) -> anyhow::Result<Router> {
     let secret_key = SecretKey::from_env()?;
     let variables = Variables::from_env()?;
 
     let idp_client = IdpClient::default();
 
+    let trash_retention_days = match std::env::var("TRASH_RETENTION_DAYS") {
+        Ok(days) => days
+            .parse()
+            .context("TRASH_RETENTION_DAYS could not be parsed")?,
+        Err(_) => trash::DEFAULT_RETENTION_DAYS,
+    };
+    let trash_retention = Duration::from_secs(trash_retention_days * 24 * 60 * 60);
+    tokio::spawn(trash::purge_periodically(db.clone(), trash_retention));
+
+    let moderate_new_aliases = match std::env::var("MODERATE_NEW_ALIASES") {
+        Ok(enabled) => enabled
+            .parse()
+            .context("MODERATE_NEW_ALIASES could not be parsed")?,
+        Err(_) => false,
+    };
+
+    let report_threshold = match std::env::var("REPORT_THRESHOLD") {
+        Ok(threshold) => threshold
+            .parse()
+            .context("REPORT_THRESHOLD could not be parsed")?,
+        Err(_) => report::DEFAULT_REPORT_THRESHOLD,
+    };
+
+    tokio::spawn(link_health::check_periodically(db.clone(), clients.links));
+
+    let suggestions = db.call(|conn| suggest::SuggestIndex::load(conn)).await?;
+    let suggestions = Arc::new(RwLock::new(suggestions));
+
+    let uses = Arc::new(usage::UseCounts::default());
+    tokio::spawn(usage::flush_periodically(
+        db.clone(),
+        uses.clone(),
+        suggestions.clone(),
+    ));
+
+    let webhooks_queued = Arc::new(Notify::new());
+    tokio::spawn(webhook::deliver_continuously(
+        db.clone(),
+        clients.webhooks,
+        webhooks_queued.clone(),
+    ));
+
+    let media = media::MediaStore::from_env().await?;
+    if let Some(media) = &media {
+        media.read_missing_metadata(&db).await?;
+    }
+
+    let proxy = proxy::ProxyCache::from_env(clients.upstream).await?;
+
     let cdb = db.clone();
     let auth_callback = AuthCallback(Arc::new(Box::new(move |name| {
         crate::user::create_user_if_missing(cdb.clone(), name).boxed()
@@ -101,16 +307,91 @@ pub async fn api_route(db: tokio_rusqlite::Connection) -> anyhow::Result<Router>
         .route("/api/user/:username", get(user::get_user_by_username))
         .route("/api/alias", get(alias::get_aliases))
         .route("/api/alias", post(alias::post_alias))
+        .route("/api/alias/search", get(search::search_aliases))
+        .route("/api/alias/suggest", get(suggest::get_suggestions))
+        .route("/api/alias/events", get(events::get_alias_events))
+        .route("/api/alias/changes", get(changes::get_alias_changes))
         .route("/api/alias/:name", get(alias::get_alias_by_name))
         .route("/api/alias/:name", put(alias::put_alias_by_name))
         .route("/api/alias/:name", delete(alias::delete_alias_by_name))
+        .route("/api/alias/:name/history", get(revision::get_alias_history))
+        .route(
+            "/api/alias/:name/restore/:revision",
+            post(revision::post_restore_revision),
+        )
+        .route("/api/alias/:name/rename", post(rename::post_rename_alias))
+        .route("/api/alias/:name/synonyms", put(synonym::put_synonyms))
+        .route("/api/alias/:name/tags", put(tag::put_alias_tags))
+        .route("/api/alias/:name/report", post(report::post_report))
+        .route(
+            "/api/alias/:name/media",
+            post(media::post_media).layer(DefaultBodyLimit::max(2 * media::MAX_MEDIA_SIZE)),
+        )
+        .route("/media/:hash", get(media::get_media))
+        .route("/api/alias/:name/content", get(proxy::get_alias_content))
+        .route("/api/tags", get(tag::get_tags).post(tag::post_tag))
+        .route("/api/tags/:name", put(tag::put_tag).delete(tag::delete_tag))
+        .route(
+            "/api/types",
+            get(alias_type::get_types).post(alias_type::post_type),
+        )
+        .route(
+            "/api/types/:name",
+            put(alias_type::put_type).delete(alias_type::delete_type),
+        )
+        .route("/api/expand", post(expand::post_expand))
+        .route("/api/trash", get(trash::get_trash))
+        .route("/api/trash/:name/restore", post(trash::post_restore_trash))
+        .route("/api/duplicates", get(duplicate::get_duplicates))
+        .route("/api/moderation/queue", get(moderation::get_queue))
+        .route(
+            "/api/moderation/:name/approve",
+            post(moderation::post_approve),
+        )
+        .route(
+            "/api/moderation/:name/reject",
+            post(moderation::post_reject),
+        )
+        .route("/api/reports", get(report::get_reports))
+        .route(
+            "/api/reports/:name/resolve",
+            post(report::post_resolve_reports),
+        )
+        .route(
+            "/api/reports/:name/dismiss",
+            post(report::post_dismiss_reports),
+        )
+        .route("/api/links", get(link_health::get_link_health))
+        .route("/api/audit", get(audit::get_audit_log))
+        .route(
+            "/api/webhooks",
+            get(webhook::get_webhooks).post(webhook::post_webhook),
+        )
+        .route("/api/webhooks/:id", delete(webhook::delete_webhook))
+        .route("/api/webhooks/:id/deliveries", get(webhook::get_deliveries))
+        .route(
+            "/api/webhooks/:id/deliveries/:delivery/retry",
+            post(webhook::post_retry_delivery),
+        )
         .nest(
             "/api/auth",
             idlib::api_route(idp_client, Some(auth_callback)),
         )
+        .layer(middleware::from_fn(audit::request_id))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
-        .layer(Extension(Arc::new(AppState { db })))
+        .layer(Extension(Arc::new(AppState {
+            db,
+            suggestions,
+            uses,
+            trash_retention,
+            media,
+            proxy,
+            moderate_new_aliases,
+            report_threshold,
+            webhooks_queued,
+            events: Arc::new(events::EventHub::new()),
+        })))
         .layer(Extension(IdpClient::default()))
         .layer(Extension(secret_key))
         .layer(Extension(Arc::new(variables))))
@@ -128,7 +409,32 @@ async fn health() -> StatusCode {
     StatusCode::OK
 }
 
-pub(crate) const MIGRATIONS: [M; 1] = [M::up(include_str!("../migrations/001_initial.sql"))];
+pub(crate) const MIGRATIONS: [M; 24] = [
+    M::up(include_str!("../migrations/001_initial.sql")),
+    M::up(include_str!("../migrations/002_alias_popularity.sql")),
+    M::up(include_str!("../migrations/003_alias_search.sql")),
+    M::up(include_str!("../migrations/004_alias_revisions.sql")),
+    M::up(include_str!("../migrations/005_alias_trash.sql")),
+    M::up(include_str!("../migrations/006_alias_redirects.sql")),
+    M::up(include_str!("../migrations/007_alias_synonyms.sql")),
+    M::up(include_str!("../migrations/008_tags.sql")),
+    M::up(include_str!("../migrations/009_alias_type_hints.sql")),
+    M::up(include_str!("../migrations/010_media.sql")),
+    M::up(include_str!("../migrations/011_media_dimensions.sql")),
+    M::up(include_str!("../migrations/012_media_phash.sql")),
+    M::up(include_str!("../migrations/013_link_checks.sql")),
+    M::up(include_str!("../migrations/014_proxy_cache.sql")),
+    M::up(include_str!("../migrations/015_alias_moderation.sql")),
+    M::up(include_str!("../migrations/016_alias_reports.sql")),
+    M::up(include_str!("../migrations/017_audit_log.sql")),
+    M::up(include_str!("../migrations/018_webhooks.sql")),
+    M::up(include_str!("../migrations/019_alias_changes.sql")),
+    M::up(include_str!("../migrations/020_alias_search_content.sql")),
+    M::up(include_str!("../migrations/021_emote_size.sql")),
+    M::up(include_str!("../migrations/022_media_metadata_read.sql")),
+    M::up(include_str!("../migrations/023_alias_published.sql")),
+    M::up(include_str!("../migrations/024_alias_ids.sql")),
+];
 
 pub async fn setup_database(path: &Path) -> anyhow::Result<tokio_rusqlite::Connection> {
     let db = tokio_rusqlite::Connection::open(path).await?;
@@ -146,3 +452,18 @@ pub async fn setup_database(path: &Path) -> anyhow::Result<tokio_rusqlite::Conne
 
     Ok(db)
 }
+
+/// An in-memory database with every migration applied.
+#[cfg(test)]
+pub(crate) async fn test_database() -> tokio_rusqlite::Connection {
+    let db = tokio_rusqlite::Connection::open_in_memory().await.unwrap();
+
+    let migrations = Migrations::new(MIGRATIONS.to_vec());
+    db.call(move |conn| {
+        migrations.to_latest(conn).unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+    })
+    .await;
+
+    db
+}
@@ -0,0 +1,640 @@
+//! Periodically checks that the URLs in the content of aliases still work, so aliases pointing at
+//! images which have been taken down can be found and fixed.
+
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{Extension, Json};
+use futures::StreamExt;
+use reqwest::Method;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use tracing::{error, info};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::error::Error;
+use crate::proxy::{self, PinnedClients, Resolver, MAX_REDIRECTS};
+use crate::AppState;
+
+/// How long a check is considered up to date before the URL is checked again.
+pub const RECHECK_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An alias is broken after this many checks of its URL have failed in a row, so hosts which are
+/// briefly down don't flag their aliases.
+pub const BROKEN_AFTER_FAILURES: u32 = 2;
+
+/// How often aliases which are due for a check are looked for.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The most URLs checked in one go, the rest are checked in later runs.
+const BATCH_SIZE: usize = 100;
+
+/// How many URLs are checked at the same time.
+const CONCURRENT_CHECKS: usize = 8;
+
+/// How long a single request may take before the URL counts as broken.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// What the server behind a URL responded with.
+#[derive(Debug, Clone)]
+pub struct LinkResponse {
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// The `Location` header of redirects.
+    pub location: Option<String>,
+}
+
+impl LinkResponse {
+    /// Error statuses and web pages in place of media, which is what many image hosts return for
+    /// removed images, count as broken.
+    pub fn is_ok(&self) -> bool {
+        let is_page = self
+            .content_type
+            .as_ref()
+            .filter(|content_type| content_type.starts_with("text/html"))
+            .is_some();
+        self.status < 400 && !is_page
+    }
+}
+
+/// Makes the requests for link checks, implemented by [`HttpLinkClient`] and replaceable in tests.
+/// Like the image proxy, the link checker only connects to public addresses, so implementations
+/// have to connect to the address they are given instead of resolving the host themselves.
+#[async_trait]
+pub trait LinkClient: Resolver {
+    /// Requests `uri` from `addr` without following redirects, returning a description of the
+    /// error if no response was received.
+    async fn request(
+        &self,
+        method: Method,
+        uri: &Uri,
+        addr: SocketAddr,
+    ) -> Result<LinkResponse, String>;
+}
+
+/// Checks links over HTTP using the system resolver.
+pub struct HttpLinkClient {
+    clients: PinnedClients,
+}
+
+impl Default for HttpLinkClient {
+    fn default() -> Self {
+        Self {
+            clients: PinnedClients::new(|| {
+                reqwest::Client::builder()
+                    .timeout(REQUEST_TIMEOUT)
+                    .user_agent(concat!(
+                        env!("CARGO_PKG_NAME"),
+                        "-link-checker/",
+                        env!("CARGO_PKG_VERSION")
+                    ))
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for HttpLinkClient {
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        proxy::lookup_host(host, port).await
+    }
+}
+
+#[async_trait]
+impl LinkClient for HttpLinkClient {
+    async fn request(
+        &self,
+        method: Method,
+        uri: &Uri,
+        addr: SocketAddr,
+    ) -> Result<LinkResponse, String> {
+        let host = uri.host().ok_or("The URL has no host")?;
+        let client = self.clients.get(host, addr)?;
+
+        // Only the headers are read, the body of GET requests is dropped with the response.
+        let response = client
+            .request(method, uri.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        };
+
+        Ok(LinkResponse {
+            status: response.status().as_u16(),
+            content_type: header(reqwest::header::CONTENT_TYPE),
+            location: header(reqwest::header::LOCATION),
+        })
+    }
+}
+
+/// Requests `url` with a HEAD request, falling back to GET for servers which don't support HEAD.
+/// Redirects are followed as long as they stay on public addresses, URLs which resolve or redirect
+/// to private addresses count as broken.
+pub async fn check(client: &dyn LinkClient, url: &str) -> Result<LinkResponse, String> {
+    let mut uri = proxy::parse_url(url).map_err(|e| e.to_string())?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let addr = proxy::public_addr(client, &uri)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut response = client.request(Method::HEAD, &uri, addr).await?;
+        if matches!(response.status, 405 | 501) {
+            response = client.request(Method::GET, &uri, addr).await?;
+        }
+
+        match (response.status, &response.location) {
+            (300..=399, Some(location)) => {
+                uri = proxy::redirect_target(&uri, location).map_err(|e| e.to_string())?;
+            }
+            _ => return Ok(response),
+        }
+    }
+
+    Err(format!("{url} redirected more than {MAX_REDIRECTS} times"))
+}
+
+/// The result of the latest check of the URL in the content of an alias.
+#[derive(Debug, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct LinkCheck {
+    /// The name of the alias.
+    #[schema(example = "funny.png")]
+    pub alias: String,
+
+    /// The URL which was checked.
+    #[schema(example = "https://example.com/funny.png")]
+    pub url: String,
+
+    /// The HTTP status of the response, missing if no response was received.
+    #[schema(example = 404)]
+    pub status: Option<u16>,
+
+    /// The content type of the response.
+    #[schema(example = "text/html")]
+    pub content_type: Option<String>,
+
+    /// Why no response was received.
+    #[schema(example = "operation timed out")]
+    pub error: Option<String>,
+
+    /// How many checks in a row have failed.
+    #[schema(example = 3)]
+    pub failures: u32,
+
+    /// Whether enough checks in a row have failed for the alias to be considered broken.
+    pub broken: bool,
+
+    /// A unix timestamp of when the URL was last checked.
+    #[schema(example = 1670802822)]
+    pub checked_at: u64,
+}
+
+/// Checks up to [`BATCH_SIZE`] aliases which have not been checked yet, whose content changed
+/// since they were checked or whose check is outdated, returning how many were checked.
+pub async fn check_due(
+    db: &tokio_rusqlite::Connection,
+    client: &dyn LinkClient,
+) -> Result<usize, Error> {
+    let due = db.call(due_aliases).await?;
+
+    let results = futures::stream::iter(due)
+        .map(|(alias, url)| async move {
+            let result = check(client, &url).await;
+            (alias, url, result)
+        })
+        .buffer_unordered(CONCURRENT_CHECKS)
+        .collect::<Vec<_>>()
+        .await;
+
+    let checked = results.len();
+    db.call(move |conn| {
+        let tx = conn.transaction().context("Failed to create transaction")?;
+        for (alias, url, result) in results {
+            record(&tx, &alias, &url, result)?;
+        }
+        tx.commit().context("Failed to commit transaction")?;
+
+        Ok::<_, Error>(())
+    })
+    .await?;
+
+    Ok(checked)
+}
+
+/// Live non-text aliases with an external URL as content which are due for a check, the ones
+/// which have waited longest first.
+fn due_aliases(conn: &mut Connection) -> Result<Vec<(String, String)>, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let outdated = now.saturating_sub(RECHECK_AFTER.as_secs());
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT a.name, a.content
+            FROM aliases a
+            JOIN alias_types at ON at.id = a.type
+            LEFT JOIN link_checks lc ON lc.alias = a.name
+            WHERE a.deleted_at IS NULL
+                AND a.media IS NULL
+                AND at.name != 'text'
+                AND (a.content LIKE 'http://%' OR a.content LIKE 'https://%')
+                AND (lc.alias IS NULL OR lc.url != a.content OR lc.checked_at < ?)
+            ORDER BY lc.checked_at IS NOT NULL, lc.checked_at, a.name
+            LIMIT ?",
+        )
+        .context("Failed to prepare statement for due link checks")?;
+
+    let due = stmt
+        .query_map(params![outdated, BATCH_SIZE], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .context("Failed to query due link checks")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect due link checks")?;
+
+    Ok(due)
+}
+
+fn record(
+    conn: &Connection,
+    alias: &str,
+    url: &str,
+    result: Result<LinkResponse, String>,
+) -> Result<(), Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+    // Failures only count towards the same URL, the content might have been fixed since.
+    let previous_failures = conn
+        .query_row(
+            "SELECT failures FROM link_checks WHERE alias = ? AND url = ?",
+            params![alias, url],
+            |row| row.get::<_, u32>(0),
+        )
+        .optional()
+        .context("Failed to query previous link check")?
+        .unwrap_or(0);
+
+    let (status, content_type, error, ok) = match result {
+        Ok(response) => {
+            let ok = response.is_ok();
+            (Some(response.status), response.content_type, None, ok)
+        }
+        Err(error) => (None, None, Some(error), false),
+    };
+    let failures = if ok { 0 } else { previous_failures + 1 };
+
+    conn.execute(
+        "INSERT INTO link_checks
+            (alias, url, status, content_type, error, failures, checked_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT (alias) DO UPDATE SET
+            url = excluded.url,
+            status = excluded.status,
+            content_type = excluded.content_type,
+            error = excluded.error,
+            failures = excluded.failures,
+            checked_at = excluded.checked_at",
+        params![alias, url, status, content_type, error, failures, now],
+    )
+    .context("Failed to record link check")?;
+
+    Ok(())
+}
+
+/// Checks aliases which are due for a check every ten minutes.
+pub async fn check_periodically(db: tokio_rusqlite::Connection, client: Arc<dyn LinkClient>) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match check_due(&db, client.as_ref()).await {
+            Ok(0) => {}
+            Ok(checked) => info!("Checked the links of {checked} aliases"),
+            Err(e) => error!("Failed to check links: {e}"),
+        }
+    }
+}
+
+/// An overview of the link checks of all aliases.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct LinkHealthReport {
+    /// How many aliases have an up to date check.
+    #[schema(example = 1200)]
+    pub checked: u64,
+
+    /// How many aliases with an external URL haven't been checked since they were created or their
+    /// content changed.
+    #[schema(example = 12)]
+    pub unchecked: u64,
+
+    /// The checks of the broken aliases, most failures first.
+    pub broken: Vec<LinkCheck>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbLinkCheck {
+    alias: String,
+    url: String,
+    status: Option<u16>,
+    content_type: Option<String>,
+    error: Option<String>,
+    failures: u32,
+    checked_at: u64,
+}
+
+impl From<DbLinkCheck> for LinkCheck {
+    fn from(check: DbLinkCheck) -> Self {
+        Self {
+            alias: check.alias,
+            url: check.url,
+            status: check.status,
+            content_type: check.content_type,
+            error: check.error,
+            failures: check.failures,
+            broken: check.failures >= BROKEN_AFTER_FAILURES,
+            checked_at: check.checked_at,
+        }
+    }
+}
+
+/// Get the link health of aliases with external URLs.
+/// # Note
+/// Links are checked in the background about once a day, an alias counts as broken once two checks
+/// of its URL in a row have failed. Failed requests, error statuses, web pages instead of media and
+/// URLs which resolve or redirect to private addresses count as failures.
+#[utoipa::path(
+    get,
+    path = "/api/links",
+    responses(
+        (status = 200, description = "The link health report is returned.", body = LinkHealthReport),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_link_health(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move { state.db.call(move |conn| report(conn).map(Json)).await })
+        .await
+}
+
+pub fn report(conn: &Connection) -> Result<LinkHealthReport, Error> {
+    let (checked, unchecked) = conn
+        .query_row(
+            "SELECT
+                COUNT(lc.alias),
+                COUNT(*) - COUNT(lc.alias)
+            FROM aliases a
+            JOIN alias_types at ON at.id = a.type
+            LEFT JOIN link_checks lc ON lc.alias = a.name AND lc.url = a.content
+            WHERE a.deleted_at IS NULL
+                AND a.media IS NULL
+                AND at.name != 'text'
+                AND (a.content LIKE 'http://%' OR a.content LIKE 'https://%')",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .context("Failed to count link checks")?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                lc.alias,
+                lc.url,
+                lc.status,
+                lc.content_type,
+                lc.error,
+                lc.failures,
+                lc.checked_at
+            FROM link_checks lc
+            JOIN aliases a ON a.name = lc.alias AND a.content = lc.url
+            WHERE a.deleted_at IS NULL AND lc.failures >= ?
+            ORDER BY lc.failures DESC, lc.alias",
+        )
+        .context("Failed to prepare statement for broken links query")?;
+
+    let broken = stmt
+        .query_map(params![BROKEN_AFTER_FAILURES], |row| {
+            Ok(LinkCheck::from(from_row::<DbLinkCheck>(row).unwrap()))
+        })
+        .context("Failed to query broken links")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect broken links")?;
+
+    Ok(LinkHealthReport {
+        checked,
+        unchecked,
+        broken,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Resolves `example.com` to a public and `internal.example.com` to a private address and
+    /// answers requests from `responses`, keyed by method and URL, with a 404 for anything else.
+    struct StubClient {
+        responses: HashMap<String, LinkResponse>,
+        requests: Mutex<Vec<String>>,
+    }
+
+    impl StubClient {
+        fn new(responses: Vec<(&str, LinkResponse)>) -> Self {
+            Self {
+                responses: responses
+                    .into_iter()
+                    .map(|(request, response)| (request.to_owned(), response))
+                    .collect(),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        fn requested(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Resolver for StubClient {
+        async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+            let ip = match host {
+                "example.com" => "93.184.216.34",
+                "internal.example.com" => "10.0.0.1",
+                _ => return Err("unknown host".into()),
+            };
+            Ok(vec![SocketAddr::new(ip.parse().unwrap(), port)])
+        }
+    }
+
+    #[async_trait]
+    impl LinkClient for StubClient {
+        async fn request(
+            &self,
+            method: Method,
+            uri: &Uri,
+            _addr: SocketAddr,
+        ) -> Result<LinkResponse, String> {
+            let request = format!("{method} {uri}");
+            self.requests.lock().unwrap().push(request.clone());
+            Ok(self
+                .responses
+                .get(&request)
+                .cloned()
+                .unwrap_or_else(|| response(404, None, None)))
+        }
+    }
+
+    fn response(status: u16, content_type: Option<&str>, location: Option<&str>) -> LinkResponse {
+        LinkResponse {
+            status,
+            content_type: content_type.map(ToOwned::to_owned),
+            location: location.map(ToOwned::to_owned),
+        }
+    }
+
+    fn image() -> LinkResponse {
+        response(200, Some("image/png"), None)
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_and_falls_back_to_get() {
+        let client = StubClient::new(vec![
+            ("HEAD http://example.com/a.png", response(405, None, None)),
+            (
+                "GET http://example.com/a.png",
+                response(301, None, Some("/b.png")),
+            ),
+            ("HEAD http://example.com/b.png", image()),
+        ]);
+
+        let response = check(&client, "http://example.com/a.png").await.unwrap();
+
+        assert!(response.is_ok());
+        assert_eq!(
+            client.requested(),
+            [
+                "HEAD http://example.com/a.png",
+                "GET http://example.com/a.png",
+                "HEAD http://example.com/b.png",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_private_addresses() {
+        let client = StubClient::new(vec![(
+            "HEAD http://example.com/a.png",
+            response(302, None, Some("http://internal.example.com/a.png")),
+        )]);
+
+        for url in [
+            "http://127.0.0.1/a.png",
+            "http://[::ffff:192.168.1.1]/a.png",
+            "http://internal.example.com/a.png",
+            "http://example.com/a.png",
+        ] {
+            assert!(check(&client, url).await.is_err(), "{url} should fail");
+        }
+
+        // Only the request to the public address was made.
+        assert_eq!(client.requested(), ["HEAD http://example.com/a.png"]);
+    }
+
+    #[tokio::test]
+    async fn records_failures_of_due_links() {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0);
+                INSERT INTO aliases (name, content, type, author, created_at)
+                VALUES
+                    ('ok.png', 'https://example.com/ok.png', 2, 'alice', 0),
+                    ('gone.png', 'https://example.com/gone.png', 2, 'alice', 0),
+                    ('private.png', 'http://internal.example.com/a.png', 2, 'alice', 0),
+                    ('page.png', 'https://example.com/page', 2, 'alice', 0),
+                    ('text', 'https://example.com/gone.png', 1, 'alice', 0);",
+            )
+            .unwrap();
+        })
+        .await;
+        let client = StubClient::new(vec![
+            ("HEAD https://example.com/ok.png", image()),
+            (
+                "HEAD https://example.com/page",
+                response(200, Some("text/html; charset=utf-8"), None),
+            ),
+        ]);
+
+        assert_eq!(check_due(&db, &client).await.unwrap(), 4);
+        // Checks are up to date for a day.
+        assert_eq!(check_due(&db, &client).await.unwrap(), 0);
+
+        let health = db.call(|conn| report(conn)).await.unwrap();
+        assert_eq!((health.checked, health.unchecked), (4, 0));
+        assert!(health.broken.is_empty());
+
+        db.call(|conn| {
+            conn.execute(
+                "UPDATE link_checks SET checked_at = checked_at - ?",
+                [RECHECK_AFTER.as_secs() + 1],
+            )
+            .unwrap();
+        })
+        .await;
+        assert_eq!(check_due(&db, &client).await.unwrap(), 4);
+
+        let health = db.call(|conn| report(conn)).await.unwrap();
+        let broken = health
+            .broken
+            .iter()
+            .map(|check| (check.alias.as_str(), check.failures, check.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            broken,
+            [
+                ("gone.png", 2, Some(404)),
+                ("page.png", 2, Some(200)),
+                ("private.png", 2, None),
+            ]
+        );
+        assert!(health.broken.iter().all(|check| check.broken));
+
+        // Failures only count towards the URL which failed.
+        db.call(|conn| {
+            conn.execute(
+                "UPDATE aliases SET content = 'https://example.com/ok.png' WHERE name = 'gone.png'",
+                [],
+            )
+            .unwrap();
+        })
+        .await;
+        assert_eq!(check_due(&db, &client).await.unwrap(), 1);
+        let health = db.call(|conn| report(conn)).await.unwrap();
+        assert_eq!(health.broken.len(), 2);
+    }
+}
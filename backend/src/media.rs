@@ -0,0 +1,731 @@
+use axum::extract::multipart::MultipartRejection;
+use axum::extract::rejection::QueryRejection;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{
+    extract::{Multipart, Path, Query},
+    Extension, Json,
+};
+use dashmap::DashMap;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{info, warn};
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::path::{Path as FilePath, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias::{self, Alias};
+use crate::alias_type::{self, AliasType};
+use crate::audit::{self, AuditAction, RequestId};
+use crate::duplicate::{self, SimilarAlias};
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::moderation::{self, Viewer};
+use crate::policy::{self, AliasAction};
+use crate::probe::{self, ImageInfo};
+use crate::rendition::{self, Rendition};
+use crate::revision;
+use crate::suggest;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// The largest file in bytes which can be uploaded.
+pub const MAX_MEDIA_SIZE: usize = 8 * 1024 * 1024;
+
+/// How many renditions are rendered at once at most, decoding large images takes a lot of memory.
+const MAX_PARALLEL_RENDERS: usize = 4;
+
+/// Hosted media never changes since it is named after the hash of its content.
+const CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// The image formats which can be uploaded, detected from the content rather than trusting the
+/// client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl MediaFormat {
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(MediaFormat::Png)
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(MediaFormat::Jpeg)
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            Some(MediaFormat::Gif)
+        } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+            Some(MediaFormat::Webp)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            MediaFormat::Png => "image/png",
+            MediaFormat::Jpeg => "image/jpeg",
+            MediaFormat::Gif => "image/gif",
+            MediaFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "image/png" => Some(MediaFormat::Png),
+            "image/jpeg" => Some(MediaFormat::Jpeg),
+            "image/gif" => Some(MediaFormat::Gif),
+            "image/webp" => Some(MediaFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// Hosted media the content of an alias points at.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct MediaInfo {
+    /// Hex encoded SHA-256 of the media.
+    #[schema(example = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")]
+    pub hash: String,
+
+    #[schema(example = "image/png")]
+    pub mime: String,
+
+    /// The size of the file in bytes.
+    #[schema(example = 52311)]
+    pub size: u64,
+
+    /// The width in pixels, missing if it could not be read from the file.
+    #[schema(example = 32)]
+    pub width: Option<u32>,
+
+    /// The height in pixels, missing if it could not be read from the file.
+    #[schema(example = 32)]
+    pub height: Option<u32>,
+
+    /// The number of frames, animated media has more than one.
+    #[schema(example = 1)]
+    pub frames: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbImageInfo {
+    mime: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    frames: Option<u32>,
+}
+
+impl DbImageInfo {
+    /// What was read from the header of the media if it could be read.
+    fn info(&self) -> Option<ImageInfo> {
+        Some(ImageInfo {
+            format: MediaFormat::from_mime(&self.mime)?,
+            width: self.width?,
+            height: self.height?,
+            frames: self.frames?,
+        })
+    }
+}
+
+fn query_media(conn: &Connection, hash: &str) -> Result<Option<DbImageInfo>, Error> {
+    let media = conn
+        .query_row(
+            "SELECT mime, width, height, frames FROM media WHERE hash = ?",
+            params![hash],
+            |row| Ok(from_row::<DbImageInfo>(row).unwrap()),
+        )
+        .optional()
+        .context("Failed to query media")?;
+
+    Ok(media)
+}
+
+/// Returns what was read from the header of the hosted media with `hash` if it could be read.
+fn image_info(conn: &Connection, hash: &str) -> Result<Option<ImageInfo>, Error> {
+    Ok(query_media(conn, hash)?.and_then(|media| media.info()))
+}
+
+/// Returns the type an alias should have when its content points at the hosted media with `hash`,
+/// see [`ImageInfo::enforced_type`].
+pub(crate) fn enforced_type(
+    conn: &Connection,
+    hash: Option<&str>,
+    requested: AliasType,
+) -> Result<AliasType, Error> {
+    let info = match hash {
+        Some(hash) => image_info(conn, hash)?,
+        None => None,
+    };
+
+    Ok(match info {
+        Some(info) => info.enforced_type(requested, alias_type::emote_size(conn)?),
+        None => requested,
+    })
+}
+
+/// Where hosted media is stored on disk and the URL it is served from.
+pub struct MediaStore {
+    dir: PathBuf,
+    url: String,
+    /// Locks for the renditions which are being rendered, so the same rendition isn't rendered
+    /// multiple times at once while different ones can be rendered in parallel.
+    rendering: DashMap<PathBuf, Arc<Mutex<()>>>,
+    renders: Semaphore,
+}
+
+impl MediaStore {
+    /// Reads the configuration from `MEDIA_DIR` and `MEDIA_URL`, creating the directory if it
+    /// doesn't exist yet. `MEDIA_URL` is the absolute URL `/media` is reachable at, like
+    /// `https://xdd.example.com/media`. Media isn't hosted if it isn't set.
+    pub async fn from_env() -> anyhow::Result<Option<Self>> {
+        let dir = PathBuf::from(std::env::var("MEDIA_DIR").unwrap_or_else(|_| "media".into()));
+        let url = match std::env::var("MEDIA_URL") {
+            Ok(url) => url,
+            Err(_) => {
+                info!("MEDIA_URL is not set, media is not hosted");
+                return Ok(None);
+            }
+        };
+
+        // The URL becomes the content of aliases which are used outside of this site, so it has to
+        // include the host.
+        let valid = url.parse::<Uri>().ok().filter(|uri| {
+            matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+        });
+        if valid.is_none() {
+            anyhow::bail!("MEDIA_URL has to be an absolute http or https URL, got '{url}'");
+        }
+
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create media directory {}", dir.display()))?;
+
+        Ok(Some(Self {
+            dir,
+            url: url.trim_end_matches('/').to_owned(),
+            rendering: DashMap::new(),
+            renders: Semaphore::new(MAX_PARALLEL_RENDERS),
+        }))
+    }
+
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+
+    /// The URL the media with `hash` is served from, used as the content of aliases.
+    pub fn url(&self, hash: &str) -> String {
+        format!("{}/{hash}", self.url)
+    }
+
+    /// Returns the hash of the media `content` points at if it is the URL of hosted media.
+    pub fn hash_of(&self, content: &str) -> Option<String> {
+        content
+            .strip_prefix(&self.url)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .filter(|hash| is_hash(hash))
+            .map(ToOwned::to_owned)
+    }
+
+    /// Stores `data` under the hex encoded SHA-256 of its content, returning the hash.
+    pub async fn save(&self, data: &[u8]) -> Result<String, Error> {
+        let hash = content_hash(data);
+        let path = self.path(&hash);
+
+        if tokio::fs::metadata(&path).await.is_err() {
+            write(&path, data).await?;
+        }
+
+        Ok(hash)
+    }
+
+    /// Returns the path of a rendition of the media with `hash`, rendering it first if that hasn't
+    /// been done yet.
+    pub async fn rendition(
+        &self,
+        hash: &str,
+        info: ImageInfo,
+        rendition: Rendition,
+    ) -> Result<PathBuf, Error> {
+        let path = self.dir.join(format!("{hash}.{rendition}"));
+        if tokio::fs::metadata(&path).await.is_ok() {
+            return Ok(path);
+        }
+
+        let lock = self.rendering.entry(path.clone()).or_default().clone();
+        let rendered = self.render(hash, info, rendition, &path, &lock).await;
+        // The last request waiting for the rendition removes its lock again.
+        self.rendering
+            .remove_if(&path, |_, lock| Arc::strong_count(lock) == 2);
+        rendered?;
+
+        Ok(path)
+    }
+
+    async fn render(
+        &self,
+        hash: &str,
+        info: ImageInfo,
+        rendition: Rendition,
+        path: &FilePath,
+        lock: &Mutex<()>,
+    ) -> Result<(), Error> {
+        let _rendering = lock.lock().await;
+        // Another request might have rendered it while waiting for the lock.
+        if tokio::fs::metadata(path).await.is_err() {
+            let _permit = self
+                .renders
+                .acquire()
+                .await
+                .context("Failed to wait for rendering")?;
+            let data = tokio::fs::read(self.path(hash))
+                .await
+                .context("Failed to read media")?;
+            let rendered =
+                tokio::task::spawn_blocking(move || rendition::render(&data, &info, rendition))
+                    .await
+                    .context("Failed to join rendering task")?
+                    .context("Failed to render media")?;
+
+            write(path, &rendered).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads the dimensions and perceptual hashes of media stored before they were recorded.
+    pub async fn read_missing_metadata(
+        &self,
+        db: &tokio_rusqlite::Connection,
+    ) -> anyhow::Result<()> {
+        let hashes = db
+            .call(|conn| {
+                conn.prepare("SELECT hash FROM media WHERE metadata_read_at IS NULL")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .context("Failed to query media without metadata")?;
+
+        for hash in hashes {
+            let data = match tokio::fs::read(self.path(&hash)).await {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Failed to read media {hash}: {e}");
+                    continue;
+                }
+            };
+
+            // Media which can't be read is only marked as read so it isn't tried again.
+            let info = probe::probe(&data);
+            let phash = match info {
+                Some(info) => perceptual_hash(data, info).await,
+                None => None,
+            };
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            db.call(move |conn| {
+                conn.execute(
+                    "UPDATE media
+                    SET
+                        width = ?,
+                        height = ?,
+                        frames = ?,
+                        phash = ?,
+                        metadata_read_at = ?
+                    WHERE hash = ?",
+                    params![
+                        info.map(|info| info.width),
+                        info.map(|info| info.height),
+                        info.map(|info| info.frames),
+                        phash,
+                        now,
+                        hash
+                    ],
+                )
+            })
+            .await
+            .context("Failed to update media metadata")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Computes the perceptual hash of media on the blocking thread pool, stored as a signed integer
+/// since that is what SQLite supports. Missing if the media could not be decoded or is too large
+/// to decode.
+async fn perceptual_hash(data: Vec<u8>, info: ImageInfo) -> Option<i64> {
+    tokio::task::spawn_blocking(move || duplicate::perceptual_hash(&data, &info))
+        .await
+        .ok()?
+        .ok()
+        .map(|phash| phash as i64)
+}
+
+/// Writes to a temporary file first which is moved into place so partially written media is never
+/// served.
+pub(crate) async fn write(path: &FilePath, data: &[u8]) -> Result<(), Error> {
+    // Every write gets its own temporary file, concurrent writes of the same file would otherwise
+    // interleave their data.
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+    tokio::fs::write(&temporary, data)
+        .await
+        .context("Failed to write media")?;
+    tokio::fs::rename(&temporary, path)
+        .await
+        .context("Failed to move media into place")?;
+
+    Ok(())
+}
+
+/// The multipart form used to upload media.
+#[derive(ToSchema)]
+#[allow(dead_code)] // Only used for the API documentation.
+pub struct MediaUpload {
+    /// A PNG, JPEG, GIF or WebP image of at most 8 MiB.
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// The hex encoded SHA-256 of `data` which hosted media is stored under.
+fn content_hash(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostMediaResponse {
+    /// The alias with the uploaded media as its content.
+    pub alias: Alias,
+
+    /// Other aliases with hosted media similar to the uploaded media, these might be duplicates.
+    pub similar: Vec<SimilarAlias>,
+}
+
+/// Returns where hosted media is stored, failing if media isn't hosted.
+fn store(state: &AppState) -> Result<&MediaStore, Error> {
+    state.media.as_ref().ok_or(Error::MediaNotHosted)
+}
+
+/// Returns the hash of the media `content` points at if it is the URL of hosted media.
+pub(crate) fn hash_of(state: &AppState, content: &str) -> Option<String> {
+    state
+        .media
+        .as_ref()
+        .and_then(|store| store.hash_of(content))
+}
+
+fn is_hash(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+/// Upload an image, GIF or WebP file to be hosted by the server and used as the content of the
+/// alias.
+/// # Note
+/// Requires `edit-aliases` permission unless the alias was created by the user. The type of the
+/// alias is set from the dimensions and frame count of the image, images which fit in the maximum
+/// size of the `emote` type are emotes and animated images are gifs or animated emotes. Aliases with a custom type keep it. Media already
+/// used by another alias is rejected. When aliases are moderated, media uploaded by users who
+/// aren't moderators is pending until it is approved again.
+#[utoipa::path(
+    post,
+    path = "/api/alias/{name}/media",
+    request_body(content = MediaUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The media was stored, the updated alias and aliases with similar media are returned.", body = PostMediaResponse),
+        (status = 400, description = "The form is malformed, has no file or another alias already uses the media."),
+        (status = 404, description = "Alias with the specified name does not exist."),
+        (status = 413, description = "The file is too large."),
+        (status = 415, description = "The file is not a supported image format."),
+        (status = 501, description = "Media is not hosted by this server."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to upload media for."),
+    )
+)]
+pub async fn post_media(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let store = store(&state)?;
+            let mut multipart = multipart?;
+
+            let mut data = None;
+            while let Some(field) = multipart
+                .next_field()
+                .await
+                .map_err(|e| Error::InvalidUpload(e.to_string()))?
+            {
+                if field.name() != Some("file") {
+                    continue;
+                }
+
+                let mut field = field;
+                let mut bytes = Vec::new();
+                while let Some(chunk) = field
+                    .chunk()
+                    .await
+                    .map_err(|e| Error::InvalidUpload(e.to_string()))?
+                {
+                    if bytes.len() + chunk.len() > MAX_MEDIA_SIZE {
+                        return Err(Error::MediaTooLarge {
+                            maximum: MAX_MEDIA_SIZE,
+                        });
+                    }
+                    bytes.extend_from_slice(&chunk);
+                }
+                data = Some(bytes);
+            }
+            let data = data.ok_or(Error::EmptyField("file"))?;
+
+            let info = probe::probe(&data).ok_or(Error::UnsupportedMedia)?;
+
+            let hash = content_hash(&data);
+            let url = store.url(&hash);
+
+            // Checked before storing the file so rejected uploads don't leave it behind, the checks
+            // are repeated in the transaction below.
+            let checked = hash.clone();
+            let (user, groups) = (payload.name.clone(), payload.groups.clone());
+            let name = state
+                .db
+                .call(move |conn| {
+                    let alias = alias::get_by_name(conn, name)?;
+                    policy::authorize(&user, &groups, &alias.author, AliasAction::Edit)?;
+                    let viewer = Viewer::new(&user, &groups);
+                    duplicate::check_identical(conn, &alias.name, Some(&checked), &viewer)?;
+
+                    Ok::<_, Error>(alias.name)
+                })
+                .await?;
+
+            store.save(&data).await?;
+            let size = data.len();
+            let phash = perceptual_hash(data, info).await;
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let moderated = state.moderate_new_aliases && !viewer.is_moderator();
+
+            let events = state.events.clone();
+            let response = state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let alias = alias::get_by_name(&tx, name)?;
+                    policy::authorize(
+                        &payload.name,
+                        &payload.groups,
+                        &alias.author,
+                        AliasAction::Edit,
+                    )?;
+                    duplicate::check_identical(&tx, &alias.name, Some(&hash), &viewer)?;
+
+                    let emote_size = alias_type::emote_size(&tx)?;
+                    let typ = if alias.typ.is_text() {
+                        info.detected_type(emote_size)
+                    } else {
+                        info.enforced_type(alias.typ.clone(), emote_size)
+                    };
+                    let type_id = alias_type::usable_id(&tx, &typ)?;
+                    let resubmit = moderated && url != alias.content;
+
+                    tx.execute(
+                        "INSERT OR IGNORE INTO media (
+                            hash,
+                            mime,
+                            size,
+                            uploaded_by,
+                            created_at,
+                            width,
+                            height,
+                            frames,
+                            phash,
+                            metadata_read_at
+                        )
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                        params![
+                            &hash,
+                            info.format.mime(),
+                            size,
+                            &payload.name,
+                            now,
+                            info.width,
+                            info.height,
+                            info.frames,
+                            phash,
+                            now
+                        ],
+                    )
+                    .context("Failed to insert media")?;
+
+                    let previous = revision::stored_values(&tx, &alias.name)?;
+
+                    tx.execute(
+                        "UPDATE aliases SET content = ?, type = ?, media = ? WHERE name = ?",
+                        params![&url, type_id, &hash, &alias.name],
+                    )
+                    .context("Failed to update alias")?;
+
+                    revision::record(&tx, &alias.name, Some(previous), &payload.name)?;
+                    if resubmit {
+                        moderation::submit(&tx, &alias.name, now)?;
+                        // Subscribers only know about published aliases, it is created again for
+                        // them once it is approved.
+                        webhook::enqueue(&tx, WebhookEvent::AliasDeleted, &alias, None)?;
+                    }
+
+                    let after = alias::get_by_name(&tx, alias.name.clone())?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasMedia,
+                        &alias.name,
+                        audit::snapshot(&alias),
+                        audit::snapshot(&after),
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasUpdated, &after, None)?;
+
+                    let similar = duplicate::find_similar(&tx, &after.name, &hash, &viewer)?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    if resubmit {
+                        events.publish(AliasEventKind::Deleted, alias, None);
+                    }
+                    events.publish(AliasEventKind::Updated, after.clone(), None);
+
+                    Ok::<_, Error>(PostMediaResponse {
+                        alias: after,
+                        similar,
+                    })
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, vec![response.alias.name.clone()]).await?;
+
+            Ok::<_, Error>(Json(response))
+        })
+        .await
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaQuery {
+    /// The rendition to return instead of the original.
+    pub size: Option<Rendition>,
+}
+
+/// Get hosted media by the hash of its content.
+/// # Note
+/// Media is served without authentication so it can be embedded by other services. Responses can
+/// be cached forever since the content of a hash never changes. Renditions are rendered the first
+/// time they are requested, the original is returned if it is already smaller than the rendition.
+#[utoipa::path(
+    get,
+    path = "/media/{hash}",
+    responses(
+        (status = 200, description = "The media is returned."),
+        (status = 304, description = "The media matches the `If-None-Match` header."),
+        (status = 400, description = "The size is not a known rendition."),
+        (status = 404, description = "No media with that hash exists."),
+        (status = 501, description = "Media is not hosted by this server."),
+    ),
+    params(
+        ("hash" = String, Path, description = "Hex encoded SHA-256 of the media."),
+        MediaQuery,
+    )
+)]
+pub async fn get_media(
+    Path(hash): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<MediaQuery>, QueryRejection>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    let Query(query) = query?;
+    let store = store(&state)?;
+
+    if !is_hash(&hash) {
+        return Err(Error::NotFound);
+    }
+
+    let check_hash = hash.clone();
+    let media = state
+        .db
+        .call(move |conn| query_media(conn, &check_hash))
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let rendition = match (query.size, media.info()) {
+        (Some(rendition), Some(info)) if rendition.is_smaller(&info) => Some((rendition, info)),
+        _ => None,
+    };
+
+    let etag = match rendition {
+        Some((rendition, _)) => format!("\"{hash}-{rendition}\""),
+        None => format!("\"{hash}\""),
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(CACHE_CONTROL),
+    );
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).context("Failed to create etag header")?,
+    );
+
+    let matches = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+        });
+    if matches == Some(true) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Vec::new()));
+    }
+
+    let (path, mime) = match rendition {
+        Some((rendition, info)) => (
+            store.rendition(&hash, info, rendition).await?,
+            rendition.mime(&info),
+        ),
+        None => (store.path(&hash), media.mime.as_str()),
+    };
+
+    let data = tokio::fs::read(path)
+        .await
+        .context("Failed to read media")?;
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(mime).context("Failed to create content type header")?,
+    );
+
+    Ok((StatusCode::OK, headers, data))
+}
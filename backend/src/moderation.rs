@@ -0,0 +1,624 @@
+//! An optional approval workflow for new aliases. When `MODERATE_NEW_ALIASES` is enabled aliases
+//! created by users without the `moderate-aliases` group are pending until a moderator approves
+//! them, and only their author and moderators can see them until then. Changing the content of an
+//! alias submits it again.
+
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::response::IntoResponse;
+use idlib::{AuthorizeCookie, Has};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias::{self, Alias};
+use crate::alias_type::AliasType;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::{AliasEventKind, EventHub};
+use crate::suggest;
+use crate::util::non_empty_trimmed_str;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// The group of users who can approve and reject aliases, their own aliases are never moderated.
+pub const MODERATOR_GROUP: &str = "moderate-aliases";
+
+pub(crate) type HasModerateAliases = Has<"moderate-aliases">;
+
+/// A condition on the aliases `a` which only holds for aliases everyone can see, those which are
+/// approved and weren't hidden after being reported.
+pub(crate) const PUBLISHED: &str = "(a.hidden_at IS NULL AND NOT EXISTS (SELECT 1
+    FROM alias_moderation mq
+    WHERE mq.alias = a.name AND mq.status != 'approved'))";
+
+/// Where an alias is in the moderation workflow, aliases which were published without moderation
+/// count as approved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum ModerationStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl ModerationStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ModerationStatus::Pending => "pending",
+            ModerationStatus::Approved => "approved",
+            ModerationStatus::Rejected => "rejected",
+        }
+    }
+}
+
+/// The user aliases are being looked up for.
+pub(crate) struct Viewer {
+    name: String,
+    moderator: bool,
+}
+
+impl Viewer {
+    pub fn new(name: &str, groups: &[String]) -> Self {
+        Self {
+            name: name.to_owned(),
+            moderator: groups.iter().any(|group| group == MODERATOR_GROUP),
+        }
+    }
+
+    pub fn is_moderator(&self) -> bool {
+        self.moderator
+    }
+
+    /// Whether `author` is the viewer, ignoring ASCII case like the usernames in the database.
+    pub fn is_author(&self, author: &str) -> bool {
+        author.eq_ignore_ascii_case(&self.name)
+    }
+
+    /// Aliases which aren't approved or were hidden after being reported can only be seen by their
+    /// author and moderators.
+    pub fn can_see(&self, alias: &Alias) -> bool {
+        let published = alias.status == ModerationStatus::Approved && alias.hidden_at.is_none();
+        published || self.moderator || self.is_author(&alias.author)
+    }
+
+    /// A condition on the aliases `a` which only holds for aliases the viewer can see, taking the
+    /// parameters from [`Viewer::where_params`].
+    pub fn where_str(&self) -> Option<String> {
+        if self.moderator {
+            None
+        } else {
+            Some(format!("({PUBLISHED} OR a.author = ? COLLATE NOCASE)"))
+        }
+    }
+
+    pub fn where_params(&self) -> Vec<Box<dyn ToSql>> {
+        if self.moderator {
+            Vec::new()
+        } else {
+            vec![Box::new(self.name.clone())]
+        }
+    }
+}
+
+/// Puts an alias into the moderation queue, also when it has been moderated before so edits of
+/// approved aliases are checked again.
+pub(crate) fn submit(conn: &Connection, name: &str, now: u64) -> Result<(), Error> {
+    conn.execute(
+        "INSERT INTO alias_moderation (alias, status, submitted_at) VALUES (?, 'pending', ?)
+        ON CONFLICT (alias) DO UPDATE SET
+            status = excluded.status,
+            submitted_at = excluded.submitted_at,
+            moderator = NULL,
+            reason = NULL,
+            moderated_at = NULL",
+        params![name, now],
+    )
+    .context("Failed to submit alias for moderation")?;
+
+    Ok(())
+}
+
+/// An alias which went through moderation.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationItem {
+    /// The name of the alias.
+    #[schema(example = "funny.png")]
+    pub name: String,
+
+    /// The content of the alias.
+    #[schema(example = "https://example.com/funny.png")]
+    pub content: String,
+
+    /// A category describing the type of content in the alias.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The username of the account who submitted the alias.
+    #[schema(example = "Alice")]
+    pub author: String,
+
+    pub status: ModerationStatus,
+
+    /// A unix timestamp of when the alias was submitted.
+    #[schema(example = 1670802822)]
+    pub submitted_at: u64,
+
+    /// The username of the moderator who approved or rejected the alias.
+    #[schema(example = "Bob")]
+    pub moderator: Option<String>,
+
+    /// Why the alias was approved or rejected.
+    #[schema(example = "Already exists as lol.png")]
+    pub reason: Option<String>,
+
+    /// A unix timestamp of when the alias was approved or rejected.
+    #[schema(example = 1670802822)]
+    pub moderated_at: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbModerationItem {
+    name: String,
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+    author: String,
+    status: ModerationStatus,
+    submitted_at: u64,
+    moderator: Option<String>,
+    reason: Option<String>,
+    moderated_at: Option<u64>,
+}
+
+impl From<DbModerationItem> for ModerationItem {
+    fn from(item: DbModerationItem) -> Self {
+        Self {
+            name: item.name,
+            content: item.content,
+            typ: item.typ,
+            author: item.author,
+            status: item.status,
+            submitted_at: item.submitted_at,
+            moderator: item.moderator,
+            reason: item.reason,
+            moderated_at: item.moderated_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ModerationQuery {
+    /// Which aliases to list, defaults to `pending`.
+    pub status: Option<ModerationStatus>,
+}
+
+/// Get the aliases in the moderation queue, oldest submissions first.
+/// # Note
+/// Moderators see the submissions of everyone, other users only see their own. Pass
+/// `status=rejected` to see why submissions were rejected.
+#[utoipa::path(
+    get,
+    path = "/api/moderation/queue",
+    responses(
+        (status = 200, description = "The submissions with the status are returned.", body = [ModerationItem]),
+        (status = 400, description = "The status is invalid."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(ModerationQuery),
+)]
+pub async fn get_queue(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<ModerationQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+            let status = query.status.unwrap_or(ModerationStatus::Pending);
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+
+            state
+                .db
+                .call(move |conn| get_items(conn, status, &viewer).map(Json))
+                .await
+        })
+        .await
+}
+
+pub(crate) fn get_items(
+    conn: &Connection,
+    status: ModerationStatus,
+    viewer: &Viewer,
+) -> Result<Vec<ModerationItem>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                a.name,
+                a.content,
+                at.name as type,
+                a.author,
+                mq.status,
+                mq.submitted_at,
+                mq.moderator,
+                mq.reason,
+                mq.moderated_at
+            FROM alias_moderation mq
+            JOIN aliases a ON a.name = mq.alias
+            JOIN alias_types at ON at.id = a.type
+            WHERE mq.status = ? AND a.deleted_at IS NULL AND (? OR a.author = ? COLLATE NOCASE)
+            ORDER BY mq.submitted_at, a.name",
+        )
+        .context("Failed to prepare statement for moderation queue query")?;
+
+    let items = stmt
+        .query_map(
+            params![status.as_str(), viewer.moderator, viewer.name],
+            |row| {
+                Ok(ModerationItem::from(
+                    from_row::<DbModerationItem>(row).unwrap(),
+                ))
+            },
+        )
+        .context("Failed to query moderation queue")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect moderation queue")?;
+
+    Ok(items)
+}
+
+/// The decision of a moderator on a pending alias.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationDecision {
+    /// Why the alias was approved or rejected, shown to the author. Required when rejecting.
+    #[schema(example = "Already exists as lol.png")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub reason: Option<String>,
+}
+
+/// Approve a pending alias so everyone can see it.
+/// # Note
+/// Requires `moderate-aliases` permission.
+#[utoipa::path(
+    post,
+    path = "/api/moderation/{name}/approve",
+    request_body = ModerationDecision,
+    responses(
+        (status = 200, description = "The alias was approved."),
+        (status = 400, description = "The alias is not pending."),
+        (status = 404, description = "No alias with that name was submitted for moderation."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to approve."),
+    ),
+)]
+pub async fn post_approve(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<ModerationDecision>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let names = vec![name.clone()];
+            let Json(request) = request?;
+
+            let events = state.events.clone();
+            state
+                .db
+                .call(move |conn| {
+                    decide(
+                        conn,
+                        &events,
+                        &request_id,
+                        &name,
+                        &payload.name,
+                        ModerationStatus::Approved,
+                        request.reason,
+                    )
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Reject a pending alias, it stays hidden from everyone but its author and moderators.
+/// # Note
+/// Requires `moderate-aliases` permission. The author can delete the rejected alias to free up its
+/// name.
+#[utoipa::path(
+    post,
+    path = "/api/moderation/{name}/reject",
+    request_body = ModerationDecision,
+    responses(
+        (status = 200, description = "The alias was rejected."),
+        (status = 400, description = "The alias is not pending or the reason is missing."),
+        (status = 404, description = "No alias with that name was submitted for moderation."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to reject."),
+    ),
+)]
+pub async fn post_reject(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<ModerationDecision>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+            if request.reason.is_none() {
+                return Err(Error::EmptyField("reason"));
+            }
+
+            let events = state.events.clone();
+            state
+                .db
+                .call(move |conn| {
+                    decide(
+                        conn,
+                        &events,
+                        &request_id,
+                        &name,
+                        &payload.name,
+                        ModerationStatus::Rejected,
+                        request.reason,
+                    )
+                })
+                .await
+        })
+        .await
+}
+
+fn decide(
+    conn: &mut Connection,
+    events: &EventHub,
+    request_id: &RequestId,
+    name: &str,
+    moderator: &str,
+    status: ModerationStatus,
+    reason: Option<String>,
+) -> Result<(), Error> {
+    let tx = conn.transaction().context("Failed to create transaction")?;
+
+    let current = tx
+        .query_row(
+            "SELECT mq.status
+            FROM alias_moderation mq
+            JOIN aliases a ON a.name = mq.alias
+            WHERE mq.alias = ? AND a.deleted_at IS NULL",
+            params![name],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()
+        .context("Failed to query moderation status")?
+        .ok_or(Error::NotFound)?;
+
+    if current != ModerationStatus::Pending.as_str() {
+        return Err(Error::NotPending(name.to_owned()));
+    }
+
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    tx.execute(
+        "UPDATE alias_moderation
+        SET status = ?, moderator = ?, reason = ?, moderated_at = ?
+        WHERE alias = ?",
+        params![status.as_str(), moderator, reason, now, name],
+    )
+    .context("Failed to update moderation status")?;
+
+    if status == ModerationStatus::Approved {
+        tx.execute(
+            "UPDATE aliases SET published_at = COALESCE(published_at, ?) WHERE name = ?",
+            params![now, name],
+        )
+        .context("Failed to publish alias")?;
+    }
+
+    let action = match status {
+        ModerationStatus::Rejected => AuditAction::AliasReject,
+        _ => AuditAction::AliasApprove,
+    };
+    audit::record(
+        &tx,
+        Some(request_id),
+        moderator,
+        action,
+        name,
+        Some(json!({ "status": current })),
+        Some(json!({ "status": status, "reason": reason })),
+    )?;
+
+    let alias = alias::get_by_name(&tx, name.to_owned())?;
+    if status == ModerationStatus::Approved {
+        webhook::enqueue(&tx, WebhookEvent::AliasCreated, &alias, None)?;
+    }
+
+    tx.commit().context("Failed to commit transaction")?;
+    // Only the author and moderators could see the alias before it was approved.
+    let kind = match status {
+        ModerationStatus::Approved => AliasEventKind::Created,
+        _ => AliasEventKind::Updated,
+    };
+    events.publish(kind, alias, None);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with the published alias `fb` by alice and the alias `new` by bob which is
+    /// waiting for its first moderation.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at)
+                VALUES ('alice', 0), ('bob', 0), ('mod', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, published_at)
+                VALUES ('fb', 'foobar', 1, 'alice', 0, 0);
+                INSERT INTO aliases (name, content, type, author, created_at)
+                VALUES ('new', 'secret', 1, 'bob', 0);",
+            )
+            .unwrap();
+            submit(conn, "new", 0).unwrap();
+        })
+        .await;
+        db
+    }
+
+    fn moderator() -> Viewer {
+        Viewer::new("mod", &[MODERATOR_GROUP.to_owned()])
+    }
+
+    fn queue(conn: &Connection, status: ModerationStatus, viewer: &Viewer) -> Vec<String> {
+        get_items(conn, status, viewer)
+            .unwrap()
+            .into_iter()
+            .map(|item| item.name)
+            .collect()
+    }
+
+    fn decide_as_mod(
+        conn: &mut Connection,
+        name: &str,
+        status: ModerationStatus,
+    ) -> Result<(), Error> {
+        let request_id = RequestId("test".to_owned());
+        let reason = Some("because".to_owned());
+        decide(
+            conn,
+            &EventHub::new(),
+            &request_id,
+            name,
+            "mod",
+            status,
+            reason,
+        )
+    }
+
+    #[tokio::test]
+    async fn only_authors_and_moderators_see_pending_aliases() {
+        let db = setup().await;
+        db.call(|conn| {
+            let alias = alias::get_by_name(conn, "new".to_owned()).unwrap();
+            assert_eq!(alias.status, ModerationStatus::Pending);
+
+            assert!(Viewer::new("bob", &[]).can_see(&alias));
+            assert!(Viewer::new("Bob", &[]).can_see(&alias));
+            assert!(moderator().can_see(&alias));
+            assert!(!Viewer::new("alice", &[]).can_see(&alias));
+
+            let pending = ModerationStatus::Pending;
+            assert_eq!(queue(conn, pending, &Viewer::new("BOB", &[])), ["new"]);
+            assert_eq!(queue(conn, pending, &moderator()), ["new"]);
+            assert!(queue(conn, pending, &Viewer::new("alice", &[])).is_empty());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn approving_publishes_aliases() {
+        let db = setup().await;
+        db.call(|conn| {
+            decide_as_mod(conn, "new", ModerationStatus::Approved).unwrap();
+
+            let alias = alias::get_by_name(conn, "new".to_owned()).unwrap();
+            assert_eq!(alias.status, ModerationStatus::Approved);
+            assert!(Viewer::new("alice", &[]).can_see(&alias));
+
+            let published_at = conn
+                .query_row(
+                    "SELECT published_at FROM aliases WHERE name = 'new'",
+                    [],
+                    |row| row.get::<_, Option<u64>>(0),
+                )
+                .unwrap();
+            assert!(published_at.is_some());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn rejected_aliases_stay_hidden() {
+        let db = setup().await;
+        db.call(|conn| {
+            decide_as_mod(conn, "new", ModerationStatus::Rejected).unwrap();
+
+            let alias = alias::get_by_name(conn, "new".to_owned()).unwrap();
+            assert_eq!(alias.status, ModerationStatus::Rejected);
+            assert!(!Viewer::new("alice", &[]).can_see(&alias));
+
+            let rejected =
+                get_items(conn, ModerationStatus::Rejected, &Viewer::new("bob", &[])).unwrap();
+            assert_eq!(rejected[0].moderator.as_deref(), Some("mod"));
+            assert_eq!(rejected[0].reason.as_deref(), Some("because"));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn only_decides_on_pending_aliases() {
+        let db = setup().await;
+        db.call(|conn| {
+            decide_as_mod(conn, "new", ModerationStatus::Approved).unwrap();
+
+            assert!(matches!(
+                decide_as_mod(conn, "new", ModerationStatus::Rejected),
+                Err(Error::NotPending(_))
+            ));
+            assert!(matches!(
+                decide_as_mod(conn, "fb", ModerationStatus::Approved),
+                Err(Error::NotFound)
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn resubmitting_clears_the_previous_decision() {
+        let db = setup().await;
+        db.call(|conn| {
+            decide_as_mod(conn, "new", ModerationStatus::Rejected).unwrap();
+            submit(conn, "new", 1).unwrap();
+
+            let items = get_items(conn, ModerationStatus::Pending, &moderator()).unwrap();
+            assert_eq!(items.len(), 1);
+            assert_eq!(items[0].submitted_at, 1);
+            assert_eq!(items[0].moderator, None);
+            assert_eq!(items[0].reason, None);
+        })
+        .await;
+    }
+}
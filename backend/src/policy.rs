@@ -0,0 +1,111 @@
+//! Who may change an alias. Authors can always edit and delete their own aliases, the
+//! `edit-aliases` and `delete-aliases` groups allow doing so for the aliases of other users.
+//! Handlers check this after looking up the alias, in the same transaction as the change.
+
+use crate::error::Error;
+
+/// An action on an existing alias. The author of an alias can always take it, everyone else
+/// needs the moderator group of the action.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum AliasAction {
+    Edit,
+    Delete,
+}
+
+impl AliasAction {
+    /// The group which allows taking the action on the aliases of other users.
+    pub fn group(self) -> &'static str {
+        match self {
+            AliasAction::Edit => "edit-aliases",
+            AliasAction::Delete => "delete-aliases",
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            AliasAction::Edit => "edit",
+            AliasAction::Delete => "delete",
+        }
+    }
+}
+
+/// Whether the user `name` with `groups` may take `action` on an alias created by `author`. Like
+/// usernames in the database, the names are compared ignoring ASCII case.
+pub(crate) fn is_allowed(name: &str, groups: &[String], author: &str, action: AliasAction) -> bool {
+    name.eq_ignore_ascii_case(author) || groups.iter().any(|group| group == action.group())
+}
+
+/// Fails with [`Error::Forbidden`] unless the user may take `action` on an alias created by
+/// `author`.
+pub(crate) fn authorize(
+    name: &str,
+    groups: &[String],
+    author: &str,
+    action: AliasAction,
+) -> Result<(), Error> {
+    if is_allowed(name, groups, author, action) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden {
+            action: action.verb(),
+            group: action.group(),
+        })
+    }
+}
+
+/// Fails with [`Error::ForbiddenForOthers`] unless the user may take `action` on the aliases of
+/// every user, which changes affecting the aliases of many users like renaming a tag need.
+pub(crate) fn authorize_for_all(groups: &[String], action: AliasAction) -> Result<(), Error> {
+    if groups.iter().any(|group| group == action.group()) {
+        Ok(())
+    } else {
+        Err(Error::ForbiddenForOthers {
+            action: action.verb(),
+            group: action.group(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authors_can_change_their_aliases() {
+        assert!(is_allowed("alice", &[], "alice", AliasAction::Edit));
+        assert!(is_allowed("alice", &[], "alice", AliasAction::Delete));
+        assert!(authorize("alice", &[], "alice", AliasAction::Delete).is_ok());
+    }
+
+    #[test]
+    fn other_users_need_the_group_of_the_action() {
+        let editors = ["edit-aliases".to_owned()];
+
+        assert!(!is_allowed("bob", &[], "alice", AliasAction::Edit));
+        assert!(is_allowed("bob", &editors, "alice", AliasAction::Edit));
+        assert!(!is_allowed("bob", &editors, "alice", AliasAction::Delete));
+        assert!(matches!(
+            authorize("bob", &editors, "alice", AliasAction::Delete),
+            Err(Error::Forbidden {
+                group: "delete-aliases",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn ignores_the_case_of_names() {
+        assert!(is_allowed("Alice", &[], "alice", AliasAction::Edit));
+        assert!(is_allowed("alice", &[], "ALICE", AliasAction::Delete));
+        assert!(!is_allowed("alicia", &[], "alice", AliasAction::Edit));
+    }
+
+    #[test]
+    fn changes_affecting_everyone_need_the_group() {
+        assert!(authorize_for_all(&["edit-aliases".to_owned()], AliasAction::Edit).is_ok());
+        assert!(matches!(
+            authorize_for_all(&[], AliasAction::Edit),
+            Err(Error::ForbiddenForOthers { .. })
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+//! Reads the format, dimensions and frame count of images from their headers without decoding
+//! the pixel data.
+
+use crate::alias_type::{AliasType, BUILTIN_TYPES};
+use crate::media::MediaFormat;
+
+/// Images which are at most this many pixels wide and high are emotes when the `emote` type has no
+/// maximum size, this matches the threshold the frontend uses.
+pub const DEFAULT_EMOTE_SIZE: u32 = 40;
+
+/// What could be read from the header of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: MediaFormat,
+    pub width: u32,
+    pub height: u32,
+    /// The number of frames, more than one means the image is animated.
+    pub frames: u32,
+}
+
+impl ImageInfo {
+    pub fn is_animated(&self) -> bool {
+        self.frames > 1
+    }
+
+    /// The built-in type fitting the image, images which are at most `emote_size` pixels wide and
+    /// high are emotes and animated images are animated emotes or gifs.
+    pub fn detected_type(&self, emote_size: u32) -> AliasType {
+        let small = self.width <= emote_size && self.height <= emote_size;
+
+        match (small, self.is_animated()) {
+            (true, true) => AliasType::from("animatedEmote"),
+            (true, false) => AliasType::from("emote"),
+            (false, true) => AliasType::from("gif"),
+            (false, false) => AliasType::from("image"),
+        }
+    }
+
+    /// Returns the type an alias with this image should have when it was given `requested`. The
+    /// built-in media types are replaced with the detected type while text and custom types are
+    /// left alone.
+    pub fn enforced_type(&self, requested: AliasType, emote_size: u32) -> AliasType {
+        if !requested.is_text() && BUILTIN_TYPES.contains(&requested.as_str()) {
+            self.detected_type(emote_size)
+        } else {
+            requested
+        }
+    }
+}
+
+/// Reads the header of a PNG, JPEG, GIF or WebP image, returning `None` if it is another format
+/// or the header is malformed.
+pub fn probe(data: &[u8]) -> Option<ImageInfo> {
+    let format = MediaFormat::detect(data)?;
+
+    let (width, height, frames) = match format {
+        MediaFormat::Png => probe_png(data)?,
+        MediaFormat::Jpeg => probe_jpeg(data)?,
+        MediaFormat::Gif => probe_gif(data)?,
+        MediaFormat::Webp => probe_webp(data)?,
+    };
+
+    if width == 0 || height == 0 || frames == 0 {
+        return None;
+    }
+
+    Some(ImageInfo {
+        format,
+        width,
+        height,
+        frames,
+    })
+}
+
+fn u16_le(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn u16_be(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+fn u24_le(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+fn u32_le(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_be(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// PNG starts with an `IHDR` chunk, animated PNGs have an `acTL` chunk with the number of frames
+/// before the image data.
+fn probe_png(data: &[u8]) -> Option<(u32, u32, u32)> {
+    if data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32_be(data, 16)?;
+    let height = u32_be(data, 20)?;
+
+    let mut frames = 1;
+    let mut at = 8;
+    while let (Some(length), Some(kind)) = (u32_be(data, at), data.get(at + 4..at + 8)) {
+        match kind {
+            b"acTL" => {
+                frames = u32_be(data, at + 8)?;
+                break;
+            }
+            b"IDAT" | b"IEND" => break,
+            _ => at += 12 + length as usize,
+        }
+    }
+
+    Some((width, height, frames))
+}
+
+/// JPEG is a list of segments, the dimensions are in the start of frame segment.
+fn probe_jpeg(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let mut at = 2;
+
+    loop {
+        if *data.get(at)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(at + 1)?;
+
+        match marker {
+            // Padding before a marker.
+            0xff => at += 1,
+            // Markers without a segment.
+            0x01 | 0xd0..=0xd7 => at += 2,
+            // Start of frame, except for the markers in that range which are something else.
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = u16_be(data, at + 5)?;
+                let width = u16_be(data, at + 7)?;
+                return Some((width, height, 1));
+            }
+            _ => at += 2 + u16_be(data, at + 2)? as usize,
+        }
+    }
+}
+
+/// GIF has the dimensions in the logical screen descriptor followed by a list of blocks, every
+/// image descriptor block is a frame.
+fn probe_gif(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let width = u16_le(data, 6)?;
+    let height = u16_le(data, 8)?;
+
+    let mut at = 13 + color_table_size(*data.get(10)?);
+    let mut frames = 0;
+
+    loop {
+        match *data.get(at)? {
+            // Extension, a label followed by sub-blocks.
+            0x21 => at = skip_sub_blocks(data, at + 2)?,
+            // Image descriptor, followed by the minimum code size and sub-blocks of image data.
+            0x2c => {
+                frames += 1;
+                let packed = *data.get(at + 9)?;
+                at = skip_sub_blocks(data, at + 11 + color_table_size(packed))?;
+            }
+            // Trailer.
+            0x3b => break,
+            _ => return None,
+        }
+    }
+
+    Some((width, height, frames))
+}
+
+fn color_table_size(packed: u8) -> usize {
+    if packed & 0x80 != 0 {
+        3 * (1 << ((packed & 0x07) + 1))
+    } else {
+        0
+    }
+}
+
+/// Skips GIF sub-blocks starting at `at`, returning the position after the terminating block.
+fn skip_sub_blocks(data: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let size = *data.get(at)? as usize;
+        at += 1;
+        if size == 0 {
+            return Some(at);
+        }
+        at += size;
+    }
+}
+
+/// WebP is a RIFF container, the first chunk says if the image is lossy, lossless or uses the
+/// extended format which can be animated.
+fn probe_webp(data: &[u8]) -> Option<(u32, u32, u32)> {
+    let kind = data.get(12..16)?;
+    let chunk = 20;
+
+    match kind {
+        b"VP8 " => {
+            if data.get(chunk + 3..chunk + 6)? != [0x9d, 0x01, 0x2a] {
+                return None;
+            }
+            let width = u16_le(data, chunk + 6)? & 0x3fff;
+            let height = u16_le(data, chunk + 8)? & 0x3fff;
+            Some((width, height, 1))
+        }
+        b"VP8L" => {
+            if *data.get(chunk)? != 0x2f {
+                return None;
+            }
+            let bits = u32_le(data, chunk + 1)?;
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+            Some((width, height, 1))
+        }
+        b"VP8X" => {
+            let animated = *data.get(chunk)? & 0x02 != 0;
+            let width = u24_le(data, chunk + 4)? + 1;
+            let height = u24_le(data, chunk + 7)? + 1;
+
+            let mut frames = 1;
+            if animated {
+                frames = 0;
+                let mut at = 12;
+                while let (Some(kind), Some(size)) = (data.get(at..at + 4), u32_le(data, at + 4)) {
+                    if kind == b"ANMF" {
+                        frames += 1;
+                    }
+                    // Chunks are padded to an even size.
+                    at += 8 + size as usize + (size as usize & 1);
+                }
+            }
+
+            Some((width, height, frames))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::codecs::gif::GifEncoder;
+    use image::{DynamicImage, Frame, ImageOutputFormat, RgbaImage};
+
+    use std::io::Cursor;
+
+    fn encode(width: u32, height: u32, format: ImageOutputFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::new(width, height))
+            .write_to(&mut Cursor::new(&mut data), format)
+            .unwrap();
+        data
+    }
+
+    fn info(format: MediaFormat, width: u32, height: u32, frames: u32) -> Option<ImageInfo> {
+        Some(ImageInfo {
+            format,
+            width,
+            height,
+            frames,
+        })
+    }
+
+    fn riff(chunks: &[u8]) -> Vec<u8> {
+        let mut data = b"RIFF".to_vec();
+        data.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+        data.extend_from_slice(b"WEBP");
+        data.extend_from_slice(chunks);
+        data
+    }
+
+    #[test]
+    fn reads_png_headers() {
+        let data = encode(300, 20, ImageOutputFormat::Png);
+        assert_eq!(probe(&data), info(MediaFormat::Png, 300, 20, 1));
+    }
+
+    #[test]
+    fn reads_animated_png_headers() {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend_from_slice(&13u32.to_be_bytes());
+        data.extend_from_slice(b"IHDR");
+        data.extend_from_slice(&64u32.to_be_bytes());
+        data.extend_from_slice(&32u32.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(b"acTL");
+        data.extend_from_slice(&12u32.to_be_bytes());
+        data.extend_from_slice(&[0; 8]);
+
+        assert_eq!(probe(&data), info(MediaFormat::Png, 64, 32, 12));
+    }
+
+    #[test]
+    fn reads_jpeg_headers() {
+        let data = encode(17, 250, ImageOutputFormat::Jpeg(80));
+        assert_eq!(probe(&data), info(MediaFormat::Jpeg, 17, 250, 1));
+    }
+
+    #[test]
+    fn counts_gif_frames() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut data);
+            let frames = (0..3).map(|_| Frame::new(RgbaImage::new(30, 40)));
+            encoder.encode_frames(frames).unwrap();
+        }
+
+        assert_eq!(probe(&data), info(MediaFormat::Gif, 30, 40, 3));
+    }
+
+    #[test]
+    fn reads_lossy_webp_headers() {
+        let mut chunk = b"VP8 ".to_vec();
+        chunk.extend_from_slice(&10u32.to_le_bytes());
+        chunk.extend_from_slice(&[0, 0, 0, 0x9d, 0x01, 0x2a]);
+        chunk.extend_from_slice(&(640u16 | 0x4000).to_le_bytes());
+        chunk.extend_from_slice(&480u16.to_le_bytes());
+
+        assert_eq!(probe(&riff(&chunk)), info(MediaFormat::Webp, 640, 480, 1));
+    }
+
+    #[test]
+    fn reads_lossless_webp_headers() {
+        let mut chunk = b"VP8L".to_vec();
+        chunk.extend_from_slice(&5u32.to_le_bytes());
+        chunk.push(0x2f);
+        chunk.extend_from_slice(&((100u32 - 1) | (50 - 1) << 14).to_le_bytes());
+
+        assert_eq!(probe(&riff(&chunk)), info(MediaFormat::Webp, 100, 50, 1));
+    }
+
+    #[test]
+    fn counts_animated_webp_frames() {
+        let mut chunks = b"VP8X".to_vec();
+        chunks.extend_from_slice(&10u32.to_le_bytes());
+        chunks.extend_from_slice(&[0x02, 0, 0, 0]);
+        chunks.extend_from_slice(&(32u32 - 1).to_le_bytes()[..3]);
+        chunks.extend_from_slice(&(48u32 - 1).to_le_bytes()[..3]);
+        for _ in 0..2 {
+            chunks.extend_from_slice(b"ANMF");
+            chunks.extend_from_slice(&1u32.to_le_bytes());
+            // Padding to an even size.
+            chunks.extend_from_slice(&[0, 0]);
+        }
+
+        assert_eq!(probe(&riff(&chunks)), info(MediaFormat::Webp, 32, 48, 2));
+    }
+
+    #[test]
+    fn rejects_truncated_and_unknown_data() {
+        let data = encode(300, 20, ImageOutputFormat::Png);
+
+        assert_eq!(probe(&data[..20]), None);
+        assert_eq!(probe(b"GIF89a"), None);
+        assert_eq!(probe(&[0xff, 0xd8, 0xff]), None);
+        assert_eq!(probe(&riff(b"VP8 ")), None);
+        assert_eq!(probe(b"not an image"), None);
+    }
+
+    #[test]
+    fn detects_the_type_from_the_size_and_frames() {
+        let image = |width, height, frames| ImageInfo {
+            format: MediaFormat::Png,
+            width,
+            height,
+            frames,
+        };
+
+        assert_eq!(image(40, 40, 1).detected_type(40).as_str(), "emote");
+        assert_eq!(image(40, 41, 1).detected_type(40).as_str(), "image");
+        assert_eq!(image(40, 41, 1).detected_type(64).as_str(), "emote");
+        assert_eq!(image(20, 20, 2).detected_type(40).as_str(), "animatedEmote");
+        assert_eq!(image(200, 20, 2).detected_type(40).as_str(), "gif");
+    }
+
+    #[test]
+    fn only_enforces_built_in_media_types() {
+        let image = ImageInfo {
+            format: MediaFormat::Gif,
+            width: 500,
+            height: 500,
+            frames: 10,
+        };
+
+        assert_eq!(image.enforced_type("emote".into(), 40).as_str(), "gif");
+        assert_eq!(image.enforced_type("text".into(), 40).as_str(), "text");
+        assert_eq!(image.enforced_type("meme".into(), 40).as_str(), "meme");
+    }
+}
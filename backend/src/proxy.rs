@@ -0,0 +1,946 @@
+//! Serves external alias content through the server so the hosts of the images don't see the IPs
+//! of users and images keep working when their host goes down.
+
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{extract::Path, Extension};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_rusqlite::from_row;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::alias;
+use crate::error::Error;
+use crate::media::{self, MediaFormat, MAX_MEDIA_SIZE};
+use crate::moderation::Viewer;
+use crate::AppState;
+
+/// How large the cache can get if `PROXY_CACHE_MAX_MB` is not set.
+pub const DEFAULT_CACHE_MAX_MB: u64 = 512;
+
+/// The most redirects followed before giving up on a URL.
+pub const MAX_REDIRECTS: usize = 5;
+
+/// How long a single request may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How long fetched content is served from the cache before it is fetched again, since the
+/// content behind a URL can change.
+const REFETCH_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The content of an alias can change, so clients should check again after a day.
+const CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// How many pinned clients are kept before they are all dropped and created again as needed.
+const MAX_PINNED_CLIENTS: usize = 256;
+
+/// A response to a single request, redirects are not followed.
+#[derive(Debug, Clone)]
+pub struct UpstreamResponse {
+    pub status: u16,
+    /// The `Location` header of redirects.
+    pub location: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Resolves host names for clients which only connect to public addresses, see [`public_addr`].
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    /// Resolves a host name to the addresses it can be reached at.
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String>;
+}
+
+/// Fetches content for the proxy, implemented by [`HttpUpstream`] and replaceable in tests. The
+/// proxy checks every address it connects to, so implementations have to connect to the address
+/// they are given instead of resolving the host of the URL themselves.
+#[async_trait]
+pub trait Upstream: Resolver {
+    /// Requests `uri` from `addr`, failing if the body is larger than `limit` bytes.
+    async fn get(
+        &self,
+        uri: &Uri,
+        addr: SocketAddr,
+        limit: usize,
+    ) -> Result<UpstreamResponse, String>;
+}
+
+/// HTTP clients which connect to a host at a fixed address and don't follow redirects. They are
+/// kept between requests so connections to the same host can be reused.
+pub struct PinnedClients {
+    builder: fn() -> reqwest::ClientBuilder,
+    clients: Mutex<HashMap<(String, SocketAddr), reqwest::Client>>,
+}
+
+impl PinnedClients {
+    /// Creates the clients from the configuration of `builder`.
+    pub fn new(builder: fn() -> reqwest::ClientBuilder) -> Self {
+        Self {
+            builder,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the client which connects to `host` at `addr`.
+    pub fn get(&self, host: &str, addr: SocketAddr) -> Result<reqwest::Client, String> {
+        let mut clients = self.clients.lock().unwrap();
+
+        let key = (host.to_owned(), addr);
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        // Pinning the address stops the host from resolving to a different one than was checked.
+        let client = (self.builder)()
+            .redirect(reqwest::redirect::Policy::none())
+            .resolve(host, addr)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        if clients.len() >= MAX_PINNED_CLIENTS {
+            clients.clear();
+        }
+        clients.insert(key, client.clone());
+
+        Ok(client)
+    }
+}
+
+/// Resolves `host` with the system resolver.
+pub async fn lookup_host(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    tokio::net::lookup_host((host, port))
+        .await
+        .map(Iterator::collect)
+        .map_err(|e| e.to_string())
+}
+
+/// Fetches content over HTTP using the system resolver.
+pub struct HttpUpstream {
+    clients: PinnedClients,
+}
+
+impl Default for HttpUpstream {
+    fn default() -> Self {
+        Self {
+            clients: PinnedClients::new(|| reqwest::Client::builder().timeout(REQUEST_TIMEOUT)),
+        }
+    }
+}
+
+#[async_trait]
+impl Resolver for HttpUpstream {
+    async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+        lookup_host(host, port).await
+    }
+}
+
+#[async_trait]
+impl Upstream for HttpUpstream {
+    async fn get(
+        &self,
+        uri: &Uri,
+        addr: SocketAddr,
+        limit: usize,
+    ) -> Result<UpstreamResponse, String> {
+        let host = uri.host().ok_or("The URL has no host")?;
+        let client = self.clients.get(host, addr)?;
+
+        let mut response = client
+            .get(uri.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status().as_u16();
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            if body.len() + chunk.len() > limit {
+                return Err(format!("The response is larger than {limit} bytes"));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(UpstreamResponse {
+            status,
+            location,
+            body,
+        })
+    }
+}
+
+/// Fetched content stored on disk, evicting the least recently used content when it gets too
+/// large.
+pub struct ProxyCache {
+    dir: PathBuf,
+    max_size: u64,
+    upstream: Arc<dyn Upstream>,
+}
+
+/// Content served by the proxy.
+pub struct Cached {
+    pub data: Vec<u8>,
+    pub mime: String,
+    /// Hex encoded SHA-256 of the content.
+    pub etag: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbCacheEntry {
+    file: String,
+    mime: String,
+    etag: String,
+    fetched_at: u64,
+}
+
+impl ProxyCache {
+    /// Reads the configuration from `PROXY_CACHE_DIR` and `PROXY_CACHE_MAX_MB`, creating the
+    /// directory if it doesn't exist yet.
+    pub async fn from_env(upstream: Arc<dyn Upstream>) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(
+            std::env::var("PROXY_CACHE_DIR").unwrap_or_else(|_| "proxy-cache".into()),
+        );
+        let max_mb = match std::env::var("PROXY_CACHE_MAX_MB") {
+            Ok(mb) => mb
+                .parse()
+                .context("PROXY_CACHE_MAX_MB could not be parsed")?,
+            Err(_) => DEFAULT_CACHE_MAX_MB,
+        };
+
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Failed to create proxy cache directory {}", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            max_size: max_mb * 1024 * 1024,
+            upstream,
+        })
+    }
+
+    /// Returns the content at `url` from the cache, fetching it if it isn't cached yet or was
+    /// fetched more than a day ago. Outdated content is still served if it can't be fetched again.
+    pub async fn get(&self, db: &tokio_rusqlite::Connection, url: &str) -> Result<Cached, Error> {
+        let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+        let cached_url = url.to_owned();
+        let entry = db.call(move |conn| touch(conn, &cached_url, now)).await?;
+        let mut outdated = None;
+        if let Some(entry) = entry {
+            // The file might have been evicted by another request in the meantime.
+            if let Ok(data) = tokio::fs::read(self.dir.join(&entry.file)).await {
+                let cached = Cached {
+                    data,
+                    mime: entry.mime,
+                    etag: entry.etag,
+                };
+                if now.saturating_sub(entry.fetched_at) < REFETCH_AFTER.as_secs() {
+                    return Ok(cached);
+                }
+                outdated = Some(cached);
+            }
+        }
+
+        let data = match (self.fetch(url).await, outdated) {
+            (Ok(data), _) => data,
+            (Err(e), Some(outdated)) => {
+                warn!("Serving outdated content of {url} from the proxy cache: {e}");
+                return Ok(outdated);
+            }
+            (Err(e), None) => return Err(e),
+        };
+        // The type is sniffed from the content since external hosts often send the wrong one.
+        let format = MediaFormat::detect(&data)
+            .ok_or_else(|| Error::ProxyFailed(format!("{url} is not a supported image")))?;
+
+        let file = format!("{:x}", Sha256::digest(url.as_bytes()));
+        let etag = format!("{:x}", Sha256::digest(&data));
+        media::write(&self.dir.join(&file), &data).await?;
+
+        let cached = Cached {
+            data,
+            mime: format.mime().to_owned(),
+            etag,
+        };
+
+        let (url, mime, etag, size, max_size) = (
+            url.to_owned(),
+            cached.mime.clone(),
+            cached.etag.clone(),
+            cached.data.len(),
+            self.max_size,
+        );
+        let evicted = db
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO proxy_cache (url, file, mime, etag, size, fetched_at, used_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?)
+                    ON CONFLICT (url) DO UPDATE SET
+                        mime = excluded.mime,
+                        etag = excluded.etag,
+                        size = excluded.size,
+                        fetched_at = excluded.fetched_at,
+                        used_at = excluded.used_at",
+                    params![url, file, mime, etag, size, now, now],
+                )
+                .context("Failed to insert proxy cache entry")?;
+
+                evict(conn, max_size)
+            })
+            .await?;
+
+        for file in evicted {
+            // The file might already be gone, it is not served without its entry either way.
+            let _ = tokio::fs::remove_file(self.dir.join(file)).await;
+        }
+
+        Ok(cached)
+    }
+
+    /// Fetches `url`, following redirects as long as they stay on public addresses.
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, Error> {
+        let mut uri = parse_url(url)?;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let addr = public_addr(self.upstream.as_ref(), &uri).await?;
+            let response = self
+                .upstream
+                .get(&uri, addr, MAX_MEDIA_SIZE)
+                .await
+                .map_err(|e| Error::ProxyFailed(format!("Failed to fetch {uri}: {e}")))?;
+
+            match (response.status, response.location) {
+                (200..=299, _) => return Ok(response.body),
+                (300..=399, Some(location)) => uri = redirect_target(&uri, &location)?,
+                (status, _) => {
+                    return Err(Error::ProxyFailed(format!(
+                        "{uri} responded with status {status}"
+                    )))
+                }
+            }
+        }
+
+        Err(Error::ProxyFailed(format!(
+            "{url} redirected more than {MAX_REDIRECTS} times"
+        )))
+    }
+}
+
+/// Resolves the host of `uri` with `resolver`, failing if any of its addresses is not public. The
+/// address which is returned has to be connected to instead of resolving the host again.
+pub async fn public_addr<R: Resolver + ?Sized>(
+    resolver: &R,
+    uri: &Uri,
+) -> Result<SocketAddr, Error> {
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::ProxyFailed(format!("{uri} has no host")))?;
+    let port = uri
+        .port_u16()
+        .unwrap_or(if uri.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        });
+
+    // IPv6 addresses are in brackets in URLs.
+    let addrs = match host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<IpAddr>()
+    {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => resolver
+            .resolve(host, port)
+            .await
+            .map_err(|e| Error::ProxyFailed(format!("Failed to resolve {host}: {e}")))?,
+    };
+
+    // All addresses have to be public, otherwise which one is used could be influenced.
+    if addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err(Error::ProxyBlocked(host.to_owned()));
+    }
+
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::ProxyFailed(format!("{host} has no addresses")))
+}
+
+/// Returns the cache entry for `url` if there is one, marking it as used.
+fn touch(conn: &Connection, url: &str, now: u64) -> Result<Option<DbCacheEntry>, Error> {
+    let entry = conn
+        .query_row(
+            "UPDATE proxy_cache
+            SET used_at = ?
+            WHERE url = ?
+            RETURNING file, mime, etag, fetched_at",
+            params![now, url],
+            |row| Ok(from_row::<DbCacheEntry>(row).unwrap()),
+        )
+        .optional()
+        .context("Failed to query proxy cache")?;
+
+    Ok(entry)
+}
+
+/// Removes the least recently used entries until the cache is at most `max_size` bytes, returning
+/// the files of the removed entries.
+fn evict(conn: &mut Connection, max_size: u64) -> Result<Vec<String>, Error> {
+    let tx = conn.transaction().context("Failed to create transaction")?;
+
+    let entries = tx
+        .prepare("SELECT url, file, size FROM proxy_cache ORDER BY used_at DESC, fetched_at DESC")
+        .context("Failed to prepare statement for proxy cache query")?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u64>(2)?,
+            ))
+        })
+        .context("Failed to query proxy cache")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect proxy cache entries")?;
+
+    let mut size = 0;
+    let mut evicted = Vec::new();
+    for (url, file, entry_size) in entries {
+        size += entry_size;
+        if size > max_size {
+            tx.execute("DELETE FROM proxy_cache WHERE url = ?", params![url])
+                .context("Failed to evict proxy cache entry")?;
+            evicted.push(file);
+        }
+    }
+
+    tx.commit().context("Failed to commit transaction")?;
+
+    Ok(evicted)
+}
+
+pub(crate) fn parse_url(url: &str) -> Result<Uri, Error> {
+    let uri = url
+        .parse::<Uri>()
+        .map_err(|_| Error::ProxyFailed(format!("{url} is not a valid URL")))?;
+
+    match uri.scheme_str() {
+        Some("http" | "https") if uri.host().is_some() => Ok(uri),
+        _ => Err(Error::ProxyFailed(format!(
+            "{url} is not an HTTP or HTTPS URL"
+        ))),
+    }
+}
+
+/// Resolves the `Location` of a redirect from `current`.
+pub(crate) fn redirect_target(current: &Uri, location: &str) -> Result<Uri, Error> {
+    let scheme = current.scheme_str().unwrap_or("https");
+    let authority = current
+        .authority()
+        .map(|authority| authority.as_str())
+        .unwrap_or_default();
+
+    let target = if location.contains("://") {
+        location.to_owned()
+    } else if location.starts_with("//") {
+        format!("{scheme}:{location}")
+    } else if location.starts_with('/') {
+        format!("{scheme}://{authority}{}", remove_dot_segments(location))
+    } else {
+        let path = current.path();
+        let directory = &path[..path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+        let location = remove_dot_segments(&format!("{directory}{location}"));
+        format!("{scheme}://{authority}{location}")
+    };
+
+    parse_url(&target)
+}
+
+/// Resolves the `.` and `..` segments in the path of an absolute reference, leaving the query
+/// untouched.
+fn remove_dot_segments(reference: &str) -> String {
+    let (path, query) = match reference.find(['?', '#']) {
+        Some(i) => reference.split_at(i),
+        None => (reference, ""),
+    };
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in path.split('/').skip(1) {
+        trailing_slash = matches!(segment, "." | ".." | "");
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    if trailing_slash && segments.last().map(|s| !s.is_empty()).unwrap_or(false) {
+        segments.push("");
+    }
+
+    format!("/{}{query}", segments.join("/"))
+}
+
+/// Whether `ip` can be reached on the public internet, which rules out loopback, private, link
+/// local, shared, documentation and reserved ranges.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", 0.0.0.0/8.
+        || a == 0
+        // Shared address space for carrier-grade NAT, 100.64.0.0/10.
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15.
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved, 240.0.0.0/4.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+
+    // Addresses with an embedded IPv4 address are as public as that address: IPv4 mapped
+    // (::ffff:0:0/96), IPv4 compatible (::/96) and NAT64 (64:ff9b::/96).
+    if let Some(ipv4) = ip.to_ipv4() {
+        return is_public_v4(ipv4);
+    }
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [.., high, low] = segments;
+        return is_public_v4(Ipv4Addr::from(((high as u32) << 16) | low as u32));
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link local, fe80::/10.
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Site local, deprecated but still routed by some networks, fec0::/10.
+        || (segments[0] & 0xffc0) == 0xfec0
+        // Documentation, 2001:db8::/32.
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// Get the content of an image alias through the server instead of from the host it points at.
+/// # Note
+/// The content is cached on disk and fetched again once a day, the least recently used content is
+/// evicted when the cache gets too large. Only PNG, JPEG, GIF and WebP images of at most 8 MiB are served
+/// and URLs which resolve or redirect to private addresses are refused. Aliases with hosted media
+/// are redirected to the media. Aliases waiting for moderation or hidden after being reported are
+/// only found for their author and moderators.
+#[utoipa::path(
+    get,
+    path = "/api/alias/{name}/content",
+    responses(
+        (status = 200, description = "The content of the alias is returned."),
+        (status = 304, description = "The content matches the `If-None-Match` header."),
+        (status = 307, description = "Redirects to the hosted media of the alias."),
+        (status = 400, description = "The content of the alias is not a URL."),
+        (status = 403, description = "The URL points at a private address."),
+        (status = 404, description = "No alias with that name exists."),
+        (status = 502, description = "The content could not be fetched or is not a supported image."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to get the content of."),
+    )
+)]
+pub async fn get_alias_content(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    request_headers: HeaderMap,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let alias = state
+                .db
+                .call(move |conn| {
+                    let alias = alias::get_by_name(conn, name)?;
+                    if !viewer.can_see(&alias) {
+                        return Err(Error::NotFound);
+                    }
+
+                    Ok::<_, Error>(alias)
+                })
+                .await?;
+
+            if alias.media.is_some() {
+                return Ok(Redirect::temporary(&alias.content).into_response());
+            }
+            if alias.typ.is_text() || parse_url(&alias.content).is_err() {
+                return Err(Error::NotAUrl);
+            }
+
+            let cached = state.proxy.get(&state.db, &alias.content).await?;
+
+            let etag = format!("\"{}\"", cached.etag);
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static(CACHE_CONTROL),
+            );
+            headers.insert(
+                header::ETAG,
+                HeaderValue::from_str(&etag).context("Failed to create etag header")?,
+            );
+
+            let matches = request_headers
+                .get(header::IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| {
+                    value
+                        .split(',')
+                        .any(|tag| tag.trim() == etag || tag.trim() == "*")
+                });
+            if matches == Some(true) {
+                return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+            }
+
+            headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_str(&cached.mime)
+                    .context("Failed to create content type header")?,
+            );
+
+            Ok::<Response, Error>((StatusCode::OK, headers, cached.data).into_response())
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nimage";
+
+    /// Resolves the hosts in `hosts` and answers every request with the next of `responses`,
+    /// remembering the requests.
+    struct StubUpstream {
+        hosts: Vec<(&'static str, IpAddr)>,
+        responses: Mutex<Vec<UpstreamResponse>>,
+        requests: Mutex<Vec<(String, SocketAddr)>>,
+    }
+
+    impl StubUpstream {
+        fn new(hosts: Vec<(&'static str, &str)>, responses: Vec<UpstreamResponse>) -> Arc<Self> {
+            Arc::new(Self {
+                hosts: hosts
+                    .into_iter()
+                    .map(|(host, ip)| (host, ip.parse().unwrap()))
+                    .collect(),
+                responses: Mutex::new(responses),
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn requested(&self) -> Vec<String> {
+            let requests = self.requests.lock().unwrap();
+            requests.iter().map(|(uri, _)| uri.clone()).collect()
+        }
+    }
+
+    #[async_trait]
+    impl Resolver for StubUpstream {
+        async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+            Ok(self
+                .hosts
+                .iter()
+                .filter(|(name, _)| *name == host)
+                .map(|(_, ip)| SocketAddr::new(*ip, port))
+                .collect())
+        }
+    }
+
+    #[async_trait]
+    impl Upstream for StubUpstream {
+        async fn get(
+            &self,
+            uri: &Uri,
+            addr: SocketAddr,
+            _limit: usize,
+        ) -> Result<UpstreamResponse, String> {
+            self.requests.lock().unwrap().push((uri.to_string(), addr));
+            let mut responses = self.responses.lock().unwrap();
+            if responses.is_empty() {
+                return Err("No more responses".into());
+            }
+            Ok(responses.remove(0))
+        }
+    }
+
+    fn ok(body: &[u8]) -> UpstreamResponse {
+        UpstreamResponse {
+            status: 200,
+            location: None,
+            body: body.to_vec(),
+        }
+    }
+
+    fn redirect(location: &str) -> UpstreamResponse {
+        UpstreamResponse {
+            status: 302,
+            location: Some(location.to_owned()),
+            body: Vec::new(),
+        }
+    }
+
+    fn cache(upstream: Arc<StubUpstream>, dir: PathBuf) -> ProxyCache {
+        ProxyCache {
+            dir,
+            max_size: u64::MAX,
+            upstream,
+        }
+    }
+
+    #[test]
+    fn public_addresses() {
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "2606:4700::1111",
+            "::ffff:93.184.216.34",
+            "64:ff9b::5db8:d822",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[test]
+    fn non_public_addresses() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.0.0.1",
+            "100.64.0.1",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "192.0.0.8",
+            "192.0.2.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+            "2001:db8::1",
+            // IPv4 mapped, IPv4 compatible and NAT64 addresses of private IPv4 addresses.
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::192.168.1.1",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip} should not be public");
+        }
+    }
+
+    #[test]
+    fn resolves_redirect_targets() {
+        let current = "https://example.com/images/a/cat.png?size=2"
+            .parse::<Uri>()
+            .unwrap();
+
+        for (location, expected) in [
+            ("http://other.com/x.png", "http://other.com/x.png"),
+            ("//cdn.example.com/x.png", "https://cdn.example.com/x.png"),
+            ("/x.png", "https://example.com/x.png"),
+            ("/a/../b/./x.png", "https://example.com/b/x.png"),
+            ("dog.png", "https://example.com/images/a/dog.png"),
+            ("../dog.png?v=1", "https://example.com/images/dog.png?v=1"),
+            ("../../../../dog.png", "https://example.com/dog.png"),
+        ] {
+            assert_eq!(
+                redirect_target(&current, location).unwrap().to_string(),
+                expected,
+                "{location}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_non_http_redirect_targets() {
+        let current = "https://example.com/cat.png".parse::<Uri>().unwrap();
+
+        for location in ["file:///etc/passwd", "ftp://example.com/x.png", "http://"] {
+            assert!(
+                redirect_target(&current, location).is_err(),
+                "{location} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn removes_dot_segments() {
+        for (reference, expected) in [
+            ("/", "/"),
+            ("/a/b/c", "/a/b/c"),
+            ("/a/./b", "/a/b"),
+            ("/a/b/../c", "/a/c"),
+            ("/a/b/..", "/a/"),
+            ("/a/b/.", "/a/b/"),
+            ("/a/b/", "/a/b/"),
+            ("/../../a", "/a"),
+            ("/a/../../b/", "/b/"),
+            ("/a/b?c=../d", "/a/b?c=../d"),
+            ("/a/../b#../c", "/b#../c"),
+        ] {
+            assert_eq!(remove_dot_segments(reference), expected, "{reference}");
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_on_public_addresses() {
+        let upstream = StubUpstream::new(
+            vec![
+                ("example.com", "93.184.216.34"),
+                ("cdn.example.com", "2606:4700::1111"),
+            ],
+            vec![redirect("//cdn.example.com/cat.png"), ok(PNG)],
+        );
+        let cache = cache(upstream.clone(), PathBuf::new());
+
+        let data = cache.fetch("http://example.com/cat.png").await.unwrap();
+
+        assert_eq!(data, PNG);
+        assert_eq!(
+            upstream.requested(),
+            [
+                "http://example.com/cat.png",
+                "http://cdn.example.com/cat.png"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn blocks_redirects_to_private_addresses() {
+        for location in [
+            "http://127.0.0.1/admin",
+            "http://[::ffff:169.254.169.254]/latest/meta-data",
+            "http://internal.example.com/",
+            "http://mixed.example.com/",
+        ] {
+            let upstream = StubUpstream::new(
+                vec![
+                    ("example.com", "93.184.216.34"),
+                    ("internal.example.com", "10.0.0.1"),
+                    ("mixed.example.com", "1.1.1.1"),
+                    ("mixed.example.com", "192.168.1.1"),
+                ],
+                vec![redirect(location), ok(PNG)],
+            );
+            let cache = cache(upstream.clone(), PathBuf::new());
+
+            let result = cache.fetch("http://example.com/cat.png").await;
+
+            assert!(
+                matches!(result, Err(Error::ProxyBlocked(_))),
+                "{location} should be blocked"
+            );
+            assert_eq!(upstream.requested(), ["http://example.com/cat.png"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_too_many_redirects() {
+        let upstream = StubUpstream::new(
+            vec![("example.com", "93.184.216.34")],
+            vec![redirect("/cat.png"); MAX_REDIRECTS + 1],
+        );
+        let cache = cache(upstream.clone(), PathBuf::new());
+
+        let result = cache.fetch("http://example.com/cat.png").await;
+
+        assert!(matches!(result, Err(Error::ProxyFailed(_))));
+        assert_eq!(upstream.requested().len(), MAX_REDIRECTS + 1);
+    }
+
+    #[tokio::test]
+    async fn refetches_outdated_content() {
+        let dir = std::env::temp_dir().join(format!("xdd-proxy-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let db = crate::test_database().await;
+
+        let newer = [PNG, b"newer"].concat();
+        let upstream = StubUpstream::new(
+            vec![("example.com", "93.184.216.34")],
+            vec![
+                ok(PNG),
+                ok(&newer),
+                UpstreamResponse {
+                    status: 500,
+                    location: None,
+                    body: Vec::new(),
+                },
+            ],
+        );
+        let cache = cache(upstream.clone(), dir.clone());
+        let url = "http://example.com/cat.png";
+
+        let age = |days: u64| {
+            db.call(move |conn| {
+                conn.execute(
+                    "UPDATE proxy_cache SET fetched_at = fetched_at - ?",
+                    params![days * 24 * 60 * 60],
+                )
+                .unwrap();
+            })
+        };
+
+        assert_eq!(cache.get(&db, url).await.unwrap().data, PNG);
+        assert_eq!(cache.get(&db, url).await.unwrap().data, PNG);
+        assert_eq!(upstream.requested().len(), 1);
+
+        age(2).await;
+        assert_eq!(cache.get(&db, url).await.unwrap().data, newer);
+        assert_eq!(upstream.requested().len(), 2);
+
+        // Outdated content is still served while the host is failing.
+        age(2).await;
+        assert_eq!(cache.get(&db, url).await.unwrap().data, newer);
+        assert_eq!(upstream.requested().len(), 3);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::expand;
+use crate::policy::{self, AliasAction};
+use crate::suggest;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// The maximum number of days the old name of a renamed alias can keep redirecting.
+pub const MAX_REDIRECT_DAYS: u32 = 365;
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct RenameAlias {
+    /// The new name of the alias.
+    #[schema(example = "funnier.png")]
+    pub name: String,
+
+    /// For how many days the old name should keep resolving to the renamed alias so existing
+    /// references keep working. No redirect is left if this is missing.
+    #[schema(example = 30, maximum = 365)]
+    pub redirect_days: Option<u32>,
+}
+
+/// Rename an alias, keeping its author, creation time and history.
+/// # Note
+/// Requires `edit-aliases` permission unless the alias was created by the user. The old name can
+/// optionally be left as a redirect to the new name for a grace period, the new name can not be
+/// taken by another alias.
+#[utoipa::path(
+    post,
+    path = "/api/alias/{name}/rename",
+    request_body = RenameAlias,
+    responses(
+        (status = 200, description = "The alias was successfully renamed."),
+        (status = 400, description = "One of the values sent in is invalid or the new name is already taken."),
+        (status = 404, description = "Alias with the specified name does not exist."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Current name of the alias to rename."),
+    )
+)]
+pub async fn post_rename_alias(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<RenameAlias>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            let new_name = request.name;
+            if new_name.is_empty() {
+                return Err(Error::EmptyField("name"));
+            }
+            if let Some(days) = request.redirect_days {
+                if days == 0 || days > MAX_REDIRECT_DAYS {
+                    return Err(Error::OutOfRange {
+                        field: "redirectDays",
+                        minimum: 1,
+                        maximum: MAX_REDIRECT_DAYS as u64,
+                    });
+                }
+            }
+
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            let names = vec![name.clone(), new_name.clone()];
+
+            let events = state.events.clone();
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    alias::check_name_available(&tx, &new_name)?;
+
+                    // Synonyms and redirects can't be used to rename an alias.
+                    let before = alias::query_by_name(&tx, &name)?
+                        .filter(|alias| alias.name == name)
+                        .ok_or(Error::NotFound)?;
+                    policy::authorize(
+                        &payload.name,
+                        &payload.groups,
+                        &before.author,
+                        AliasAction::Edit,
+                    )?;
+
+                    let renamed = tx
+                        .execute(
+                            "UPDATE aliases SET name = ? WHERE name = ? AND deleted_at IS NULL",
+                            params![&new_name, &name],
+                        )
+                        .context("Failed to rename alias")?;
+                    if renamed == 0 {
+                        return Err(Error::NotFound);
+                    }
+
+                    tx.execute(
+                        "UPDATE alias_revisions SET alias = ? WHERE alias = ?",
+                        params![&new_name, &name],
+                    )
+                    .context("Failed to move alias revisions")?;
+
+                    // The new name replaces any redirect using it and older redirects follow
+                    // the alias to its new name.
+                    tx.execute(
+                        "DELETE FROM alias_redirects WHERE name = ? OR expires_at <= ?",
+                        params![&new_name, now],
+                    )
+                    .context("Failed to remove old redirects")?;
+                    tx.execute(
+                        "UPDATE alias_redirects SET target = ? WHERE target = ?",
+                        params![&new_name, &name],
+                    )
+                    .context("Failed to update redirects")?;
+
+                    if let Some(days) = request.redirect_days {
+                        tx.execute(
+                            "INSERT INTO alias_redirects (name, target, created_by, created_at, expires_at)
+                            VALUES (?, ?, ?, ?, ?)",
+                            params![
+                                &name,
+                                &new_name,
+                                &payload.name,
+                                now,
+                                now + days as u64 * 24 * 60 * 60
+                            ],
+                        )
+                        .context("Failed to insert redirect")?;
+                    }
+
+                    // Other aliases might already reference the new name.
+                    let alias = alias::get_by_name(&tx, new_name.clone())?;
+                    if alias.typ.is_text() {
+                        expand::check_cycles(&tx, &new_name, &alias.content)?;
+                    }
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasRename,
+                        &name,
+                        audit::snapshot(&before),
+                        audit::snapshot(&alias),
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasUpdated, &alias, Some(&name))?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Updated, alias, Some(name));
+
+                    Ok::<_, Error>(())
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Returns the name of the alias `name` redirects to if the redirect hasn't expired yet.
+pub fn resolve(conn: &Connection, name: &str) -> Result<Option<String>, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+    let target = conn
+        .query_row(
+            "SELECT target FROM alias_redirects WHERE name = ? AND expires_at > ?",
+            params![name, now],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to query alias redirect")?;
+
+    Ok(target)
+}
+
+/// Removes the redirect using `name`, this needs to be called when an alias with that name is
+/// created.
+pub fn remove(conn: &Connection, name: &str) -> Result<(), Error> {
+    conn.execute("DELETE FROM alias_redirects WHERE name = ?", params![name])
+        .context("Failed to remove alias redirect")?;
+
+    Ok(())
+}
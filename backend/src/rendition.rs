@@ -0,0 +1,162 @@
+//! Smaller versions of hosted media so clients don't have to download large images to show them
+//! as thumbnails or emotes.
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::imageops::{self, FilterType};
+use image::{AnimationDecoder, Frame, ImageFormat, ImageOutputFormat, ImageResult};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Cursor;
+
+use crate::media::MediaFormat;
+use crate::probe::ImageInfo;
+
+/// Media with more pixels than this across all frames is always served in its original size,
+/// decoding it would take too much memory.
+pub const MAX_RENDER_PIXELS: u64 = 64 * 1024 * 1024;
+
+/// A size hosted media can be requested in. Renditions keep the aspect ratio of the original and
+/// animated media stays animated.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, TS, ToSchema,
+)]
+#[ts(export, export_to = "../frontend/src/types/")]
+pub enum Rendition {
+    /// Fits in 256x256 pixels, used for previews.
+    #[serde(rename = "thumbnail")]
+    Thumbnail,
+    /// Fits in 32x32 pixels, the size emotes are shown in.
+    #[serde(rename = "1x")]
+    X1,
+    /// Fits in 64x64 pixels.
+    #[serde(rename = "2x")]
+    X2,
+    /// Fits in 128x128 pixels.
+    #[serde(rename = "4x")]
+    X4,
+}
+
+impl Rendition {
+    pub const ALL: [Rendition; 4] = [
+        Rendition::Thumbnail,
+        Rendition::X1,
+        Rendition::X2,
+        Rendition::X4,
+    ];
+
+    /// The largest width and height of the rendition in pixels.
+    pub fn size(&self) -> u32 {
+        match self {
+            Rendition::Thumbnail => 256,
+            Rendition::X1 => 32,
+            Rendition::X2 => 64,
+            Rendition::X4 => 128,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rendition::Thumbnail => "thumbnail",
+            Rendition::X1 => "1x",
+            Rendition::X2 => "2x",
+            Rendition::X4 => "4x",
+        }
+    }
+
+    /// Whether the rendition of the image differs from the original, images are never scaled up.
+    pub fn is_smaller(&self, info: &ImageInfo) -> bool {
+        let pixels = info.width as u64 * info.height as u64 * info.frames as u64;
+        info.width.max(info.height) > self.size() && pixels <= MAX_RENDER_PIXELS
+    }
+
+    /// Renditions of animated images are GIFs, everything else is a PNG.
+    pub fn mime(&self, info: &ImageInfo) -> &'static str {
+        if info.is_animated() {
+            MediaFormat::Gif.mime()
+        } else {
+            MediaFormat::Png.mime()
+        }
+    }
+
+    /// The URLs of every rendition of the hosted media at `url`.
+    pub fn urls(url: &str) -> BTreeMap<Rendition, String> {
+        Rendition::ALL
+            .into_iter()
+            .map(|rendition| (rendition, format!("{url}?size={rendition}")))
+            .collect()
+    }
+}
+
+impl fmt::Display for Rendition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Scales the image down to fit the rendition, this is slow and should not be run on the async
+/// runtime.
+pub fn render(data: &[u8], info: &ImageInfo, rendition: Rendition) -> ImageResult<Vec<u8>> {
+    let size = rendition.size();
+
+    if info.is_animated() {
+        let (width, height) = fit(info.width, info.height, size);
+        let frames = decode_frames(data, info.format)?.into_iter().map(|frame| {
+            let delay = frame.delay();
+            let buffer = imageops::resize(frame.buffer(), width, height, FilterType::Triangle);
+            Frame::from_parts(buffer, 0, 0, delay)
+        });
+
+        let mut output = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut output);
+            encoder.set_repeat(Repeat::Infinite)?;
+            encoder.encode_frames(frames)?;
+        }
+        Ok(output)
+    } else {
+        let image = image::load_from_memory_with_format(data, image_format(info.format))?;
+
+        let mut output = Cursor::new(Vec::new());
+        image
+            .resize(size, size, FilterType::Lanczos3)
+            .write_to(&mut output, ImageOutputFormat::Png)?;
+        Ok(output.into_inner())
+    }
+}
+
+fn decode_frames(data: &[u8], format: MediaFormat) -> ImageResult<Vec<Frame>> {
+    let data = Cursor::new(data);
+
+    match format {
+        MediaFormat::Gif => GifDecoder::new(data)?.into_frames().collect_frames(),
+        MediaFormat::Png => PngDecoder::new(data)?.apng().into_frames().collect_frames(),
+        MediaFormat::Webp => WebPDecoder::new(data)?.into_frames().collect_frames(),
+        MediaFormat::Jpeg => {
+            let image = image::load_from_memory_with_format(data.into_inner(), ImageFormat::Jpeg)?;
+            Ok(vec![Frame::new(image.into_rgba8())])
+        }
+    }
+}
+
+pub(crate) fn image_format(format: MediaFormat) -> ImageFormat {
+    match format {
+        MediaFormat::Png => ImageFormat::Png,
+        MediaFormat::Jpeg => ImageFormat::Jpeg,
+        MediaFormat::Gif => ImageFormat::Gif,
+        MediaFormat::Webp => ImageFormat::WebP,
+    }
+}
+
+/// The dimensions of an image scaled down to fit in a `size` by `size` square.
+fn fit(width: u32, height: u32, size: u32) -> (u32, u32) {
+    let scale = size as f64 / width.max(height) as f64;
+    let scaled = |n: u32| ((n as f64 * scale).round() as u32).max(1);
+
+    (scaled(width), scaled(height))
+}
@@ -0,0 +1,664 @@
+//! Lets users flag offensive or broken aliases for moderators. Aliases reported by enough users
+//! are hidden until a moderator resolves or dismisses the reports.
+
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias::{self, Alias};
+use crate::alias_type::AliasType;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::{AliasEventKind, EventHub};
+use crate::moderation::{HasModerateAliases, Viewer};
+use crate::suggest;
+use crate::util::{check_length, non_empty_trimmed_str};
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// How many users need to report an alias before it is hidden if `REPORT_THRESHOLD` is not set.
+pub const DEFAULT_REPORT_THRESHOLD: u64 = 3;
+
+/// The maximum number of characters in the text of a report.
+pub const MAX_REPORT_LENGTH: u64 = 1000;
+
+/// Why an alias was reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum ReportCategory {
+    /// The content is offensive or otherwise inappropriate.
+    Offensive,
+    /// The content points at something which no longer works.
+    Broken,
+    /// The alias is spam or an advertisement.
+    Spam,
+    /// The alias is the same as another alias.
+    Duplicate,
+    Other,
+}
+
+impl ReportCategory {
+    fn as_str(self) -> &'static str {
+        match self {
+            ReportCategory::Offensive => "offensive",
+            ReportCategory::Broken => "broken",
+            ReportCategory::Spam => "spam",
+            ReportCategory::Duplicate => "duplicate",
+            ReportCategory::Other => "other",
+        }
+    }
+}
+
+/// A report of an alias.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostReport {
+    pub category: ReportCategory,
+
+    /// Details for the moderators.
+    /// # Note
+    /// The input is trimmed and can be at most 1000 characters long.
+    #[schema(example = "The image has been taken down")]
+    #[serde(default, deserialize_with = "non_empty_trimmed_str")]
+    pub text: Option<String>,
+}
+
+/// Report an alias to the moderators.
+/// # Note
+/// Every user can have one open report per alias. Once `REPORT_THRESHOLD` users (3 by default)
+/// have reported an alias it is hidden from everyone but its author and moderators until a
+/// moderator handles the reports.
+#[utoipa::path(
+    post,
+    path = "/api/alias/{name}/report",
+    request_body = PostReport,
+    responses(
+        (status = 200, description = "The alias was reported."),
+        (status = 400, description = "One of the values sent in is invalid or the user already reported the alias."),
+        (status = 404, description = "No alias with that name exists."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to report."),
+    ),
+)]
+pub async fn post_report(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PostReport>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+            check_length("text", request.text.as_deref(), MAX_REPORT_LENGTH)?;
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            let threshold = state.report_threshold;
+            let events = state.events.clone();
+            let hidden = state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let (alias, after, hidden) = report(
+                        &tx,
+                        name,
+                        &viewer,
+                        &payload.name,
+                        &request,
+                        threshold,
+                        Some(&request_id),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    if hidden {
+                        // Events are only sent to users who can see the alias, so everyone but
+                        // moderators and the author only learns that it was deleted.
+                        events.publish(AliasEventKind::Deleted, alias, None);
+                        events.publish(AliasEventKind::Updated, after.clone(), None);
+                    }
+
+                    Ok::<_, Error>(hidden.then_some(after.name))
+                })
+                .await?;
+
+            if let Some(name) = hidden {
+                state.webhooks_queued.notify_one();
+                suggest::update(&state, vec![name]).await?;
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Records the report of `reporter` on the alias `name` in the transaction `tx`, hiding the alias
+/// once `threshold` users reported it. Returns the alias before and after the report and whether
+/// it was hidden by it.
+fn report(
+    tx: &Connection,
+    name: String,
+    viewer: &Viewer,
+    reporter: &str,
+    request: &PostReport,
+    threshold: u64,
+    request_id: Option<&RequestId>,
+) -> Result<(Alias, Alias, bool), Error> {
+    let alias = alias::get_by_name(tx, name)?;
+    if !viewer.can_see(&alias) {
+        return Err(Error::NotFound);
+    }
+
+    let existing = tx
+        .query_row(
+            "SELECT id FROM alias_reports
+            WHERE alias = ? AND reporter = ? AND status = 'open'",
+            params![&alias.name, reporter],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()
+        .context("Failed to check for existing report")?;
+    if existing.is_some() {
+        return Err(Error::AlreadyReported(alias.name));
+    }
+
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    tx.execute(
+        "INSERT INTO alias_reports
+            (alias, reporter, category, text, created_at, status)
+        VALUES (?, ?, ?, ?, ?, 'open')",
+        params![
+            &alias.name,
+            reporter,
+            request.category.as_str(),
+            request.text,
+            now
+        ],
+    )
+    .context("Failed to insert report")?;
+
+    let reports: u64 = tx
+        .query_row(
+            "SELECT COUNT(*) FROM alias_reports WHERE alias = ? AND status = 'open'",
+            params![&alias.name],
+            |row| row.get(0),
+        )
+        .context("Failed to count reports")?;
+
+    let hidden = alias.hidden_at.is_none() && reports >= threshold;
+    if hidden {
+        tx.execute(
+            "UPDATE aliases SET hidden_at = ? WHERE name = ?",
+            params![now, &alias.name],
+        )
+        .context("Failed to hide alias")?;
+
+        // Subscribers only know about published aliases, it is created again for them if it is
+        // shown again.
+        webhook::enqueue(tx, WebhookEvent::AliasDeleted, &alias, None)?;
+    }
+    let after = alias::get_by_name(tx, alias.name.clone())?;
+
+    audit::record(
+        tx,
+        request_id,
+        reporter,
+        AuditAction::AliasReport,
+        &alias.name,
+        None,
+        Some(json!({
+            "category": request.category,
+            "text": request.text,
+            "hidden": hidden,
+        })),
+    )?;
+
+    Ok((alias, after, hidden))
+}
+
+/// A report of an alias which hasn't been handled by a moderator.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    #[schema(example = 12)]
+    pub id: i64,
+
+    /// The username of the account who reported the alias.
+    #[schema(example = "Bob")]
+    pub reporter: String,
+
+    pub category: ReportCategory,
+
+    /// Details for the moderators.
+    #[schema(example = "The image has been taken down")]
+    pub text: Option<String>,
+
+    /// A unix timestamp of when the alias was reported.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+}
+
+/// An alias along with its open reports.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct ReportedAlias {
+    /// The name of the alias.
+    #[schema(example = "funny.png")]
+    pub name: String,
+
+    /// The content of the alias.
+    #[schema(example = "https://example.com/funny.png")]
+    pub content: String,
+
+    /// A category describing the type of content in the alias.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The username of the account who created the alias.
+    #[schema(example = "Alice")]
+    pub author: String,
+
+    /// A unix timestamp of when the alias was hidden because of the reports.
+    #[schema(example = 1670802822)]
+    pub hidden_at: Option<u64>,
+
+    /// The open reports of the alias, oldest first.
+    pub reports: Vec<Report>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbReport {
+    id: i64,
+    alias: String,
+    reporter: String,
+    category: ReportCategory,
+    text: Option<String>,
+    created_at: u64,
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+    author: String,
+    hidden_at: Option<u64>,
+}
+
+/// Get the aliases with open reports, the most reported aliases first.
+/// # Note
+/// Requires `moderate-aliases` permission.
+#[utoipa::path(
+    get,
+    path = "/api/reports",
+    responses(
+        (status = 200, description = "The reported aliases are returned.", body = [ReportedAlias]),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+)]
+pub async fn get_reports(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move { state.db.call(move |conn| get_open(conn).map(Json)).await })
+        .await
+}
+
+pub fn get_open(conn: &Connection) -> Result<Vec<ReportedAlias>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                r.id,
+                r.alias,
+                r.reporter,
+                r.category,
+                r.text,
+                r.created_at,
+                a.content,
+                at.name as type,
+                a.author,
+                a.hidden_at
+            FROM alias_reports r
+            JOIN aliases a ON a.name = r.alias
+            JOIN alias_types at ON at.id = a.type
+            WHERE r.status = 'open' AND a.deleted_at IS NULL
+            ORDER BY r.alias, r.id",
+        )
+        .context("Failed to prepare statement for report query")?;
+
+    let reports = stmt
+        .query_map([], |row| Ok(from_row::<DbReport>(row).unwrap()))
+        .context("Failed to query reports")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect reports")?;
+
+    let mut aliases: Vec<ReportedAlias> = Vec::new();
+    for report in reports {
+        let same_alias = aliases
+            .last()
+            .filter(|alias| alias.name == report.alias)
+            .is_some();
+        if !same_alias {
+            aliases.push(ReportedAlias {
+                name: report.alias,
+                content: report.content,
+                typ: report.typ,
+                author: report.author,
+                hidden_at: report.hidden_at,
+                reports: Vec::new(),
+            });
+        }
+
+        aliases.last_mut().unwrap().reports.push(Report {
+            id: report.id,
+            reporter: report.reporter,
+            category: report.category,
+            text: report.text,
+            created_at: report.created_at,
+        });
+    }
+
+    aliases.sort_by_key(|alias| Reverse(alias.reports.len()));
+
+    Ok(aliases)
+}
+
+/// Mark the open reports of an alias as resolved, after the alias was fixed.
+/// # Note
+/// Requires `moderate-aliases` permission. The alias is shown again if it was hidden, delete it
+/// instead if it shouldn't be.
+#[utoipa::path(
+    post,
+    path = "/api/reports/{name}/resolve",
+    responses(
+        (status = 200, description = "The reports were resolved."),
+        (status = 404, description = "The alias has no open reports."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the reported alias."),
+    ),
+)]
+pub async fn post_resolve_reports(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let names = vec![name.clone()];
+            let events = state.events.clone();
+            let shown = state
+                .db
+                .call(move |conn| {
+                    close(
+                        conn,
+                        &events,
+                        &request_id,
+                        &name,
+                        &payload.name,
+                        AuditAction::ReportsResolve,
+                    )
+                })
+                .await?;
+
+            if shown.is_some() {
+                state.webhooks_queued.notify_one();
+            }
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Dismiss the open reports of an alias because there is nothing wrong with it.
+/// # Note
+/// Requires `moderate-aliases` permission. The alias is shown again if it was hidden.
+#[utoipa::path(
+    post,
+    path = "/api/reports/{name}/dismiss",
+    responses(
+        (status = 200, description = "The reports were dismissed."),
+        (status = 404, description = "The alias has no open reports."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the reported alias."),
+    ),
+)]
+pub async fn post_dismiss_reports(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasModerateAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let names = vec![name.clone()];
+            let events = state.events.clone();
+            let shown = state
+                .db
+                .call(move |conn| {
+                    close(
+                        conn,
+                        &events,
+                        &request_id,
+                        &name,
+                        &payload.name,
+                        AuditAction::ReportsDismiss,
+                    )
+                })
+                .await?;
+
+            if shown.is_some() {
+                state.webhooks_queued.notify_one();
+            }
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Closes the open reports of an alias as resolved or dismissed and shows the alias again,
+/// returning the alias if it was hidden.
+fn close(
+    conn: &mut Connection,
+    events: &EventHub,
+    request_id: &RequestId,
+    name: &str,
+    moderator: &str,
+    action: AuditAction,
+) -> Result<Option<Alias>, Error> {
+    let tx = conn.transaction().context("Failed to create transaction")?;
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let status = match action {
+        AuditAction::ReportsDismiss => "dismissed",
+        _ => "resolved",
+    };
+
+    let hidden_at: Option<u64> = tx
+        .query_row(
+            "SELECT hidden_at FROM aliases WHERE name = ?",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("Failed to query alias")?
+        .flatten();
+
+    let closed = tx
+        .execute(
+            "UPDATE alias_reports
+            SET status = ?, handled_by = ?, handled_at = ?
+            WHERE alias = ? AND status = 'open'",
+            params![status, moderator, now, name],
+        )
+        .context("Failed to close reports")?;
+    if closed == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.execute(
+        "UPDATE aliases SET hidden_at = NULL WHERE name = ?",
+        params![name],
+    )
+    .context("Failed to show alias")?;
+
+    // The alias might be in the trash, which subscribers learn about when it is restored.
+    let shown = match hidden_at {
+        Some(_) => alias::query_by_name(&tx, name)?.filter(|alias| alias.name == name),
+        None => None,
+    };
+    if let Some(alias) = &shown {
+        webhook::enqueue(&tx, WebhookEvent::AliasCreated, alias, None)?;
+    }
+
+    audit::record(
+        &tx,
+        Some(request_id),
+        moderator,
+        action,
+        name,
+        Some(json!({ "openReports": closed, "hiddenAt": hidden_at })),
+        Some(json!({ "status": status, "hiddenAt": null })),
+    )?;
+
+    tx.commit().context("Failed to commit transaction")?;
+    if let Some(alias) = &shown {
+        events.publish(AliasEventKind::Created, alias.clone(), None);
+    }
+
+    Ok(shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with the published aliases `fb` and `gif` by alice.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at)
+                VALUES ('alice', 0), ('bob', 0), ('carol', 0), ('dave', 0), ('mod', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, published_at)
+                VALUES ('fb', 'foobar', 1, 'alice', 0, 0), ('gif', 'lol', 1, 'alice', 0, 0);",
+            )
+            .unwrap();
+        })
+        .await;
+        db
+    }
+
+    fn report_as(conn: &Connection, reporter: &str, name: &str) -> Result<bool, Error> {
+        let request = PostReport {
+            category: ReportCategory::Broken,
+            text: None,
+        };
+        let viewer = Viewer::new(reporter, &[]);
+        let (_, _, hidden) = report(conn, name.to_owned(), &viewer, reporter, &request, 2, None)?;
+
+        Ok(hidden)
+    }
+
+    fn close_as_mod(conn: &mut Connection, name: &str) -> Result<Option<Alias>, Error> {
+        let request_id = RequestId("test".to_owned());
+        close(
+            conn,
+            &EventHub::new(),
+            &request_id,
+            name,
+            "mod",
+            AuditAction::ReportsDismiss,
+        )
+    }
+
+    #[tokio::test]
+    async fn hides_aliases_reported_by_enough_users() {
+        let db = setup().await;
+        db.call(|conn| {
+            assert!(!report_as(conn, "bob", "fb").unwrap());
+            assert!(matches!(
+                report_as(conn, "bob", "fb"),
+                Err(Error::AlreadyReported(_))
+            ));
+            assert!(report_as(conn, "carol", "fb").unwrap());
+
+            let alias = alias::get_by_name(conn, "fb".to_owned()).unwrap();
+            assert!(alias.hidden_at.is_some());
+            assert!(Viewer::new("alice", &[]).can_see(&alias));
+            assert!(!Viewer::new("dave", &[]).can_see(&alias));
+            assert!(matches!(
+                report_as(conn, "dave", "fb"),
+                Err(Error::NotFound)
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn lists_the_most_reported_aliases_first() {
+        let db = setup().await;
+        let open = db
+            .call(|conn| {
+                report_as(conn, "bob", "gif").unwrap();
+                report_as(conn, "bob", "fb").unwrap();
+                report_as(conn, "carol", "fb").unwrap();
+                get_open(conn).unwrap()
+            })
+            .await;
+
+        let reports = open
+            .iter()
+            .map(|alias| {
+                let reporters = alias.reports.iter().map(|report| report.reporter.as_str());
+                (alias.name.as_str(), reporters.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reports,
+            [("fb", vec!["bob", "carol"]), ("gif", vec!["bob"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn closing_reports_shows_aliases_again() {
+        let db = setup().await;
+        db.call(|conn| {
+            report_as(conn, "bob", "fb").unwrap();
+            report_as(conn, "carol", "fb").unwrap();
+
+            let shown = close_as_mod(conn, "fb").unwrap();
+            assert_eq!(shown.map(|alias| alias.hidden_at), Some(None));
+            assert!(get_open(conn).unwrap().is_empty());
+            assert!(matches!(close_as_mod(conn, "fb"), Err(Error::NotFound)));
+
+            // Users can report the alias again once their reports were handled.
+            assert!(!report_as(conn, "bob", "fb").unwrap());
+        })
+        .await;
+    }
+}
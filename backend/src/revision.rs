@@ -0,0 +1,519 @@
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias::{self, Alias};
+use crate::alias_type::{self, AliasType};
+use crate::audit::{self, AuditAction, RequestId};
+use crate::duplicate;
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::expand;
+use crate::media;
+use crate::moderation::{self, Viewer};
+use crate::policy::{self, AliasAction};
+use crate::suggest;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// A change to the content or type of an alias.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct AliasRevision {
+    /// The id of the revision, used to restore the alias to this revision.
+    #[schema(example = 12)]
+    pub id: i64,
+
+    /// The content of the alias after the change.
+    #[schema(example = "https://example.com/funny.png")]
+    pub content: String,
+
+    /// The type of the alias after the change.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The content of the alias before the change, missing for the revision which created the
+    /// alias.
+    #[schema(example = "https://example.com/old.png")]
+    pub previous_content: Option<String>,
+
+    /// The type of the alias before the change, missing for the revision which created the alias.
+    pub previous_type: Option<AliasType>,
+
+    /// The username of the account who made the change.
+    #[schema(example = "Alice")]
+    pub editor: String,
+
+    /// A unix timestamp of when the change was made.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbAliasRevision {
+    id: i64,
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+    previous_content: Option<String>,
+    previous_type: Option<AliasType>,
+    editor: String,
+    created_at: u64,
+}
+
+impl From<DbAliasRevision> for AliasRevision {
+    fn from(revision: DbAliasRevision) -> Self {
+        Self {
+            id: revision.id,
+            content: revision.content,
+            typ: revision.typ,
+            previous_content: revision.previous_content,
+            previous_type: revision.previous_type,
+            editor: revision.editor,
+            created_at: revision.created_at,
+        }
+    }
+}
+
+/// The content and type id of an alias as they are stored in the database.
+#[derive(Deserialize, Debug, PartialEq)]
+pub(crate) struct StoredValues {
+    content: String,
+    #[serde(rename = "type")]
+    typ: i64,
+}
+
+pub(crate) fn stored_values(conn: &Connection, name: &str) -> Result<StoredValues, Error> {
+    let values = conn
+        .query_row(
+            "SELECT content, type FROM aliases WHERE name = ? AND deleted_at IS NULL",
+            params![name],
+            |row| Ok(from_row::<StoredValues>(row).unwrap()),
+        )
+        .optional()
+        .context("Failed to query alias values")?
+        .ok_or(Error::NotFound)?;
+
+    Ok(values)
+}
+
+/// Records the current values of an alias as a new revision if they differ from `previous`.
+pub(crate) fn record(
+    conn: &Connection,
+    name: &str,
+    previous: Option<StoredValues>,
+    editor: &str,
+) -> Result<(), Error> {
+    let current = stored_values(conn, name)?;
+    if previous.as_ref() == Some(&current) {
+        return Ok(());
+    }
+
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let (previous_content, previous_type) = previous.map(|p| (p.content, p.typ)).unzip();
+
+    conn.execute(
+        "INSERT INTO alias_revisions
+            (alias, content, type, previous_content, previous_type, editor, created_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![
+            name,
+            current.content,
+            current.typ,
+            previous_content,
+            previous_type,
+            editor,
+            now
+        ],
+    )
+    .context("Failed to insert alias revision")?;
+
+    Ok(())
+}
+
+/// Get the revision history of an alias, newest first.
+/// # Note
+/// The history of aliases waiting for moderation or hidden after being reported is only found for
+/// their author and moderators.
+#[utoipa::path(
+    get,
+    path = "/api/alias/{name}/history",
+    responses(
+        (status = 200, description = "The revisions of the alias are returned.", body = [AliasRevision]),
+        (status = 404, description = "No alias with that name exists."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to get the history of."),
+    ),
+)]
+pub async fn get_alias_history(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+            state
+                .db
+                .call(move |conn| get_visible_history(conn, &name, &viewer).map(Json))
+                .await
+        })
+        .await
+}
+
+/// Returns the history of the alias `name` or one of its synonyms if `viewer` can see it.
+pub(crate) fn get_visible_history(
+    conn: &Connection,
+    name: &str,
+    viewer: &Viewer,
+) -> Result<Vec<AliasRevision>, Error> {
+    let alias = alias::query_by_name(conn, name)?
+        .filter(|alias| viewer.can_see(alias))
+        .ok_or(Error::NotFound)?;
+
+    // Revisions are recorded under the name of the alias rather than the synonym.
+    get_history(conn, &alias.name)
+}
+
+pub fn get_history(conn: &Connection, name: &str) -> Result<Vec<AliasRevision>, Error> {
+    stored_values(conn, name)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                r.id,
+                r.content,
+                at.name as type,
+                r.previous_content,
+                pat.name as previous_type,
+                r.editor,
+                r.created_at
+            FROM alias_revisions r
+            JOIN alias_types at ON at.id = r.type
+            LEFT JOIN alias_types pat ON pat.id = r.previous_type
+            WHERE r.alias = ?
+            ORDER BY r.id DESC",
+        )
+        .context("Failed to prepare statement for alias revision query")?;
+
+    let revisions = stmt
+        .query_map(params![name], |row| {
+            Ok(AliasRevision::from(
+                from_row::<DbAliasRevision>(row).unwrap(),
+            ))
+        })
+        .context("Failed to query alias revisions")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect alias revisions")?;
+
+    Ok(revisions)
+}
+
+#[derive(Deserialize, Debug)]
+struct DbRestoredValues {
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+}
+
+/// Restore the content and type of an alias to what they were after a revision.
+/// # Note
+/// Requires `edit-aliases` permission unless the alias was created by the user. The restore is
+/// recorded as a new revision. Revisions with a type which has been retired since can't be
+/// restored. When aliases are moderated, content restored by users who aren't moderators is
+/// pending until it is approved again.
+#[utoipa::path(
+    post,
+    path = "/api/alias/{name}/restore/{revision}",
+    responses(
+        (status = 200, description = "The alias was successfully restored."),
+        (status = 400, description = "The restored content is no longer valid or another alias already uses its media."),
+        (status = 404, description = "The alias or revision does not exist."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to restore."),
+        ("revision" = i64, Path, description = "Id of the revision to restore."),
+    ),
+)]
+pub async fn post_restore_revision(
+    Path((name, revision)): Path<(String, i64)>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let moderated = state.moderate_new_aliases
+                && !Viewer::new(&payload.name, &payload.groups).is_moderator();
+            let store = state.clone();
+            let events = state.events.clone();
+            let name = state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let restore = Restore {
+                        revision,
+                        user: &payload.name,
+                        groups: &payload.groups,
+                        moderated,
+                        request_id: Some(&request_id),
+                    };
+                    let (before, after, resubmitted) =
+                        restore.apply(&tx, name, |content| media::hash_of(&store, content))?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    if resubmitted {
+                        events.publish(AliasEventKind::Deleted, before, None);
+                    }
+                    let name = after.name.clone();
+                    events.publish(AliasEventKind::Updated, after, None);
+
+                    Ok::<_, Error>(name)
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, vec![name]).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// A restore of an alias to one of its revisions, see [`post_restore_revision`].
+pub(crate) struct Restore<'a> {
+    pub revision: i64,
+    pub user: &'a str,
+    pub groups: &'a [String],
+    /// Whether changed content has to be approved by a moderator again.
+    pub moderated: bool,
+    pub request_id: Option<&'a RequestId>,
+}
+
+impl Restore<'_> {
+    /// Restores the alias `name` in the transaction `tx`, `hash_of` returns the hosted media
+    /// content points at. Returns the alias before and after the restore and whether it was
+    /// submitted for moderation.
+    pub(crate) fn apply(
+        &self,
+        tx: &Connection,
+        name: String,
+        hash_of: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Alias, Alias, bool), Error> {
+        let before = alias::get_by_name(tx, name)?;
+        policy::authorize(self.user, self.groups, &before.author, AliasAction::Edit)?;
+        // The alias might have been found through a synonym or the redirect of an old name.
+        let name = before.name.clone();
+
+        let restored = tx
+            .query_row(
+                "SELECT
+                    r.content,
+                    at.name as type
+                FROM alias_revisions r
+                JOIN alias_types at ON at.id = r.type
+                WHERE r.id = ? AND r.alias = ?",
+                params![self.revision, &name],
+                |row| Ok(from_row::<DbRestoredValues>(row).unwrap()),
+            )
+            .optional()
+            .context("Failed to query alias revision")?
+            .ok_or(Error::NotFound)?;
+
+        let previous = stored_values(tx, &name)?;
+
+        // Revisions from before placeholders existed are restored as they are, their content is
+        // expanded literally if it isn't a valid template.
+        if restored.typ.is_text() {
+            expand::check_cycles(tx, &name, &restored.content)?;
+        }
+
+        let media = hash_of(&restored.content);
+        let viewer = Viewer::new(self.user, self.groups);
+        duplicate::check_identical(tx, &name, media.as_deref(), &viewer)?;
+
+        // Hosted media keeps the type detected from its header. Aliases can keep a type which has
+        // been retired since, but can't go back to one.
+        let typ = media::enforced_type(tx, media.as_deref(), restored.typ)?;
+        let type_id = if typ == before.typ {
+            None
+        } else {
+            Some(alias_type::usable_id(tx, &typ)?)
+        };
+        let resubmit = self.moderated && restored.content != before.content;
+
+        tx.execute(
+            "UPDATE aliases
+            SET content = ?, type = COALESCE(?, type), media = (SELECT hash FROM media WHERE hash = ?)
+            WHERE name = ?",
+            params![restored.content, type_id, media, &name],
+        )
+        .context("Failed to restore alias")?;
+
+        record(tx, &name, Some(previous), self.user)?;
+        if resubmit {
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            moderation::submit(tx, &name, now)?;
+            // Subscribers only know about published aliases, it is created again for them once it
+            // is approved.
+            webhook::enqueue(tx, WebhookEvent::AliasDeleted, &before, None)?;
+        }
+
+        let after = alias::get_by_name(tx, name.clone())?;
+        audit::record(
+            tx,
+            self.request_id,
+            self.user,
+            AuditAction::AliasRevert,
+            &name,
+            audit::snapshot(&before),
+            audit::snapshot(&after),
+        )?;
+        webhook::enqueue(tx, WebhookEvent::AliasUpdated, &after, None)?;
+
+        Ok((before, after, resubmit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::moderation::ModerationStatus;
+
+    /// A database with the alias `fb` by alice which was changed from `first` to `second`,
+    /// returning the id of the first revision.
+    async fn setup() -> (tokio_rusqlite::Connection, i64) {
+        let db = crate::test_database().await;
+        let first = db
+            .call(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO users (username, created_at) VALUES ('alice', 0), ('bob', 0);
+                    INSERT INTO aliases (name, content, type, author, created_at)
+                    VALUES ('fb', 'first', 1, 'alice', 0);",
+                )
+                .unwrap();
+                record(conn, "fb", None, "alice").unwrap();
+                let first = conn.last_insert_rowid();
+
+                let previous = stored_values(conn, "fb").unwrap();
+                conn.execute(
+                    "UPDATE aliases SET content = 'second' WHERE name = 'fb'",
+                    [],
+                )
+                .unwrap();
+                record(conn, "fb", Some(previous), "alice").unwrap();
+
+                first
+            })
+            .await;
+
+        (db, first)
+    }
+
+    fn restore(
+        conn: &mut Connection,
+        revision: i64,
+        user: &str,
+        groups: &[String],
+        moderated: bool,
+    ) -> Result<(Alias, Alias, bool), Error> {
+        let tx = conn.transaction().unwrap();
+        let restore = Restore {
+            revision,
+            user,
+            groups,
+            moderated,
+            request_id: None,
+        };
+        let restored = restore.apply(&tx, "fb".to_owned(), |_| None)?;
+        tx.commit().unwrap();
+
+        Ok(restored)
+    }
+
+    #[tokio::test]
+    async fn restores_by_non_moderators_are_pending() {
+        let (db, first) = setup().await;
+
+        let (before, after, resubmitted) = db
+            .call(move |conn| restore(conn, first, "alice", &[], true))
+            .await
+            .unwrap();
+
+        assert_eq!(before.status, ModerationStatus::Approved);
+        assert_eq!(after.content, "first");
+        assert_eq!(after.status, ModerationStatus::Pending);
+        assert!(resubmitted);
+    }
+
+    #[tokio::test]
+    async fn restores_by_moderators_stay_published() {
+        let (db, first) = setup().await;
+
+        let (_, after, resubmitted) = db
+            .call(move |conn| restore(conn, first, "alice", &[], false))
+            .await
+            .unwrap();
+
+        assert_eq!(after.content, "first");
+        assert_eq!(after.status, ModerationStatus::Approved);
+        assert!(!resubmitted);
+    }
+
+    #[tokio::test]
+    async fn other_users_need_permission_to_restore() {
+        let (db, first) = setup().await;
+
+        let result = db
+            .call(move |conn| restore(conn, first, "bob", &[], false))
+            .await;
+        assert!(matches!(result, Err(Error::Forbidden { .. })), "{result:?}");
+
+        let groups = ["edit-aliases".to_owned()];
+        let (_, after, _) = db
+            .call(move |conn| restore(conn, first, "bob", &groups, false))
+            .await
+            .unwrap();
+        assert_eq!(after.content, "first");
+    }
+
+    #[tokio::test]
+    async fn finds_the_history_through_synonyms() {
+        let (db, _) = setup().await;
+
+        let history = db
+            .call(|conn| {
+                conn.execute(
+                    "INSERT INTO alias_synonyms (name, alias, created_by, created_at)
+                    VALUES ('foobar', 'fb', 'alice', 0)",
+                    [],
+                )
+                .unwrap();
+                get_visible_history(conn, "foobar", &Viewer::new("bob", &[])).unwrap()
+            })
+            .await;
+
+        let contents = history
+            .iter()
+            .map(|revision| revision.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["second", "first"]);
+    }
+}
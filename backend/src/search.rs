@@ -0,0 +1,319 @@
+use axum::extract::rejection::QueryRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Query, Extension, Json};
+use itertools::Itertools;
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use serde_rusqlite::from_row;
+use utoipa::IntoParams;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::alias::{Alias, DbAlias, ALIAS_COLUMNS};
+use crate::error::Error;
+use crate::moderation::Viewer;
+use crate::AppState;
+
+/// The maximum number of results that can be requested from a search.
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+/// How many rows are pulled out of the full text index before ranking them.
+const CANDIDATE_LIMIT: usize = 500;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchQuery {
+    /// The text to search for in alias names, synonyms and content.
+    #[param(example = "pepe")]
+    pub q: String,
+
+    /// The maximum amount of results to return, defaults to 20.
+    #[param(example = 20, maximum = 100)]
+    pub limit: Option<usize>,
+}
+
+/// Search aliases by name, synonyms and content.
+/// # Note
+/// Results are ranked with exact name matches first, followed by name prefix matches, name
+/// substring matches and content matches, synonyms are matched like names. Names which are only a
+/// few typos away from the query are also included after those.
+#[utoipa::path(
+    get,
+    path = "/api/alias/search",
+    responses(
+        (status = 200, description = "The matching aliases ordered by relevance.", body = [Alias]),
+        (status = 400, description = "One of the query parameters is invalid."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(SearchQuery),
+)]
+pub async fn search_aliases(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            let limit = query.limit.unwrap_or(20);
+            if limit == 0 || limit > MAX_SEARCH_LIMIT {
+                return Err(Error::InvalidQuery(format!(
+                    "limit must be between 1 and {MAX_SEARCH_LIMIT}"
+                )));
+            }
+
+            let q = query.q.trim().to_lowercase();
+            if q.is_empty() {
+                return Err(Error::EmptyField("q"));
+            }
+
+            let viewer = Viewer::new(&payload.name, &payload.groups);
+
+            state
+                .db
+                .call(move |conn| search(conn, &q, limit, &viewer).map(Json))
+                .await
+        })
+        .await
+}
+
+/// How well an alias matched the search query, lower is better.
+#[derive(Debug, PartialEq, PartialOrd)]
+enum Relevance {
+    ExactName,
+    NamePrefix,
+    NameSubstring,
+    Content,
+    Fuzzy(usize),
+}
+
+/// Searches for aliases `viewer` can see where `q` is a lowercase search query.
+pub(crate) fn search(
+    conn: &Connection,
+    q: &str,
+    limit: usize,
+    viewer: &Viewer,
+) -> Result<Vec<Alias>, Error> {
+    let mut candidates = HashMap::new();
+
+    if q.chars().count() < 3 {
+        // The trigram index can't be used for queries shorter than a single trigram, short
+        // queries are only matched against the start of names.
+        let pattern = format!("{}%", escape_like(q));
+        query_candidates(
+            conn,
+            &format!(
+                "SELECT {ALIAS_COLUMNS}, 0.0 as rank
+                FROM aliases a
+                WHERE (a.name LIKE ?1 ESCAPE '\\' OR EXISTS (SELECT 1
+                        FROM alias_synonyms s
+                        WHERE s.alias = a.name AND s.name LIKE ?1 ESCAPE '\\'
+                    ))
+                    AND a.deleted_at IS NULL
+                ORDER BY a.uses DESC
+                LIMIT ?2"
+            ),
+            &pattern,
+            &mut candidates,
+        )?;
+    } else {
+        let trigrams = trigrams(q).map(|t| fts_phrase(&t)).join(" OR ");
+        for query in [fts_query(), synonym_fts_query()] {
+            query_candidates(conn, &query, &fts_phrase(q), &mut candidates)?;
+            query_candidates(conn, &query, &trigrams, &mut candidates)?;
+        }
+    }
+
+    let mut results = candidates
+        .into_values()
+        .filter(|(alias, _)| viewer.can_see(alias))
+        .filter_map(|(alias, rank)| relevance(q, &alias).map(|relevance| (relevance, rank, alias)))
+        .collect::<Vec<_>>();
+
+    results.sort_by(|(relevance_a, rank_a, a), (relevance_b, rank_b, b)| {
+        relevance_a
+            .partial_cmp(relevance_b)
+            .unwrap_or(Ordering::Equal)
+            .then(rank_a.total_cmp(rank_b))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    Ok(results
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, alias)| alias)
+        .collect())
+}
+
+fn fts_query() -> String {
+    format!(
+        "SELECT {ALIAS_COLUMNS}, bm25(aliases_fts, 10.0, 1.0) as rank
+        FROM aliases_fts
+        JOIN aliases a ON a.id = aliases_fts.rowid
+        WHERE aliases_fts MATCH ? AND a.deleted_at IS NULL
+        ORDER BY rank
+        LIMIT ?"
+    )
+}
+
+/// Finds the aliases with matching synonyms, ranked like matches in the names of aliases.
+fn synonym_fts_query() -> String {
+    format!(
+        "SELECT {ALIAS_COLUMNS}, bm25(alias_synonyms_fts) * 10.0 as rank
+        FROM alias_synonyms_fts
+        JOIN alias_synonyms s ON s.id = alias_synonyms_fts.rowid
+        JOIN aliases a ON a.name = s.alias
+        WHERE alias_synonyms_fts MATCH ? AND a.deleted_at IS NULL
+        ORDER BY rank
+        LIMIT ?"
+    )
+}
+
+fn query_candidates(
+    conn: &Connection,
+    query: &str,
+    pattern: &str,
+    candidates: &mut HashMap<String, (Alias, f64)>,
+) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare(query)
+        .context("Failed to prepare statement for alias search")?;
+
+    let results = stmt
+        .query_map(params![pattern, CANDIDATE_LIMIT], |row| {
+            let alias = Alias::from(from_row::<DbAlias>(row).unwrap());
+            Ok((alias, row.get("rank")?))
+        })
+        .context("Failed to search aliases")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect search results")?;
+
+    for (alias, rank) in results {
+        candidates
+            .entry(alias.name.clone())
+            .or_insert((alias, rank));
+    }
+
+    Ok(())
+}
+
+fn relevance(q: &str, alias: &Alias) -> Option<Relevance> {
+    // Synonyms match the same way as the name of the alias.
+    let names = std::iter::once(&alias.name)
+        .chain(&alias.synonyms)
+        .map(|name| name.to_lowercase())
+        .collect::<Vec<_>>();
+
+    if names.iter().any(|name| name == q) {
+        Some(Relevance::ExactName)
+    } else if names.iter().any(|name| name.starts_with(q)) {
+        Some(Relevance::NamePrefix)
+    } else if names.iter().any(|name| name.contains(q)) {
+        Some(Relevance::NameSubstring)
+    } else if alias.content.to_lowercase().contains(q) {
+        Some(Relevance::Content)
+    } else {
+        // Compare against the start of the names as well so partially typed names with a typo
+        // are still found.
+        let length = q.chars().count();
+        let distance = names
+            .iter()
+            .map(|name| {
+                let prefix = name.chars().take(length).collect::<String>();
+                levenshtein(q, name).min(levenshtein(q, &prefix))
+            })
+            .min()?;
+
+        (distance <= max_typos(length)).then_some(Relevance::Fuzzy(distance))
+    }
+}
+
+/// The amount of typos allowed for a query of the given length.
+fn max_typos(length: usize) -> usize {
+    match length {
+        0..=2 => 0,
+        3..=5 => 1,
+        _ => 2,
+    }
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn trigrams(q: &str) -> impl Iterator<Item = String> + '_ {
+    let chars = q.chars().collect::<Vec<_>>();
+    (0..chars.len().saturating_sub(2))
+        .map(move |i| chars[i..i + 3].iter().collect::<String>())
+        .unique()
+}
+
+/// Quotes a string so it is matched literally by FTS5.
+fn fts_phrase(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn finds_aliases_after_vacuum() {
+        let db = crate::test_database().await;
+        let results = db
+            .call(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO users (username, created_at) VALUES ('alice', 0);
+                    INSERT INTO aliases (name, content, type, author, created_at, published_at)
+                    VALUES ('first', 'one', 1, 'alice', 0, 0),
+                        ('second', 'two', 1, 'alice', 0, 0),
+                        ('third', 'three', 1, 'alice', 0, 0);
+                    INSERT INTO alias_synonyms (name, alias, created_by, created_at)
+                    VALUES ('gone', 'first', 'alice', 0), ('synonym', 'third', 'alice', 0);
+                    DELETE FROM alias_synonyms WHERE name = 'gone';
+                    DELETE FROM aliases WHERE name = 'first';
+                    VACUUM;",
+                )
+                .unwrap();
+
+                let viewer = Viewer::new("alice", &[]);
+                ["hir", "econ", "synonym"].map(|q| {
+                    search(conn, q, 10, &viewer)
+                        .unwrap()
+                        .into_iter()
+                        .map(|alias| alias.name)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .await;
+
+        assert_eq!(results, [["third"], ["second"], ["third"]]);
+    }
+}
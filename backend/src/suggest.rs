@@ -0,0 +1,304 @@
+use axum::extract::rejection::QueryRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Query, Extension, Json};
+use rusqlite::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::alias_type::AliasType;
+use crate::error::Error;
+use crate::moderation::PUBLISHED;
+use crate::AppState;
+
+/// The maximum number of suggestions that can be requested at once.
+pub const MAX_SUGGEST_LIMIT: usize = 50;
+
+/// The maximum number of characters of the content included in a suggestion.
+const PREVIEW_LENGTH: usize = 100;
+
+/// A possible completion for an alias name that is being typed.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct Suggestion {
+    /// The name of the alias.
+    #[schema(example = "funny.png")]
+    pub name: String,
+
+    /// A category describing the type of content in the alias.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The start of the content of the alias.
+    #[schema(example = "https://example.com/funny.png")]
+    pub preview: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbSuggestion {
+    name: String,
+    /// The name of the alias, which differs from `name` for synonyms.
+    alias: String,
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+    uses: u64,
+}
+
+struct Entry {
+    key: String,
+    alias: String,
+    uses: u64,
+    suggestion: Suggestion,
+}
+
+/// An in-memory index of alias names sorted by their lowercase name so that all names starting
+/// with a prefix can be found with a binary search.
+#[derive(Default)]
+pub struct SuggestIndex {
+    entries: Vec<Entry>,
+}
+
+impl SuggestIndex {
+    pub fn load(conn: &Connection) -> Result<Self, Error> {
+        let mut entries = load_entries(conn, None)?;
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(Self { entries })
+    }
+
+    /// Replaces the entries of the aliases which changed with their current ones.
+    pub(crate) fn apply(&mut self, changes: Changes) {
+        let names = changes.names.iter().collect::<HashSet<_>>();
+
+        self.entries.retain(|e| !names.contains(&e.alias));
+        for entry in changes.entries {
+            let index = self.entries.partition_point(|e| e.key < entry.key);
+            self.entries.insert(index, entry);
+        }
+    }
+
+    /// Returns the most used aliases whose names start with `prefix`, ignoring case.
+    pub fn suggest(&self, prefix: &str, limit: usize) -> Vec<Suggestion> {
+        let prefix = prefix.to_lowercase();
+
+        let start = self
+            .entries
+            .partition_point(|e| e.key.as_str() < prefix.as_str());
+        let end = start + self.entries[start..].partition_point(|e| e.key.starts_with(&prefix));
+
+        let mut matches = self.entries[start..end].iter().collect::<Vec<_>>();
+        matches.sort_by_key(|e| (Reverse(e.uses), e.key.len()));
+
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|e| e.suggestion.clone())
+            .collect()
+    }
+}
+
+/// The current entries of some aliases, they are loaded before the index is locked so
+/// suggestions can still be served while the database is queried.
+pub(crate) struct Changes {
+    names: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+impl Changes {
+    pub(crate) fn load(conn: &Connection, names: Vec<String>) -> Result<Self, Error> {
+        let entries = load_entries(conn, Some(&names))?;
+
+        Ok(Self { names, entries })
+    }
+}
+
+/// Loads the entries of the aliases `names`, or of all aliases.
+fn load_entries(conn: &Connection, names: Option<&[String]>) -> Result<Vec<Entry>, Error> {
+    // The names are passed as a single JSON array so there can be more of them than the maximum
+    // number of parameters.
+    let (filter, params) = match names {
+        Some(names) => (
+            "AND a.name IN (SELECT value FROM json_each(?1))",
+            vec![serde_json::to_string(names).context("Failed to serialize alias names")?],
+        ),
+        None => ("", Vec::new()),
+    };
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT
+                a.name,
+                a.name as alias,
+                a.content,
+                at.name as type,
+                a.uses
+            FROM aliases a
+            JOIN alias_types at ON at.id = a.type
+            WHERE a.deleted_at IS NULL AND {PUBLISHED} {filter}
+            UNION ALL
+            SELECT
+                s.name,
+                a.name as alias,
+                a.content,
+                at.name as type,
+                a.uses
+            FROM alias_synonyms s
+            JOIN aliases a ON a.name = s.alias
+            JOIN alias_types at ON at.id = a.type
+            WHERE a.deleted_at IS NULL AND {PUBLISHED} {filter}"
+        ))
+        .context("Failed to prepare statement for suggestion query")?;
+
+    let entries = stmt
+        .query_map(params_from_iter(params), |row| {
+            let alias = from_row::<DbSuggestion>(row).unwrap();
+            Ok(Entry {
+                key: alias.name.to_lowercase(),
+                alias: alias.alias,
+                uses: alias.uses,
+                suggestion: Suggestion {
+                    preview: alias.content.chars().take(PREVIEW_LENGTH).collect(),
+                    name: alias.name,
+                    typ: alias.typ,
+                },
+            })
+        })
+        .context("Failed to query suggestions")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect suggestions")?;
+
+    Ok(entries)
+}
+
+/// Reloads the entries of the aliases `names` from the database, this needs to be called after
+/// the aliases are changed. Renamed aliases need both their old and new name.
+/// # Note
+/// The index is updated on the database thread, so updates are applied in the same order as the
+/// changes were made even if the requests making them finish in a different order.
+pub async fn update(state: &AppState, names: Vec<String>) -> Result<(), Error> {
+    let suggestions = state.suggestions.clone();
+    state
+        .db
+        .call(move |conn| {
+            let changes = Changes::load(conn, names)?;
+            suggestions.write().unwrap().apply(changes);
+            Ok(())
+        })
+        .await
+}
+
+/// Reloads the whole suggestion index from the database, this needs to be called after changes
+/// which affect many aliases.
+pub async fn rebuild(state: &AppState) -> Result<(), Error> {
+    let suggestions = state.suggestions.clone();
+    state
+        .db
+        .call(move |conn| {
+            let index = SuggestIndex::load(conn)?;
+            *suggestions.write().unwrap() = index;
+            Ok(())
+        })
+        .await
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct SuggestQuery {
+    /// The start of the alias name, optionally including the leading `!`.
+    #[param(example = "!fun")]
+    #[serde(default)]
+    pub prefix: String,
+
+    /// The maximum amount of suggestions to return, defaults to 10.
+    #[param(example = 10, maximum = 50)]
+    pub limit: Option<usize>,
+}
+
+/// Get suggestions for alias names starting with a prefix.
+/// # Note
+/// Suggestions are served from memory and ordered by how often the aliases are used.
+#[utoipa::path(
+    get,
+    path = "/api/alias/suggest",
+    responses(
+        (status = 200, description = "The suggested aliases are returned.", body = [Suggestion]),
+        (status = 400, description = "One of the query parameters is invalid."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(SuggestQuery),
+)]
+pub async fn get_suggestions(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<SuggestQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            let limit = query.limit.unwrap_or(10);
+            if limit == 0 || limit > MAX_SUGGEST_LIMIT {
+                return Err(Error::InvalidQuery(format!(
+                    "limit must be between 1 and {MAX_SUGGEST_LIMIT}"
+                )));
+            }
+
+            let prefix = query.prefix.trim();
+            let prefix = prefix.strip_prefix('!').unwrap_or(prefix);
+
+            let suggestions = state.suggestions.read().unwrap().suggest(prefix, limit);
+
+            Ok(Json(suggestions))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(suggestions: &[Suggestion]) -> Vec<&str> {
+        suggestions.iter().map(|s| s.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn updates_the_entries_of_changed_aliases() {
+        let db = crate::test_database().await;
+
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, uses)
+                VALUES ('fb', 'foobar', 1, 'alice', 0, 2), ('fbb', 'foobarbaz', 1, 'alice', 0, 1);",
+            )
+            .unwrap();
+            let mut index = SuggestIndex::load(conn).unwrap();
+            assert_eq!(names(&index.suggest("F", 10)), ["fb", "fbb"]);
+
+            conn.execute_batch(
+                "UPDATE aliases SET deleted_at = 1 WHERE name = 'fb';
+                INSERT INTO alias_synonyms (name, alias, created_by, created_at)
+                VALUES ('fbz', 'fbb', 'alice', 0);",
+            )
+            .unwrap();
+            // More names than SQLite allows parameters in a single statement.
+            let mut changed = (0..40_000).map(|i| format!("x{i}")).collect::<Vec<_>>();
+            changed.extend(["fb".to_owned(), "fbb".to_owned()]);
+            index.apply(Changes::load(conn, changed).unwrap());
+
+            assert_eq!(names(&index.suggest("fb", 10)), ["fbb", "fbz"]);
+        })
+        .await;
+    }
+}
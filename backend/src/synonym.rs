@@ -0,0 +1,125 @@
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use itertools::Itertools;
+use rusqlite::params;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::expand;
+use crate::policy::{self, AliasAction};
+use crate::rename;
+use crate::suggest;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// Replace the synonyms of an alias, these are additional names which resolve to the alias
+/// everywhere its name does.
+/// # Note
+/// Requires `edit-aliases` permission unless the alias was created by the user. Synonyms can not
+/// be used as the name or synonym of another alias.
+#[utoipa::path(
+    put,
+    path = "/api/alias/{name}/synonyms",
+    request_body(content = [String], example = json!(["lol.png", "haha.png"])),
+    responses(
+        (status = 200, description = "The synonyms were successfully replaced."),
+        (status = 400, description = "One of the synonyms is empty or already taken."),
+        (status = 404, description = "Alias with the specified name does not exist."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to set the synonyms of."),
+    )
+)]
+pub async fn put_synonyms(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<Vec<String>>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(synonyms) = request?;
+
+            if synonyms.iter().any(String::is_empty) {
+                return Err(Error::EmptyArrayElement("synonyms"));
+            }
+            let synonyms = synonyms.into_iter().unique().collect::<Vec<_>>();
+
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            let events = state.events.clone();
+            let name = state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let alias = alias::get_by_name(&tx, name)?;
+                    policy::authorize(
+                        &payload.name,
+                        &payload.groups,
+                        &alias.author,
+                        AliasAction::Edit,
+                    )?;
+
+                    tx.execute(
+                        "DELETE FROM alias_synonyms WHERE alias = ?",
+                        params![&alias.name],
+                    )
+                    .context("Failed to remove synonyms")?;
+
+                    for synonym in &synonyms {
+                        alias::check_name_available(&tx, synonym)?;
+
+                        tx.execute(
+                            "INSERT INTO alias_synonyms (name, alias, created_by, created_at)
+                            VALUES (?, ?, ?, ?)",
+                            params![synonym, &alias.name, &payload.name, now],
+                        )
+                        .context("Failed to insert synonym")?;
+
+                        rename::remove(&tx, synonym)?;
+                    }
+
+                    // Other aliases might already reference one of the new synonyms.
+                    if alias.typ.is_text() {
+                        expand::check_cycles(&tx, &alias.name, &alias.content)?;
+                    }
+
+                    let after = alias::get_by_name(&tx, alias.name.clone())?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasSynonyms,
+                        &alias.name,
+                        audit::snapshot(&alias),
+                        audit::snapshot(&after),
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasUpdated, &after, None)?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Updated, after, None);
+
+                    Ok::<_, Error>(alias.name)
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, vec![name]).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
@@ -0,0 +1,546 @@
+use axum::extract::rejection::JsonRejection;
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use itertools::Itertools;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_rusqlite::from_row;
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::alias;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::policy::{self, AliasAction};
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// A free-form label used to browse aliases, such as `reaction` or `cat`.
+#[derive(Debug, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    /// The lowercase name of the tag.
+    #[schema(example = "reaction")]
+    pub name: String,
+
+    /// How many aliases have the tag, aliases in the trash are not counted.
+    #[schema(example = 12)]
+    pub count: u64,
+}
+
+/// Returns the lowercase version of a tag name, failing if it isn't a valid name.
+pub fn normalize(name: &str) -> Result<String, Error> {
+    let name = name.trim().to_lowercase();
+
+    if name.chars().any(|c| c.is_whitespace() || c == ',') {
+        return Err(Error::InvalidTagName(name));
+    }
+
+    Ok(name)
+}
+
+/// Get a list of all tags along with how many aliases have them.
+#[utoipa::path(
+    get,
+    path = "/api/tags",
+    responses(
+        (status = 200, description = "All tags are returned.", body = [Tag]),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_tags(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move { state.db.call(move |conn| get_all(conn).map(Json)).await })
+        .await
+}
+
+pub fn get_all(conn: &Connection) -> Result<Vec<Tag>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                t.name,
+                COUNT(a.name) as count
+            FROM tags t
+            LEFT JOIN alias_tags x ON x.tag = t.id
+            LEFT JOIN aliases a ON a.name = x.alias AND a.deleted_at IS NULL
+            GROUP BY t.id
+            ORDER BY t.name",
+        )
+        .context("Failed to prepare statement for tag query")?;
+
+    let tags = stmt
+        .query_map(params![], |row| Ok(from_row::<Tag>(row).unwrap()))
+        .context("Failed to query tags")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect tags")?;
+
+    Ok(tags)
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostTag {
+    /// The name of the tag, it is stored lowercase and can not contain whitespace or commas.
+    #[schema(example = "reaction")]
+    pub name: String,
+}
+
+/// Create a tag.
+/// # Note
+/// Tags are also created when they are first added to an alias, which authors can do for their
+/// own aliases.
+#[utoipa::path(
+    post,
+    path = "/api/tags",
+    request_body = PostTag,
+    responses(
+        (status = 200, description = "The tag was successfully created."),
+        (status = 400, description = "The name is invalid or already taken."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn post_tag(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PostTag>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            let name = normalize(&request.name)?;
+            if name.is_empty() {
+                return Err(Error::EmptyField("name"));
+            }
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    if find(&tx, &name)?.is_some() {
+                        return Err(Error::TagExists);
+                    }
+
+                    insert(&tx, &name, &payload.name)?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TagCreate,
+                        &name,
+                        None,
+                        Some(json!({ "name": name })),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
+
+fn find(conn: &Connection, name: &str) -> Result<Option<i64>, Error> {
+    let id = conn
+        .query_row("SELECT id FROM tags WHERE name = ?", params![name], |row| {
+            row.get(0)
+        })
+        .optional()
+        .context("Failed to query tag")?;
+
+    Ok(id)
+}
+
+fn insert(conn: &Connection, name: &str, username: &str) -> Result<i64, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+    conn.execute(
+        "INSERT INTO tags (name, created_by, created_at) VALUES (?, ?, ?)",
+        params![name, username, now],
+    )
+    .context("Failed to insert tag")?;
+
+    Ok(conn.last_insert_rowid())
+}
+
+/// Replaces the tags of the alias `name` with `tags`, creating the tags which don't exist yet on
+/// behalf of `username`.
+fn replace_alias_tags(
+    conn: &Connection,
+    name: &str,
+    tags: &[String],
+    username: &str,
+) -> Result<(), Error> {
+    conn.execute("DELETE FROM alias_tags WHERE alias = ?", params![name])
+        .context("Failed to remove tags")?;
+
+    for tag in tags {
+        let id = match find(conn, tag)? {
+            Some(id) => id,
+            None => insert(conn, tag, username)?,
+        };
+
+        conn.execute(
+            "INSERT INTO alias_tags (alias, tag) VALUES (?, ?)",
+            params![name, id],
+        )
+        .context("Failed to tag alias")?;
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PutTag {
+    /// The new name of the tag.
+    #[schema(example = "reactions")]
+    pub name: String,
+}
+
+/// Rename a tag, aliases with the tag keep it under the new name.
+/// # Note
+/// Requires `edit-aliases` permission since the tag changes on the aliases of all users.
+#[utoipa::path(
+    put,
+    path = "/api/tags/{name}",
+    request_body = PutTag,
+    responses(
+        (status = 200, description = "The tag was successfully renamed."),
+        (status = 400, description = "The new name is invalid or already taken."),
+        (status = 404, description = "Tag with the specified name does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the tag to rename."),
+    )
+)]
+pub async fn put_tag(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PutTag>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+            policy::authorize_for_all(&payload.groups, AliasAction::Edit)?;
+
+            let new_name = normalize(&request.name)?;
+            if new_name.is_empty() {
+                return Err(Error::EmptyField("name"));
+            }
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let name = name.to_lowercase();
+                    let id = find(&tx, &name)?.ok_or(Error::NotFound)?;
+                    if matches!(find(&tx, &new_name)?, Some(other) if other != id) {
+                        return Err(Error::TagExists);
+                    }
+
+                    tx.execute(
+                        "UPDATE tags SET name = ? WHERE id = ?",
+                        params![&new_name, id],
+                    )
+                    .context("Failed to rename tag")?;
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TagUpdate,
+                        &name,
+                        Some(json!({ "name": name })),
+                        Some(json!({ "name": new_name })),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
+
+/// Delete a tag, removing it from all aliases.
+/// # Note
+/// Requires `delete-aliases` permission since the tag is removed from the aliases of all users.
+#[utoipa::path(
+    delete,
+    path = "/api/tags/{name}",
+    responses(
+        (status = 200, description = "The tag was successfully deleted."),
+        (status = 404, description = "Tag with the specified name does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the tag to delete."),
+    )
+)]
+pub async fn delete_tag(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            policy::authorize_for_all(&payload.groups, AliasAction::Delete)?;
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let name = name.to_lowercase();
+                    let deleted = tx
+                        .execute("DELETE FROM tags WHERE name = ?", params![&name])
+                        .context("Failed to delete tag")?;
+                    if deleted == 0 {
+                        return Err(Error::NotFound);
+                    }
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::TagDelete,
+                        &name,
+                        Some(json!({ "name": name })),
+                        None,
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
+
+/// Replace the tags of an alias, tags which don't exist yet are created.
+/// # Note
+/// Requires `edit-aliases` permission unless the alias was created by the user.
+#[utoipa::path(
+    put,
+    path = "/api/alias/{name}/tags",
+    request_body(content = [String], example = json!(["reaction", "cat"])),
+    responses(
+        (status = 200, description = "The tags were successfully replaced."),
+        (status = 400, description = "One of the tags is empty or invalid."),
+        (status = 404, description = "Alias with the specified name does not exist."),
+        (status = 403, description = "User is not the author of the alias and does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to set the tags of."),
+    )
+)]
+pub async fn put_alias_tags(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<idlib::NoGroups>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<Vec<String>>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(tags) = request?;
+
+            let tags = tags
+                .iter()
+                .map(|tag| normalize(tag))
+                .collect::<Result<Vec<_>, _>>()?;
+            if tags.iter().any(String::is_empty) {
+                return Err(Error::EmptyArrayElement("tags"));
+            }
+            let tags = tags.into_iter().unique().collect::<Vec<_>>();
+
+            let events = state.events.clone();
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let alias = alias::get_by_name(&tx, name)?;
+                    policy::authorize(
+                        &payload.name,
+                        &payload.groups,
+                        &alias.author,
+                        AliasAction::Edit,
+                    )?;
+
+                    replace_alias_tags(&tx, &alias.name, &tags, &payload.name)?;
+
+                    let after = alias::get_by_name(&tx, alias.name.clone())?;
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::AliasTags,
+                        &alias.name,
+                        audit::snapshot(&alias),
+                        audit::snapshot(&after),
+                    )?;
+                    webhook::enqueue(&tx, WebhookEvent::AliasUpdated, &after, None)?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Updated, after, None);
+
+                    Ok::<_, Error>(())
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::alias::{AliasQuery, TagMatch};
+    use crate::moderation::Viewer;
+
+    /// A database with the aliases `cat`, `dog` and the trashed alias `old` by alice.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            conn.execute_batch(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, deleted_at)
+                VALUES ('cat', 'meow', 1, 'alice', 0, NULL),
+                    ('dog', 'woof', 1, 'alice', 1, NULL),
+                    ('old', 'gone', 1, 'alice', 2, 0);",
+            )
+            .unwrap();
+        })
+        .await;
+        db
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn tagged(conn: &Connection, names: &[&str], tag_match: TagMatch) -> Vec<String> {
+        let query = AliasQuery {
+            tags: Some(tags(names)),
+            tag_match: Some(tag_match),
+            ..AliasQuery::default()
+        };
+
+        alias::get_all(conn, &query, &Viewer::new("alice", &[]))
+            .unwrap()
+            .0
+            .into_iter()
+            .map(|alias| alias.name)
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_names() {
+        assert_eq!(normalize(" Reaction ").unwrap(), "reaction");
+        assert!(matches!(
+            normalize("two words"),
+            Err(Error::InvalidTagName(_))
+        ));
+        assert!(matches!(normalize("a,b"), Err(Error::InvalidTagName(_))));
+    }
+
+    #[tokio::test]
+    async fn replaces_the_tags_of_aliases() {
+        let db = setup().await;
+        let alias = db
+            .call(|conn| {
+                replace_alias_tags(conn, "cat", &tags(&["animal", "cute"]), "alice").unwrap();
+                replace_alias_tags(conn, "cat", &tags(&["animal", "loud"]), "alice").unwrap();
+                alias::get_by_name(conn, "cat".to_owned()).unwrap()
+            })
+            .await;
+
+        assert_eq!(alias.tags, ["animal", "loud"]);
+    }
+
+    #[tokio::test]
+    async fn counts_tagged_aliases_outside_the_trash() {
+        let db = setup().await;
+        let all = db
+            .call(|conn| {
+                for name in ["cat", "dog", "old"] {
+                    replace_alias_tags(conn, name, &tags(&["animal"]), "alice").unwrap();
+                }
+                replace_alias_tags(conn, "old", &tags(&["animal", "gone"]), "alice").unwrap();
+                get_all(conn).unwrap()
+            })
+            .await;
+
+        let counts = all
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [("animal", 2), ("gone", 0)]);
+    }
+
+    #[tokio::test]
+    async fn browses_aliases_by_tags() {
+        let db = setup().await;
+        db.call(|conn| {
+            replace_alias_tags(conn, "cat", &tags(&["animal", "cute"]), "alice").unwrap();
+            replace_alias_tags(conn, "dog", &tags(&["animal", "loud"]), "alice").unwrap();
+
+            assert_eq!(tagged(conn, &["animal"], TagMatch::All), ["cat", "dog"]);
+            assert_eq!(tagged(conn, &["Animal", "cute"], TagMatch::All), ["cat"]);
+            assert_eq!(
+                tagged(conn, &["cute", "loud"], TagMatch::Any),
+                ["cat", "dog"]
+            );
+            assert!(tagged(conn, &["cute", "loud"], TagMatch::All).is_empty());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn keeps_tags_of_renamed_aliases() {
+        let db = setup().await;
+        let alias = db
+            .call(|conn| {
+                replace_alias_tags(conn, "cat", &tags(&["animal"]), "alice").unwrap();
+                conn.execute("UPDATE aliases SET name = 'kitten' WHERE name = 'cat'", [])
+                    .unwrap();
+                alias::get_by_name(conn, "kitten".to_owned()).unwrap()
+            })
+            .await;
+
+        assert_eq!(alias.tags, ["animal"]);
+    }
+}
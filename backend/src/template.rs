@@ -0,0 +1,299 @@
+//! Placeholders in the content of text aliases which are filled in with arguments when the alias
+//! is expanded.
+//!
+//! # Syntax
+//! ```markdown
+//! Content: "{0} has been slapped by {by:nobody}"
+//! Text: "!slap(alice, by=bob)" -> "alice has been slapped by bob"
+//! Text: "!slap(alice)" -> "alice has been slapped by nobody"
+//! ```
+//! Placeholders are either a position or a name, optionally followed by a `:` and a default value
+//! which is used when the argument is missing. Literal braces are written as `{{` and `}}`.
+
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Position(usize),
+    Name(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Placeholder { key: Key, default: Option<String> },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+/// The arguments passed to an alias, like `alice` and `by=bob` in `!slap(alice, by=bob)`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Arguments {
+    pub positional: Vec<String>,
+    pub named: HashMap<String, String>,
+}
+
+impl Template {
+    /// Parses the content of a text alias, failing with [`Error::InvalidTemplate`] if the
+    /// placeholders are malformed.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = content.chars().enumerate().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '{' if chars.peek().map(|(_, c)| *c) == Some('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek().map(|(_, c)| *c) == Some('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => {
+                    return Err(Error::InvalidTemplate(format!(
+                        "unexpected '}}' at character {i}, use '}}}}' for a literal '}}'"
+                    )));
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((j, '{')) => {
+                                return Err(Error::InvalidTemplate(format!(
+                                    "unexpected '{{' at character {j} inside the placeholder \
+                                    starting at character {i}"
+                                )));
+                            }
+                            Some((_, c)) => placeholder.push(c),
+                            None => {
+                                return Err(Error::InvalidTemplate(format!(
+                                    "the placeholder starting at character {i} is never closed"
+                                )));
+                            }
+                        }
+                    }
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(parse_placeholder(&placeholder, i)?);
+                }
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Fills in the placeholders, missing arguments without a default are left empty.
+    pub fn render(&self, arguments: &Arguments) -> String {
+        let mut output = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => output.push_str(text),
+                Segment::Placeholder { key, default } => {
+                    let value = match key {
+                        Key::Position(position) => arguments.positional.get(*position),
+                        Key::Name(name) => arguments.named.get(name),
+                    };
+
+                    if let Some(value) = value.or(default.as_ref()) {
+                        output.push_str(value);
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+fn parse_placeholder(placeholder: &str, start: usize) -> Result<Segment, Error> {
+    let (key, default) = match placeholder.split_once(':') {
+        Some((key, default)) => (key.trim(), Some(default.to_owned())),
+        None => (placeholder.trim(), None),
+    };
+
+    let key = if key.is_empty() {
+        return Err(Error::InvalidTemplate(format!(
+            "the placeholder at character {start} is missing a position or name"
+        )));
+    } else if let Ok(position) = key.parse() {
+        Key::Position(position)
+    } else if is_name(key) {
+        Key::Name(key.to_owned())
+    } else {
+        return Err(Error::InvalidTemplate(format!(
+            "'{key}' in the placeholder at character {start} is not a valid position or name, \
+            names can only contain letters, numbers and underscores"
+        )));
+    };
+
+    Ok(Segment::Placeholder { key, default })
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Parses an argument list like `(alice, by=bob)` at the start of `text`, returning the arguments
+/// and the length of the list in bytes. Returns `None` if `text` doesn't start with a complete
+/// argument list.
+///
+/// Arguments are separated by commas and surrounding whitespace is removed. A backslash makes the
+/// next character literal, so `\,`, `\)` and `\=` can be used inside arguments.
+pub fn parse_arguments(text: &str) -> Option<(Arguments, usize)> {
+    let mut chars = text.char_indices();
+    if chars.next()?.1 != '(' {
+        return None;
+    }
+
+    let mut arguments = Arguments::default();
+    let mut current = String::new();
+    let mut name = None;
+
+    let mut push = |current: &mut String, name: &mut Option<String>| {
+        let value = current.trim().to_owned();
+        current.clear();
+        match name.take() {
+            Some(name) => {
+                arguments.named.insert(name, value);
+            }
+            None => arguments.positional.push(value),
+        }
+    };
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => current.push(chars.next()?.1),
+            '=' if name.is_none() && is_name(current.trim()) => {
+                name = Some(current.trim().to_owned());
+                current.clear();
+            }
+            ',' => push(&mut current, &mut name),
+            ')' => {
+                // `!slap()` is a call without any arguments rather than one empty argument.
+                if !(current.trim().is_empty() && name.is_none() && i == 1) {
+                    push(&mut current, &mut name);
+                }
+
+                return Some((arguments, i + 1));
+            }
+            c => current.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(content: &str, arguments: &str) -> String {
+        let (arguments, _) = parse_arguments(arguments).unwrap();
+        Template::parse(content).unwrap().render(&arguments)
+    }
+
+    #[test]
+    fn parses_text_and_placeholders() {
+        let template = Template::parse("{0} by {by:nobody}!").unwrap();
+
+        assert_eq!(
+            template.segments,
+            [
+                Segment::Placeholder {
+                    key: Key::Position(0),
+                    default: None,
+                },
+                Segment::Text(" by ".into()),
+                Segment::Placeholder {
+                    key: Key::Name("by".into()),
+                    default: Some("nobody".into()),
+                },
+                Segment::Text("!".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_escaped_braces() {
+        let template = Template::parse("{{0}} is }}{{").unwrap();
+
+        assert_eq!(template.segments, [Segment::Text("{0} is }{".into())]);
+    }
+
+    #[test]
+    fn rejects_malformed_placeholders() {
+        for content in [
+            "{", "a {0", "}", "{}", "{ }", "{a-b}", "{{0}", "{0{}}", ":{:x}",
+        ] {
+            assert!(
+                matches!(Template::parse(content), Err(Error::InvalidTemplate(_))),
+                "{content} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn renders_arguments() {
+        let content = "{0} was slapped by {by:nobody}{1}";
+
+        assert_eq!(
+            render(content, "(alice, by=bob)"),
+            "alice was slapped by bob"
+        );
+        assert_eq!(render(content, "(alice)"), "alice was slapped by nobody");
+        assert_eq!(render(content, "()"), " was slapped by nobody");
+        assert_eq!(render("{{{0}}}", "(x)"), "{x}");
+    }
+
+    #[test]
+    fn parses_arguments() {
+        let (arguments, length) = parse_arguments("( alice , by = bob ) rest").unwrap();
+
+        assert_eq!(length, 20);
+        assert_eq!(arguments.positional, ["alice"]);
+        assert_eq!(arguments.named.get("by").map(String::as_str), Some("bob"));
+    }
+
+    #[test]
+    fn parses_escaped_arguments() {
+        let (arguments, _) = parse_arguments(r"(a\, b, c\)d, e\=f, x=y=z)").unwrap();
+
+        assert_eq!(arguments.positional, ["a, b", "c)d", "e=f"]);
+        assert_eq!(arguments.named.get("x").map(String::as_str), Some("y=z"));
+    }
+
+    #[test]
+    fn parses_empty_arguments() {
+        let (arguments, length) = parse_arguments("()").unwrap();
+        assert_eq!(length, 2);
+        assert!(arguments.positional.is_empty());
+
+        let (arguments, _) = parse_arguments("(,)").unwrap();
+        assert_eq!(arguments.positional, ["", ""]);
+    }
+
+    #[test]
+    fn rejects_incomplete_arguments() {
+        for text in ["", "alice)", "(alice", r"(alice\)", r"(alice\"] {
+            assert!(parse_arguments(text).is_none(), "{text} should be rejected");
+        }
+    }
+}
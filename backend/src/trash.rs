@@ -0,0 +1,422 @@
+use axum::response::IntoResponse;
+use idlib::AuthorizeCookie;
+
+use anyhow::Context;
+use axum::{extract::Path, Extension, Json};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::from_row;
+use tracing::{error, info};
+use ts_rs::TS;
+use utoipa::ToSchema;
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::alias::{self, Alias, HasDeleteAliases};
+use crate::alias_type::AliasType;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::events::AliasEventKind;
+use crate::expand;
+use crate::suggest;
+use crate::webhook::{self, WebhookEvent};
+use crate::AppState;
+
+/// How long deleted aliases are kept in the trash if `TRASH_RETENTION_DAYS` is not set.
+pub const DEFAULT_RETENTION_DAYS: u64 = 30;
+
+/// How often the trash is checked for aliases that should be purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// An alias which has been deleted but can still be restored.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedAlias {
+    /// A short handle for users to easily remember the alias.
+    #[schema(example = "funny.png")]
+    pub name: String,
+
+    /// The content of the alias which is to be used as the replacement.
+    #[schema(example = "https://example.com/funny.png")]
+    pub content: String,
+
+    /// A category describing the type of content in the alias.
+    #[serde(rename = "type")]
+    pub typ: AliasType,
+
+    /// The username of the account who first created the alias.
+    #[schema(example = "Alice")]
+    pub author: String,
+
+    /// A unix timestamp of when this alias was created.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+
+    /// The username of the account who deleted the alias.
+    #[schema(example = "Bob")]
+    pub deleted_by: String,
+
+    /// A unix timestamp of when this alias was deleted.
+    #[schema(example = 1670802822)]
+    pub deleted_at: u64,
+
+    /// A unix timestamp of when this alias will be permanently deleted.
+    #[schema(example = 1673394822)]
+    pub purge_at: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbTrashedAlias {
+    name: String,
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+    author: String,
+    created_at: u64,
+    deleted_by: String,
+    deleted_at: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbTrashedContent {
+    content: String,
+    #[serde(rename = "type")]
+    typ: AliasType,
+}
+
+impl TrashedAlias {
+    fn from_db(alias: DbTrashedAlias, retention: Duration) -> Self {
+        Self {
+            name: alias.name,
+            content: alias.content,
+            typ: alias.typ,
+            author: alias.author,
+            created_at: alias.created_at,
+            deleted_by: alias.deleted_by,
+            deleted_at: alias.deleted_at,
+            purge_at: alias.deleted_at + retention.as_secs(),
+        }
+    }
+}
+
+/// Get a list of all aliases in the trash, most recently deleted first.
+/// # Note
+/// Requires `delete-aliases` permission.
+#[utoipa::path(
+    get,
+    path = "/api/trash",
+    responses(
+        (status = 200, description = "All trashed aliases are returned.", body = [TrashedAlias]),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_trash(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasDeleteAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let retention = state.trash_retention;
+            state
+                .db
+                .call(move |conn| get_all(conn, retention).map(Json))
+                .await
+        })
+        .await
+}
+
+pub fn get_all(conn: &Connection, retention: Duration) -> Result<Vec<TrashedAlias>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                a.name,
+                a.content,
+                at.name as type,
+                a.author,
+                a.created_at,
+                a.deleted_by,
+                a.deleted_at
+            FROM aliases a
+            JOIN alias_types at ON at.id = a.type
+            WHERE a.deleted_at IS NOT NULL
+            ORDER BY a.deleted_at DESC",
+        )
+        .context("Failed to prepare statement for trash query")?;
+
+    let aliases = stmt
+        .query_map(params![], |row| {
+            Ok(TrashedAlias::from_db(
+                from_row::<DbTrashedAlias>(row).unwrap(),
+                retention,
+            ))
+        })
+        .context("Failed to query trash")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect trash")?;
+
+    Ok(aliases)
+}
+
+/// Restore an alias from the trash.
+/// # Note
+/// Requires `delete-aliases` permission.
+#[utoipa::path(
+    post,
+    path = "/api/trash/{name}/restore",
+    responses(
+        (status = 200, description = "The alias was successfully restored."),
+        (status = 400, description = "Restoring the alias would make it expand into itself."),
+        (status = 404, description = "No alias with that name is in the trash."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("name" = String, Path, description = "Name of the alias to restore."),
+    )
+)]
+pub async fn post_restore_trash(
+    Path(name): Path<String>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasDeleteAliases>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let names = vec![name.clone()];
+            let events = state.events.clone();
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+                    let restored = restore(&tx, &name, &payload.name, Some(&request_id))?;
+                    tx.commit().context("Failed to commit transaction")?;
+                    events.publish(AliasEventKind::Created, restored, None);
+
+                    Ok::<_, Error>(())
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+            suggest::update(&state, names).await?;
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+/// Moves the alias `name` out of the trash in the transaction `tx` for `user`, returning the
+/// restored alias.
+pub(crate) fn restore(
+    tx: &Connection,
+    name: &str,
+    user: &str,
+    request_id: Option<&RequestId>,
+) -> Result<Alias, Error> {
+    let trashed = tx
+        .query_row(
+            "SELECT a.content, at.name as type
+            FROM aliases a
+            JOIN alias_types at ON at.id = a.type
+            WHERE a.name = ? AND a.deleted_at IS NOT NULL",
+            params![name],
+            |row| Ok(from_row::<DbTrashedContent>(row).unwrap()),
+        )
+        .optional()
+        .context("Failed to query trashed alias")?
+        .ok_or(Error::NotFound)?;
+
+    // Other aliases might have started referencing this one while it was trashed.
+    if trashed.typ.is_text() {
+        expand::check_cycles(tx, name, &trashed.content)?;
+    }
+
+    tx.execute(
+        "UPDATE aliases
+        SET deleted_by = NULL, deleted_at = NULL
+        WHERE name = ?",
+        params![name],
+    )
+    .context("Failed to restore alias")?;
+
+    let restored = alias::get_by_name(tx, name.to_owned())?;
+    audit::record(
+        tx,
+        request_id,
+        user,
+        AuditAction::AliasRestore,
+        name,
+        None,
+        audit::snapshot(&restored),
+    )?;
+    webhook::enqueue(tx, WebhookEvent::AliasCreated, &restored, None)?;
+
+    Ok(restored)
+}
+
+/// Permanently deletes aliases which have been in the trash for longer than `retention`,
+/// returning how many were deleted.
+pub fn purge_expired(conn: &mut Connection, retention: Duration) -> Result<usize, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let cutoff = now.saturating_sub(retention.as_secs());
+
+    let tx = conn.transaction().context("Failed to create transaction")?;
+
+    tx.execute(
+        "DELETE FROM alias_revisions
+        WHERE alias IN (SELECT name FROM aliases WHERE deleted_at <= ?)",
+        params![cutoff],
+    )
+    .context("Failed to delete revisions of purged aliases")?;
+
+    tx.execute(
+        "DELETE FROM alias_redirects
+        WHERE target IN (SELECT name FROM aliases WHERE deleted_at <= ?)",
+        params![cutoff],
+    )
+    .context("Failed to delete redirects to purged aliases")?;
+
+    let purged = tx
+        .execute("DELETE FROM aliases WHERE deleted_at <= ?", params![cutoff])
+        .context("Failed to purge aliases")?;
+
+    tx.commit().context("Failed to commit transaction")?;
+
+    Ok(purged)
+}
+
+/// Purges expired aliases from the trash once every hour.
+pub async fn purge_periodically(db: tokio_rusqlite::Connection, retention: Duration) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match db.call(move |conn| purge_expired(conn, retention)).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {purged} aliases from the trash"),
+            Err(e) => error!("Failed to purge trash: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// A database with the live alias `live`, the alias `old` which was trashed long ago and has a
+    /// redirect and the alias `new` which was just trashed. `new` expands to `live`.
+    async fn setup() -> tokio_rusqlite::Connection {
+        let db = crate::test_database().await;
+        db.call(|conn| {
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+            conn.execute_batch(&format!(
+                "INSERT INTO users (username, created_at) VALUES ('alice', 0), ('bob', 0);
+                INSERT INTO aliases (name, content, type, author, created_at, deleted_by, deleted_at)
+                VALUES ('live', 'hello', 1, 'alice', 0, NULL, NULL),
+                    ('old', 'gone', 1, 'alice', 0, 'bob', 0),
+                    ('new', '!live', 1, 'alice', 0, 'bob', {now});
+                INSERT INTO alias_revisions (alias, content, type, editor, created_at)
+                VALUES ('old', 'gone', 1, 'alice', 0);
+                INSERT INTO alias_redirects (name, target, created_by, created_at, expires_at)
+                VALUES ('older', 'old', 'alice', 0, {now} + 3600);"
+            ))
+            .unwrap();
+        })
+        .await;
+        db
+    }
+
+    #[tokio::test]
+    async fn lists_trashed_aliases_newest_first() {
+        let db = setup().await;
+        let trash = db.call(|conn| get_all(conn, DAY).unwrap()).await;
+
+        let names = trash
+            .iter()
+            .map(|alias| alias.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["new", "old"]);
+        assert_eq!(trash[1].deleted_by, "bob");
+        assert_eq!(trash[1].purge_at, DAY.as_secs());
+    }
+
+    #[tokio::test]
+    async fn trashed_aliases_keep_their_name_until_purged() {
+        let db = setup().await;
+        db.call(|conn| {
+            assert!(matches!(
+                alias::get_by_name(conn, "new".to_owned()),
+                Err(Error::NotFound)
+            ));
+            assert!(matches!(
+                alias::check_name_available(conn, "new"),
+                Err(Error::AliasInTrash)
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn restores_trashed_aliases() {
+        let db = setup().await;
+        db.call(|conn| {
+            let tx = conn.transaction().unwrap();
+            let restored = restore(&tx, "new", "bob", None).unwrap();
+            tx.commit().unwrap();
+
+            assert_eq!(restored.content, "!live");
+            assert_eq!(
+                alias::get_by_name(conn, "new".to_owned()).unwrap().name,
+                "new"
+            );
+            assert!(matches!(
+                restore(conn, "live", "bob", None),
+                Err(Error::NotFound)
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn does_not_restore_aliases_into_a_cycle() {
+        let db = setup().await;
+        db.call(|conn| {
+            conn.execute(
+                "UPDATE aliases SET content = '!new' WHERE name = 'live'",
+                [],
+            )
+            .unwrap();
+
+            assert!(matches!(
+                restore(conn, "new", "bob", None),
+                Err(Error::AliasCycle(_))
+            ));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn purges_expired_aliases_with_their_history() {
+        let db = setup().await;
+        db.call(|conn| {
+            assert_eq!(purge_expired(conn, DAY).unwrap(), 1);
+
+            let count = |table: &str| -> u64 {
+                conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+            };
+            assert_eq!(count("aliases"), 2);
+            assert_eq!(count("alias_revisions"), 0);
+            assert_eq!(count("alias_redirects"), 0);
+            assert!(alias::check_name_available(conn, "old").is_ok());
+        })
+        .await;
+    }
+}
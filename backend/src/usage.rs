@@ -0,0 +1,85 @@
+//! Counts how often aliases are used in memory and writes the counts to the database in batches,
+//! so looking up an alias doesn't have to wait for a write on the database connection.
+
+use anyhow::Context;
+use rusqlite::{params, Connection};
+use tracing::error;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::suggest::{Changes, SuggestIndex};
+
+/// How often the counted uses are written to the database, uses counted since the last write are
+/// lost when the server stops.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Uses of aliases which haven't been written to the database yet.
+#[derive(Default)]
+pub struct UseCounts {
+    pending: Mutex<HashMap<String, u64>>,
+}
+
+impl UseCounts {
+    /// Counts a use of the alias `name`.
+    pub fn count(&self, name: &str) {
+        *self
+            .pending
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default() += 1;
+    }
+
+    fn take(&self) -> HashMap<String, u64> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+}
+
+/// Adds the counted uses to the aliases in a single transaction.
+pub fn write(conn: &mut Connection, uses: &HashMap<String, u64>) -> Result<(), Error> {
+    let tx = conn.transaction().context("Failed to create transaction")?;
+    for (name, uses) in uses {
+        tx.execute(
+            "UPDATE aliases SET uses = uses + ? WHERE name = ?",
+            params![uses, name],
+        )
+        .context("Failed to count alias uses")?;
+    }
+    tx.commit().context("Failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Writes the counted uses to the database every minute and updates the suggestions, which are
+/// ordered by the uses.
+pub async fn flush_periodically(
+    db: tokio_rusqlite::Connection,
+    counts: Arc<UseCounts>,
+    suggestions: Arc<RwLock<SuggestIndex>>,
+) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let uses = counts.take();
+        if uses.is_empty() {
+            continue;
+        }
+
+        let suggestions = suggestions.clone();
+        let written = db.call(move |conn| {
+            write(conn, &uses)?;
+            let changes = Changes::load(conn, uses.into_keys().collect())?;
+            suggestions.write().unwrap().apply(changes);
+            Ok::<_, Error>(())
+        });
+
+        if let Err(e) = written.await {
+            error!("Failed to write alias uses: {e}");
+        }
+    }
+}
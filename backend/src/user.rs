@@ -13,6 +13,7 @@ use utoipa::ToSchema;
 use std::sync::Arc;
 use std::time::SystemTime;
 
+use crate::audit::{self, AuditAction};
 use crate::error::Error;
 use crate::AppState;
 
@@ -139,10 +140,27 @@ pub async fn create_user_if_missing(
         } else {
             let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
 
-            conn.execute(
+            let tx = conn.transaction()?;
+            tx.execute(
                 "INSERT INTO users (username, created_at) VALUES (?1, ?2)",
                 params![name, now],
             )?;
+
+            let user = User {
+                username: name.clone(),
+                created_at: now,
+            };
+            audit::record(
+                &tx,
+                None,
+                &name,
+                AuditAction::UserCreate,
+                &name,
+                None,
+                audit::snapshot(&user),
+            )?;
+
+            tx.commit()?;
             info!("Created user for user {name}");
         }
 
@@ -41,3 +41,22 @@ pub fn check_length(
 
     Ok(())
 }
+
+/// Makes a field which is set to null `Some(None)` so it can be told apart from a missing field,
+/// which is `None` when combined with `#[serde(default)]`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+/// The number of rows to skip to get to `page`, starting at 1, when each page has `per_page` rows.
+pub fn page_offset(page: u64, per_page: u64) -> Result<u64, Error> {
+    page.checked_sub(1)
+        .and_then(|page| page.checked_mul(per_page))
+        // SQLite only takes signed offsets.
+        .filter(|offset| i64::try_from(*offset).is_ok())
+        .ok_or_else(|| Error::InvalidQuery("page is out of range".into()))
+}
@@ -0,0 +1,1198 @@
+//! Sends signed requests to external services when aliases are created, updated or deleted, so
+//! they don't have to poll the alias list. Events are queued in the same transaction as the
+//! change and delivered in the background, failed deliveries are retried with exponential backoff
+//! until they are given up on. Each webhook receives its deliveries in the order the changes were
+//! made.
+
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::Uri;
+use axum::response::IntoResponse;
+use idlib::{AuthorizeCookie, Has};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, Query},
+    Extension, Json,
+};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use itertools::Itertools;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use serde_rusqlite::from_row;
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tracing::{error, info};
+use ts_rs::TS;
+use utoipa::{IntoParams, ToSchema};
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::alias::Alias;
+use crate::audit::{self, AuditAction, RequestId};
+use crate::error::Error;
+use crate::moderation::ModerationStatus;
+use crate::AppState;
+
+/// A delivery is given up on after this many failed requests.
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// How long to wait before retrying a delivery the first time, the wait doubles with every
+/// failed attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The longest wait between two attempts of a delivery.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How often deliveries which are due for a retry are looked for.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The most deliveries sent in one go, the rest are sent right after.
+const BATCH_SIZE: usize = 100;
+
+/// How many deliveries are sent at the same time.
+const CONCURRENT_DELIVERIES: usize = 8;
+
+/// How long a single request may take before the attempt counts as failed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Successful deliveries are removed from the log after a month.
+const DELIVERED_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The most deliveries returned by the delivery log.
+const MAX_LISTED_DELIVERIES: usize = 100;
+
+/// The length of generated webhook secrets.
+const SECRET_LENGTH: usize = 40;
+
+type HasManageWebhooks = Has<"manage-webhooks">;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A change to an alias which webhooks can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+pub enum WebhookEvent {
+    /// An alias was published, either by being created, approved by a moderator, restored from
+    /// the trash or shown again after being hidden.
+    #[serde(rename = "alias.created")]
+    AliasCreated,
+    /// The content, name, synonyms, tags or media of a published alias changed.
+    #[serde(rename = "alias.updated")]
+    AliasUpdated,
+    /// A published alias was moved to the trash, hidden after being reported or changed by a user
+    /// whose changes need to be approved.
+    #[serde(rename = "alias.deleted")]
+    AliasDeleted,
+}
+
+impl WebhookEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::AliasCreated => "alias.created",
+            WebhookEvent::AliasUpdated => "alias.updated",
+            WebhookEvent::AliasDeleted => "alias.deleted",
+        }
+    }
+}
+
+/// The JSON body of the requests sent to webhooks.
+/// # Note
+/// Each request has the headers `X-Xdd-Event` with the event, `X-Xdd-Delivery` with the id of the
+/// delivery which stays the same across retries, `X-Xdd-Timestamp` with the unix timestamp of the
+/// attempt and `X-Xdd-Signature` with `sha256=` followed by the hex encoded HMAC-SHA256 of
+/// `{timestamp}.{body}` keyed with the secret of the webhook.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookPayload {
+    pub event: WebhookEvent,
+
+    /// The alias after the change, or before it for deleted aliases.
+    pub alias: Alias,
+
+    /// The name the alias had before it was renamed.
+    #[schema(example = "funny.png")]
+    pub previous_name: Option<String>,
+
+    /// A unix timestamp of when the change was made.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+}
+
+/// Queues `event` for every webhook subscribed to it. Only published aliases are sent, so
+/// subscribers never learn about aliases which are waiting for moderation or were hidden.
+pub(crate) fn enqueue(
+    conn: &Connection,
+    event: WebhookEvent,
+    alias: &Alias,
+    previous_name: Option<&str>,
+) -> Result<(), Error> {
+    if alias.status != ModerationStatus::Approved || alias.hidden_at.is_some() {
+        return Ok(());
+    }
+
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let payload = serde_json::to_string(&WebhookPayload {
+        event,
+        alias: alias.clone(),
+        previous_name: previous_name.map(ToOwned::to_owned),
+        created_at: now,
+    })
+    .context("Failed to serialize webhook payload")?;
+
+    conn.execute(
+        "INSERT INTO webhook_deliveries
+            (webhook, event, payload, status, next_attempt_at, created_at)
+        SELECT w.id, ?1, ?2, 'pending', ?3, ?3
+        FROM webhooks w
+        WHERE EXISTS (SELECT 1 FROM json_each(w.events) e WHERE e.value = ?1)",
+        params![event.as_str(), payload, now],
+    )
+    .context("Failed to queue webhook deliveries")?;
+
+    Ok(())
+}
+
+/// Makes the requests for deliveries, implemented by [`HttpWebhookClient`] and replaceable in
+/// tests.
+#[async_trait]
+pub trait WebhookClient: Send + Sync {
+    /// Posts `body` to `url`, returning the status of the response or a description of the error
+    /// if no response was received.
+    async fn post(
+        &self,
+        url: &str,
+        headers: Vec<(&'static str, String)>,
+        body: String,
+    ) -> Result<u16, String>;
+}
+
+/// Sends deliveries over HTTP, redirects are not followed.
+pub struct HttpWebhookClient {
+    client: reqwest::Client,
+}
+
+impl HttpWebhookClient {
+    pub fn new() -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "-webhooks/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()
+            .context("Failed to create HTTP client for webhooks")?;
+
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl WebhookClient for HttpWebhookClient {
+    async fn post(
+        &self,
+        url: &str,
+        headers: Vec<(&'static str, String)>,
+        body: String,
+    ) -> Result<u16, String> {
+        let mut request = self.client.post(url).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send().await.map_err(|e| e.to_string())?;
+
+        Ok(response.status().as_u16())
+    }
+}
+
+/// Returns the hex encoded HMAC-SHA256 of `{timestamp}.{body}` keyed with `secret`.
+pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take keys of any size");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body.as_bytes());
+
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+#[derive(Deserialize, Debug)]
+struct DbDueDelivery {
+    id: i64,
+    webhook: i64,
+    url: String,
+    secret: String,
+    event: String,
+    payload: String,
+}
+
+/// Sends up to [`BATCH_SIZE`] deliveries which are due, returning how many were sent.
+/// # Note
+/// The deliveries of a webhook are sent one after another in the order their events happened. A
+/// failed delivery holds back the later ones of its webhook until it is delivered or given up on,
+/// so a webhook never receives an older change of an alias after a newer one.
+pub async fn deliver_due(
+    db: &tokio_rusqlite::Connection,
+    client: &dyn WebhookClient,
+) -> Result<usize, Error> {
+    let due = db.call(due_deliveries).await?;
+
+    // Different webhooks don't wait for each other.
+    let queues = due
+        .into_iter()
+        .into_group_map_by(|delivery| delivery.webhook);
+    let results = futures::stream::iter(queues.into_values())
+        .map(|queue| async move {
+            let mut results = Vec::new();
+            for delivery in queue {
+                let id = delivery.id;
+                let result = send(client, delivery).await;
+                let delivered = is_delivered(&result);
+
+                results.push((id, result));
+                if !delivered {
+                    break;
+                }
+            }
+
+            results
+        })
+        .buffer_unordered(CONCURRENT_DELIVERIES)
+        .concat()
+        .await;
+
+    let sent = results.len();
+    db.call(move |conn| {
+        let tx = conn.transaction().context("Failed to create transaction")?;
+        for (id, result) in results {
+            record(&tx, id, result)?;
+        }
+        tx.commit().context("Failed to commit transaction")?;
+
+        Ok::<_, Error>(())
+    })
+    .await?;
+
+    Ok(sent)
+}
+
+/// Makes a signed request for `delivery`.
+async fn send(client: &dyn WebhookClient, delivery: DbDueDelivery) -> Result<u16, String> {
+    let timestamp = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let signature = sign(&delivery.secret, timestamp, &delivery.payload);
+    let headers = vec![
+        ("Content-Type", "application/json".to_owned()),
+        ("X-Xdd-Event", delivery.event),
+        ("X-Xdd-Delivery", delivery.id.to_string()),
+        ("X-Xdd-Timestamp", timestamp.to_string()),
+        ("X-Xdd-Signature", format!("sha256={signature}")),
+    ];
+
+    client.post(&delivery.url, headers, delivery.payload).await
+}
+
+fn is_delivered(result: &Result<u16, String>) -> bool {
+    matches!(result, Ok(200..=299))
+}
+
+/// Pending deliveries whose next attempt is due and which aren't held back by an earlier delivery
+/// of their webhook waiting for a retry, oldest first.
+fn due_deliveries(conn: &mut Connection) -> Result<Vec<DbDueDelivery>, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT d.id, d.webhook, w.url, w.secret, d.event, d.payload
+            FROM webhook_deliveries d
+            JOIN webhooks w ON w.id = d.webhook
+            WHERE d.status = 'pending'
+                AND d.next_attempt_at <= $1
+                AND NOT EXISTS (SELECT 1
+                    FROM webhook_deliveries e
+                    WHERE e.webhook = d.webhook
+                        AND e.status = 'pending'
+                        AND e.id < d.id
+                        AND e.next_attempt_at > $1)
+            ORDER BY d.id
+            LIMIT $2",
+        )
+        .context("Failed to prepare statement for due deliveries")?;
+
+    let due = stmt
+        .query_map(params![now, BATCH_SIZE], |row| {
+            Ok(from_row::<DbDueDelivery>(row).unwrap())
+        })
+        .context("Failed to query due deliveries")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect due deliveries")?;
+
+    Ok(due)
+}
+
+/// How long to wait after the `attempts`th failed attempt.
+fn retry_delay(attempts: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+fn record(conn: &Connection, id: i64, result: Result<u16, String>) -> Result<(), Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+    let delivered = is_delivered(&result);
+    let (status, error) = match result {
+        Ok(status) => (Some(status), None),
+        Err(error) => (None, Some(error)),
+    };
+    conn.execute(
+        "INSERT INTO webhook_attempts (delivery, status, error, attempted_at)
+        VALUES (?, ?, ?, ?)",
+        params![id, status, error, now],
+    )
+    .context("Failed to record delivery attempt")?;
+
+    let attempts: u32 = conn
+        .query_row(
+            "UPDATE webhook_deliveries SET attempts = attempts + 1 WHERE id = ? RETURNING attempts",
+            params![id],
+            |row| row.get(0),
+        )
+        .context("Failed to count delivery attempts")?;
+
+    if delivered {
+        conn.execute(
+            "UPDATE webhook_deliveries
+            SET status = 'delivered', next_attempt_at = NULL, delivered_at = ?
+            WHERE id = ?",
+            params![now, id],
+        )
+        .context("Failed to mark delivery as delivered")?;
+    } else if attempts >= MAX_ATTEMPTS {
+        conn.execute(
+            "UPDATE webhook_deliveries SET status = 'dead', next_attempt_at = NULL WHERE id = ?",
+            params![id],
+        )
+        .context("Failed to give up on delivery")?;
+    } else {
+        conn.execute(
+            "UPDATE webhook_deliveries SET next_attempt_at = ? WHERE id = ?",
+            params![now + retry_delay(attempts).as_secs(), id],
+        )
+        .context("Failed to schedule delivery retry")?;
+    }
+
+    Ok(())
+}
+
+/// Removes successful deliveries which are older than [`DELIVERED_RETENTION`], returning how many
+/// were removed.
+fn prune_delivered(conn: &mut Connection) -> Result<usize, Error> {
+    let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+    let cutoff = now.saturating_sub(DELIVERED_RETENTION.as_secs());
+
+    let pruned = conn
+        .execute(
+            "DELETE FROM webhook_deliveries WHERE status = 'delivered' AND delivered_at <= ?",
+            params![cutoff],
+        )
+        .context("Failed to prune delivered webhook deliveries")?;
+
+    Ok(pruned)
+}
+
+/// Sends deliveries as soon as they are queued and retries failed ones once they are due.
+pub async fn deliver_continuously(
+    db: tokio_rusqlite::Connection,
+    client: Arc<dyn WebhookClient>,
+    queued: Arc<Notify>,
+) {
+    loop {
+        match deliver_due(&db, client.as_ref()).await {
+            Ok(BATCH_SIZE) => continue,
+            Ok(0) => {}
+            Ok(sent) => info!("Sent {sent} webhook deliveries"),
+            Err(e) => error!("Failed to send webhook deliveries: {e}"),
+        }
+
+        if let Err(e) = db.call(prune_delivered).await {
+            error!("Failed to prune webhook deliveries: {e}");
+        }
+
+        tokio::select! {
+            _ = queued.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// A registered webhook.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    #[schema(example = 3)]
+    pub id: i64,
+
+    /// Where the requests are sent.
+    #[schema(example = "https://quotes.hivecom.net/api/aliases/webhook")]
+    pub url: String,
+
+    /// The events the webhook is sent.
+    pub events: Vec<WebhookEvent>,
+
+    /// The username of the account who registered the webhook.
+    #[schema(example = "Alice")]
+    pub created_by: String,
+
+    /// A unix timestamp of when the webhook was registered.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+
+    /// How many deliveries are waiting to be sent or retried.
+    #[schema(example = 0)]
+    pub pending: u64,
+
+    /// How many deliveries were given up on.
+    #[schema(example = 2)]
+    pub dead: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbWebhook {
+    id: i64,
+    url: String,
+    /// A JSON array of the events.
+    events: String,
+    created_by: String,
+    created_at: u64,
+    pending: u64,
+    dead: u64,
+}
+
+impl From<DbWebhook> for Webhook {
+    fn from(webhook: DbWebhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: serde_json::from_str(&webhook.events).unwrap(),
+            created_by: webhook.created_by,
+            created_at: webhook.created_at,
+            pending: webhook.pending,
+            dead: webhook.dead,
+        }
+    }
+}
+
+/// Get all registered webhooks.
+/// # Note
+/// Requires `manage-webhooks` permission.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    responses(
+        (status = 200, description = "The webhooks are returned.", body = [Webhook]),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn get_webhooks(
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasManageWebhooks>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move { state.db.call(move |conn| get_all(conn).map(Json)).await })
+        .await
+}
+
+pub fn get_all(conn: &Connection) -> Result<Vec<Webhook>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                w.id,
+                w.url,
+                w.events,
+                w.created_by,
+                w.created_at,
+                (SELECT COUNT(*)
+                    FROM webhook_deliveries d
+                    WHERE d.webhook = w.id AND d.status = 'pending') as pending,
+                (SELECT COUNT(*)
+                    FROM webhook_deliveries d
+                    WHERE d.webhook = w.id AND d.status = 'dead') as dead
+            FROM webhooks w
+            ORDER BY w.id",
+        )
+        .context("Failed to prepare statement for webhook query")?;
+
+    let webhooks = stmt
+        .query_map([], |row| {
+            Ok(Webhook::from(from_row::<DbWebhook>(row).unwrap()))
+        })
+        .context("Failed to query webhooks")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect webhooks")?;
+
+    Ok(webhooks)
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostWebhook {
+    /// Where the requests should be sent, an `http` or `https` URL.
+    #[schema(example = "https://quotes.hivecom.net/api/aliases/webhook")]
+    pub url: String,
+
+    /// The events the webhook should be sent.
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct PostWebhookResponse {
+    #[schema(example = 3)]
+    pub id: i64,
+
+    /// The key of the signatures of the requests, it can't be retrieved again.
+    #[schema(example = "q3ZkVw1c8d0Hh6Xo2Ns9TzRb4FyLpJ7mEaGuCiKe")]
+    pub secret: String,
+}
+
+/// Register a webhook which is sent a signed request whenever one of the events happens.
+/// # Note
+/// Requires `manage-webhooks` permission. The URL isn't restricted to public addresses, so
+/// services on the same network as well as local receivers for testing can be registered.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    request_body = PostWebhook,
+    responses(
+        (status = 200, description = "The webhook was registered, the secret is returned.", body = PostWebhookResponse),
+        (status = 400, description = "The URL is invalid or no events were given."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    )
+)]
+pub async fn post_webhook(
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageWebhooks>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+    request: Result<Json<PostWebhook>, JsonRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Json(request) = request?;
+
+            let url = request.url.trim().to_owned();
+            let valid = url.parse::<Uri>().ok().filter(|uri| {
+                matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+            });
+            if valid.is_none() {
+                return Err(Error::InvalidWebhookUrl(url));
+            }
+            if request.events.is_empty() {
+                return Err(Error::EmptyField("events"));
+            }
+            let events = request.events.into_iter().unique().collect::<Vec<_>>();
+            let events_str =
+                serde_json::to_string(&events).context("Failed to serialize events")?;
+
+            let secret = Alphanumeric.sample_string(&mut rand::thread_rng(), SECRET_LENGTH);
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    tx.execute(
+                        "INSERT INTO webhooks (url, secret, events, created_by, created_at)
+                        VALUES (?, ?, ?, ?, ?)",
+                        params![url, secret, events_str, payload.name, now],
+                    )
+                    .context("Failed to insert webhook")?;
+                    let id = tx.last_insert_rowid();
+
+                    // The secret is left out so it can't be read from the log.
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::WebhookCreate,
+                        &id.to_string(),
+                        None,
+                        Some(json!({ "url": url, "events": events })),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(Json(PostWebhookResponse { id, secret }))
+                })
+                .await
+        })
+        .await
+}
+
+/// Delete a webhook along with its deliveries, deliveries which weren't sent yet are dropped.
+/// # Note
+/// Requires `manage-webhooks` permission.
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    responses(
+        (status = 200, description = "The webhook was deleted."),
+        (status = 404, description = "Webhook with the specified id does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("id" = i64, Path, description = "Id of the webhook to delete."),
+    )
+)]
+pub async fn delete_webhook(
+    Path(id): Path<i64>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageWebhooks>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let (url, events) = tx
+                        .query_row(
+                            "DELETE FROM webhooks WHERE id = ? RETURNING url, events",
+                            params![id],
+                            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                        )
+                        .optional()
+                        .context("Failed to delete webhook")?
+                        .ok_or(Error::NotFound)?;
+                    let events: Vec<WebhookEvent> = serde_json::from_str(&events).unwrap();
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::WebhookDelete,
+                        &id.to_string(),
+                        Some(json!({ "url": url, "events": events })),
+                        None,
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await
+        })
+        .await
+}
+
+/// Where a delivery is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub enum DeliveryStatus {
+    /// Waiting to be sent or retried.
+    Pending,
+    /// The webhook responded with a 2xx status.
+    Delivered,
+    /// Every attempt failed, the delivery is only sent again if it is retried manually.
+    Dead,
+}
+
+impl DeliveryStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Dead => "dead",
+        }
+    }
+}
+
+/// A single request made for a delivery.
+#[derive(Debug, Serialize, Deserialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct DeliveryAttempt {
+    /// The HTTP status of the response, missing if no response was received.
+    #[schema(example = 503)]
+    pub status: Option<u16>,
+
+    /// Why no response was received.
+    #[schema(example = "operation timed out")]
+    pub error: Option<String>,
+
+    /// A unix timestamp of when the request was made.
+    #[schema(example = 1670802822)]
+    pub attempted_at: u64,
+}
+
+/// An event queued for a webhook along with the requests made to deliver it.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[ts(export, export_to = "../frontend/src/types/")]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    #[schema(example = 120)]
+    pub id: i64,
+
+    pub event: WebhookEvent,
+
+    pub status: DeliveryStatus,
+
+    /// The body of the requests, a [`WebhookPayload`].
+    #[ts(type = "WebhookPayload")]
+    #[schema(value_type = WebhookPayload)]
+    pub payload: Value,
+
+    /// Every request made for the delivery, oldest first.
+    pub attempts: Vec<DeliveryAttempt>,
+
+    /// A unix timestamp of when the delivery will be attempted next.
+    #[schema(example = 1670802852)]
+    pub next_attempt_at: Option<u64>,
+
+    /// A unix timestamp of when the event was queued.
+    #[schema(example = 1670802822)]
+    pub created_at: u64,
+
+    /// A unix timestamp of when the webhook accepted the delivery.
+    #[schema(example = json!(null))]
+    pub delivered_at: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DbWebhookDelivery {
+    id: i64,
+    event: WebhookEvent,
+    status: DeliveryStatus,
+    payload: String,
+    /// A JSON array of the attempts.
+    attempts: String,
+    next_attempt_at: Option<u64>,
+    created_at: u64,
+    delivered_at: Option<u64>,
+}
+
+impl From<DbWebhookDelivery> for WebhookDelivery {
+    fn from(delivery: DbWebhookDelivery) -> Self {
+        Self {
+            id: delivery.id,
+            event: delivery.event,
+            status: delivery.status,
+            payload: serde_json::from_str(&delivery.payload).unwrap(),
+            attempts: serde_json::from_str(&delivery.attempts).unwrap(),
+            next_attempt_at: delivery.next_attempt_at,
+            created_at: delivery.created_at,
+            delivered_at: delivery.delivered_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryQuery {
+    /// Only list deliveries with this status, `dead` lists the deliveries which were given up on.
+    pub status: Option<DeliveryStatus>,
+}
+
+/// Get the latest 100 deliveries of a webhook, newest first.
+/// # Note
+/// Requires `manage-webhooks` permission. Successful deliveries are removed after a month.
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    responses(
+        (status = 200, description = "The deliveries are returned.", body = [WebhookDelivery]),
+        (status = 400, description = "The status is invalid."),
+        (status = 404, description = "Webhook with the specified id does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("id" = i64, Path, description = "Id of the webhook."),
+        DeliveryQuery,
+    )
+)]
+pub async fn get_deliveries(
+    Path(id): Path<i64>,
+    AuthorizeCookie(_payload, maybe_token, ..): AuthorizeCookie<HasManageWebhooks>,
+    Extension(state): Extension<Arc<AppState>>,
+    query: Result<Query<DeliveryQuery>, QueryRejection>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let Query(query) = query?;
+
+            state
+                .db
+                .call(move |conn| {
+                    let exists = conn
+                        .query_row("SELECT 1 FROM webhooks WHERE id = ?", params![id], |_| {
+                            Ok(())
+                        })
+                        .optional()
+                        .context("Failed to query webhook")?
+                        .is_some();
+                    if !exists {
+                        return Err(Error::NotFound);
+                    }
+
+                    get_log(conn, id, query.status).map(Json)
+                })
+                .await
+        })
+        .await
+}
+
+pub fn get_log(
+    conn: &Connection,
+    webhook: i64,
+    status: Option<DeliveryStatus>,
+) -> Result<Vec<WebhookDelivery>, Error> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT
+                d.id,
+                d.event,
+                d.status,
+                d.payload,
+                (SELECT json_group_array(json_object(
+                        'status', wa.status,
+                        'error', wa.error,
+                        'attemptedAt', wa.attempted_at))
+                    FROM (SELECT * FROM webhook_attempts
+                        WHERE delivery = d.id
+                        ORDER BY id) wa) as attempts,
+                d.next_attempt_at,
+                d.created_at,
+                d.delivered_at
+            FROM webhook_deliveries d
+            WHERE d.webhook = ? AND (? IS NULL OR d.status = ?)
+            ORDER BY d.id DESC
+            LIMIT ?",
+        )
+        .context("Failed to prepare statement for delivery query")?;
+
+    let status = status.map(DeliveryStatus::as_str);
+    let deliveries = stmt
+        .query_map(
+            params![webhook, status, status, MAX_LISTED_DELIVERIES],
+            |row| {
+                Ok(WebhookDelivery::from(
+                    from_row::<DbWebhookDelivery>(row).unwrap(),
+                ))
+            },
+        )
+        .context("Failed to query deliveries")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to collect deliveries")?;
+
+    Ok(deliveries)
+}
+
+/// Send a delivery again right away, such as one which was given up on after the webhook was
+/// fixed.
+/// # Note
+/// Requires `manage-webhooks` permission. The delivery gets the full number of attempts again.
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/{id}/deliveries/{delivery}/retry",
+    responses(
+        (status = 200, description = "The delivery was queued again."),
+        (status = 404, description = "The webhook or delivery does not exist."),
+        (status = 403, description = "User does not have the required permissions."),
+        (status = 302, description = "Redirects to hiveID if not authenticated."),
+    ),
+    params(
+        ("id" = i64, Path, description = "Id of the webhook."),
+        ("delivery" = i64, Path, description = "Id of the delivery to retry."),
+    )
+)]
+pub async fn post_retry_delivery(
+    Path((id, delivery)): Path<(i64, i64)>,
+    AuthorizeCookie(payload, maybe_token, ..): AuthorizeCookie<HasManageWebhooks>,
+    Extension(state): Extension<Arc<AppState>>,
+    Extension(request_id): Extension<RequestId>,
+) -> impl IntoResponse {
+    maybe_token
+        .wrap_future(async move {
+            let now = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            state
+                .db
+                .call(move |conn| {
+                    let tx = conn.transaction().context("Failed to create transaction")?;
+
+                    let status = tx
+                        .query_row(
+                            "SELECT status FROM webhook_deliveries WHERE id = ? AND webhook = ?",
+                            params![delivery, id],
+                            |row| row.get::<_, String>(0),
+                        )
+                        .optional()
+                        .context("Failed to query delivery")?
+                        .ok_or(Error::NotFound)?;
+
+                    tx.execute(
+                        "UPDATE webhook_deliveries
+                        SET status = 'pending', attempts = 0, next_attempt_at = ?, delivered_at = NULL
+                        WHERE id = ?",
+                        params![now, delivery],
+                    )
+                    .context("Failed to retry delivery")?;
+
+                    audit::record(
+                        &tx,
+                        Some(&request_id),
+                        &payload.name,
+                        AuditAction::WebhookRetry,
+                        &id.to_string(),
+                        Some(json!({ "delivery": delivery, "status": status })),
+                        Some(json!({ "delivery": delivery, "status": "pending" })),
+                    )?;
+
+                    tx.commit().context("Failed to commit transaction")?;
+
+                    Ok::<_, Error>(())
+                })
+                .await?;
+
+            state.webhooks_queued.notify_one();
+
+            Ok::<_, Error>(())
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    /// Answers every request with the next of `responses`, remembering the requests.
+    struct StubClient {
+        responses: Mutex<Vec<Result<u16, String>>>,
+        requests: Mutex<Vec<(String, Vec<(&'static str, String)>, String)>>,
+    }
+
+    impl StubClient {
+        fn new(responses: Vec<Result<u16, String>>) -> Self {
+            Self {
+                responses: Mutex::new(responses),
+                requests: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// The value of the `X-Xdd-Delivery` header of every request.
+        fn deliveries(&self) -> Vec<i64> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(_, headers, _)| header(headers, "X-Xdd-Delivery").parse().unwrap())
+                .collect()
+        }
+    }
+
+    #[async_trait]
+    impl WebhookClient for StubClient {
+        async fn post(
+            &self,
+            url: &str,
+            headers: Vec<(&'static str, String)>,
+            body: String,
+        ) -> Result<u16, String> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((url.to_owned(), headers, body));
+            self.responses.lock().unwrap().remove(0)
+        }
+    }
+
+    fn header<'a>(headers: &'a [(&'static str, String)], name: &str) -> &'a str {
+        headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap()
+    }
+
+    /// A database with a webhook and `count` deliveries for it, returning the ids of the
+    /// deliveries.
+    async fn setup(count: usize) -> (tokio_rusqlite::Connection, Vec<i64>) {
+        let db = crate::test_database().await;
+        let ids = db
+            .call(move |conn| {
+                conn.execute_batch(
+                    "INSERT INTO users (username, created_at) VALUES ('alice', 0);
+                    INSERT INTO webhooks (id, url, secret, events, created_by, created_at)
+                    VALUES (1, 'http://localhost/hook', 'secret', '[\"alias.created\"]', 'alice', 0);",
+                )
+                .unwrap();
+
+                (0..count)
+                    .map(|i| {
+                        conn.execute(
+                            "INSERT INTO webhook_deliveries
+                                (webhook, event, payload, status, next_attempt_at, created_at)
+                            VALUES (1, 'alias.created', ?, 'pending', 0, 0)",
+                            params![format!("{{\"n\":{i}}}")],
+                        )
+                        .unwrap();
+                        conn.last_insert_rowid()
+                    })
+                    .collect()
+            })
+            .await;
+
+        (db, ids)
+    }
+
+    /// The status and number of attempts of a delivery.
+    async fn state(db: &tokio_rusqlite::Connection, id: i64) -> (String, u32) {
+        db.call(move |conn| {
+            conn.query_row(
+                "SELECT status, attempts FROM webhook_deliveries WHERE id = ?",
+                params![id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        })
+        .await
+    }
+
+    /// Makes the retry of every pending delivery due.
+    async fn make_due(db: &tokio_rusqlite::Connection) {
+        db.call(|conn| {
+            conn.execute(
+                "UPDATE webhook_deliveries SET next_attempt_at = 0 WHERE status = 'pending'",
+                [],
+            )
+            .unwrap()
+        })
+        .await;
+    }
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        assert_eq!(
+            sign("secret", 1670802822, r#"{"a":1}"#),
+            "0a8e53acd2cdfbe49a515c725de3c6bea283d4c14605d77b990df17c8e4e24da"
+        );
+    }
+
+    #[test]
+    fn doubles_retry_delay_up_to_maximum() {
+        assert_eq!(retry_delay(1), RETRY_DELAY);
+        assert_eq!(retry_delay(2), RETRY_DELAY * 2);
+        assert_eq!(retry_delay(4), RETRY_DELAY * 8);
+        assert_eq!(retry_delay(12), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn sends_signed_deliveries() {
+        let (db, ids) = setup(1).await;
+        let client = StubClient::new(vec![Ok(204)]);
+
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 1);
+
+        let requests = client.requests.lock().unwrap();
+        let (url, headers, body) = &requests[0];
+        let timestamp = header(headers, "X-Xdd-Timestamp").parse().unwrap();
+        assert_eq!(url, "http://localhost/hook");
+        assert_eq!(body, r#"{"n":0}"#);
+        assert_eq!(header(headers, "X-Xdd-Event"), "alias.created");
+        assert_eq!(
+            header(headers, "X-Xdd-Signature"),
+            format!("sha256={}", sign("secret", timestamp, body))
+        );
+        assert_eq!(state(&db, ids[0]).await, ("delivered".into(), 1));
+    }
+
+    #[tokio::test]
+    async fn retries_until_dead() {
+        let (db, ids) = setup(1).await;
+        let client = StubClient::new(
+            (0..MAX_ATTEMPTS)
+                .map(|i| {
+                    if i % 2 == 0 {
+                        Ok(503)
+                    } else {
+                        Err("timeout".into())
+                    }
+                })
+                .collect(),
+        );
+
+        let before = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs();
+        deliver_due(&db, &client).await.unwrap();
+        assert_eq!(state(&db, ids[0]).await, ("pending".into(), 1));
+
+        // The retry waits for the delay instead of being sent right away.
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 0);
+        let next_attempt_at: u64 = db
+            .call(|conn| {
+                conn.query_row(
+                    "SELECT next_attempt_at FROM webhook_deliveries",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap()
+            })
+            .await;
+        assert!(next_attempt_at >= before + RETRY_DELAY.as_secs());
+
+        for attempts in 2..=MAX_ATTEMPTS {
+            make_due(&db).await;
+            deliver_due(&db, &client).await.unwrap();
+
+            let status = if attempts == MAX_ATTEMPTS {
+                "dead"
+            } else {
+                "pending"
+            };
+            assert_eq!(state(&db, ids[0]).await, (status.into(), attempts));
+        }
+
+        // Dead deliveries are only sent again when they are retried manually.
+        make_due(&db).await;
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 0);
+        let log = db
+            .call(|conn| get_log(conn, 1, Some(DeliveryStatus::Dead)))
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].attempts.len(), MAX_ATTEMPTS as usize);
+    }
+
+    #[tokio::test]
+    async fn holds_back_deliveries_after_a_failure() {
+        let (db, ids) = setup(3).await;
+        let client = StubClient::new(vec![Ok(200), Ok(500), Ok(200), Ok(200)]);
+
+        // The second delivery fails, so the third isn't sent before it.
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 2);
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 0);
+
+        make_due(&db).await;
+        assert_eq!(deliver_due(&db, &client).await.unwrap(), 2);
+        assert_eq!(client.deliveries(), [ids[0], ids[1], ids[1], ids[2]]);
+    }
+}
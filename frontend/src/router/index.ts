@@ -1,5 +1,6 @@
 import { createRouter, createWebHistory } from 'vue-router'
 
+import { useAlias } from '../store/alias'
 import { useUser } from '../store/user'
 import afterEach from './guards/afterEach'
 import beforeResolve from './guards/beforeResolve'
@@ -60,10 +61,19 @@ const router = createRouter({
         title: 'Edit Alias',
         requiresAuth: true,
       },
-      beforeEnter() {
+      async beforeEnter(to) {
         const user = useUser()
+        const alias = useAlias()
+        // The list is still empty when the page is opened directly, the form needs it as well
+        await alias.fetch()
+        // The user is only set up after this guard on the first navigation, the API rejects
+        // edits which are not allowed in that case
+        if (!user.username)
+          return true
+
+        const author = alias.list.find(a => a.name === to.params.name)?.author
         // If user is not allowed, re-route back to list
-        if (!user.can('edit-aliases'))
+        if (!user.canChange('edit-aliases', author))
           return { name: 'RouteHome' }
         return true
       },
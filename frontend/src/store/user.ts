@@ -24,8 +24,17 @@ export const useUser = defineStore('user', () => {
     return allowed.some(item => permissions.value.includes(item))
   }
 
+  // Authors can always change their own aliases, other users need the permission
+  function canChange(permission: string, author?: string) {
+    if (isSignedIn.value && !isNil(author) && author === username.value)
+      return true
+
+    return can(permission)
+  }
+
   return {
     can,
+    canChange,
     reset,
     username,
     isSignedIn,
@@ -1,5 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { AliasType } from "./AliasType";
+import type { MediaInfo } from "./MediaInfo";
+import type { ModerationStatus } from "./ModerationStatus";
+import type { Rendition } from "./Rendition";
 
 export interface Alias {
   name: string;
@@ -7,4 +10,12 @@ export interface Alias {
   type: AliasType;
   author: string;
   createdAt: bigint;
+  synonyms: Array<string>;
+  tags: Array<string>;
+  media: MediaInfo | null;
+  renditions: Record<Rendition, string> | null;
+  status: ModerationStatus;
+  hiddenAt: bigint | null;
+  updatedAt: bigint;
+  changeSeq: bigint;
 }
@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Alias } from "./Alias";
+
+export interface AliasChanges {
+  aliases: Array<Alias>;
+  deleted: Array<string>;
+  seq: bigint;
+  more: boolean;
+}
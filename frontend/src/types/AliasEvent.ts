@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Alias } from "./Alias";
+import type { AliasEventKind } from "./AliasEventKind";
+
+export interface AliasEvent {
+  id: bigint;
+  type: AliasEventKind;
+  alias: Alias;
+  previousName: string | null;
+  createdAt: bigint;
+}
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+
+export interface AliasRevision {
+  id: bigint;
+  content: string;
+  type: AliasType;
+  previousContent: string | null;
+  previousType: AliasType | null;
+  editor: string;
+  createdAt: bigint;
+}
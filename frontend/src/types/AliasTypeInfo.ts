@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+import type { TypeDisplay } from "./TypeDisplay";
+
+export interface AliasTypeInfo {
+  name: AliasType;
+  label: string;
+  display: TypeDisplay;
+  maxSize: number | null;
+  builtin: boolean;
+  retiredAt: bigint | null;
+}
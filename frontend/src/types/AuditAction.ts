@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type AuditAction = "alias.create" | "alias.update" | "alias.delete" | "alias.rename" | "alias.synonyms" | "alias.tags" | "alias.media" | "alias.revert" | "alias.restore" | "alias.approve" | "alias.reject" | "alias.report" | "reports.resolve" | "reports.dismiss" | "tag.create" | "tag.update" | "tag.delete" | "type.create" | "type.update" | "type.delete" | "settings.update" | "user.create" | "webhook.create" | "webhook.delete" | "webhook.retry";
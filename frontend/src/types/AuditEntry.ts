@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuditAction } from "./AuditAction";
+
+export interface AuditEntry {
+  id: bigint;
+  actor: string;
+  action: AuditAction;
+  target: string;
+  before: unknown;
+  after: unknown;
+  requestId: string | null;
+  createdAt: bigint;
+}
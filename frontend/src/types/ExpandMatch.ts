@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+
+export interface ExpandMatch {
+  start: number;
+  end: number;
+  name: string;
+  type: AliasType;
+  content: string;
+}
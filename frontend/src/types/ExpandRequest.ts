@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { OutputFormat } from "./OutputFormat";
+import type { WordBoundary } from "./WordBoundary";
+
+export interface ExpandRequest {
+  text: string;
+  prefix: string | null;
+  boundary: WordBoundary | null;
+  escape: boolean | null;
+  format: OutputFormat | null;
+  recursive: boolean | null;
+  maxDepth: number | null;
+}
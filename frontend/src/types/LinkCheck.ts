@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface LinkCheck {
+  alias: string;
+  url: string;
+  status: number | null;
+  contentType: string | null;
+  error: string | null;
+  failures: number;
+  broken: boolean;
+  checkedAt: bigint;
+}
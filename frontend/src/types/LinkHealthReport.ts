@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LinkCheck } from "./LinkCheck";
+
+export interface LinkHealthReport {
+  checked: bigint;
+  unchecked: bigint;
+  broken: Array<LinkCheck>;
+}
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface MediaInfo {
+  hash: string;
+  mime: string;
+  size: bigint;
+  width: number | null;
+  height: number | null;
+  frames: number | null;
+}
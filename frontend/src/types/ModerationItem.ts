@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+import type { ModerationStatus } from "./ModerationStatus";
+
+export interface ModerationItem {
+  name: string;
+  content: string;
+  type: AliasType;
+  author: string;
+  status: ModerationStatus;
+  submittedAt: bigint;
+  moderator: string | null;
+  reason: string | null;
+  moderatedAt: bigint | null;
+}
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ModerationStatus } from "./ModerationStatus";
+import type { SimilarAlias } from "./SimilarAlias";
+
+export interface PostAliasResponse {
+  similar: Array<SimilarAlias>;
+  status: ModerationStatus;
+}
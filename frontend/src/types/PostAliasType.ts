@@ -0,0 +1,9 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TypeDisplay } from "./TypeDisplay";
+
+export interface PostAliasType {
+  name: string;
+  label: string;
+  display: TypeDisplay;
+  maxSize: number | null;
+}
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Alias } from "./Alias";
+import type { SimilarAlias } from "./SimilarAlias";
+
+export interface PostMediaResponse {
+  alias: Alias;
+  similar: Array<SimilarAlias>;
+}
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TypeDisplay } from "./TypeDisplay";
+
+export interface PutAliasType {
+  name: string | null;
+  label: string | null;
+  display: TypeDisplay | null;
+  maxSize: number | null;
+  retired: boolean | null;
+}
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ReportCategory } from "./ReportCategory";
+
+export interface Report {
+  id: bigint;
+  reporter: string;
+  category: ReportCategory;
+  text: string | null;
+  createdAt: bigint;
+}
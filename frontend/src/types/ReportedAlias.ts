@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+import type { Report } from "./Report";
+
+export interface ReportedAlias {
+  name: string;
+  content: string;
+  type: AliasType;
+  author: string;
+  hiddenAt: bigint | null;
+  reports: Array<Report>;
+}
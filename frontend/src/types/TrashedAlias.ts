@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AliasType } from "./AliasType";
+
+export interface TrashedAlias {
+  name: string;
+  content: string;
+  type: AliasType;
+  author: string;
+  createdAt: bigint;
+  deletedBy: string;
+  deletedAt: bigint;
+  purgeAt: bigint;
+}
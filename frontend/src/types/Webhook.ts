@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { WebhookEvent } from "./WebhookEvent";
+
+export interface Webhook {
+  id: bigint;
+  url: string;
+  events: Array<WebhookEvent>;
+  createdBy: string;
+  createdAt: bigint;
+  pending: bigint;
+  dead: bigint;
+}
@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { DeliveryAttempt } from "./DeliveryAttempt";
+import type { DeliveryStatus } from "./DeliveryStatus";
+import type { WebhookEvent } from "./WebhookEvent";
+import type { WebhookPayload } from "./WebhookPayload";
+
+export interface WebhookDelivery {
+  id: bigint;
+  event: WebhookEvent;
+  status: DeliveryStatus;
+  payload: WebhookPayload;
+  attempts: Array<DeliveryAttempt>;
+  nextAttemptAt: bigint | null;
+  createdAt: bigint;
+  deliveredAt: bigint | null;
+}
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Alias } from "./Alias";
+import type { WebhookEvent } from "./WebhookEvent";
+
+export interface WebhookPayload {
+  event: WebhookEvent;
+  alias: Alias;
+  previousName: string | null;
+  createdAt: bigint;
+}